@@ -0,0 +1,136 @@
+use axum::extract::{FromRequestParts, Query};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::request::Parts;
+use axum::http::{StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use db::repository::RepositoryError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::task::JoinError;
+
+const PROBLEM_JSON: &str = "application/problem+json";
+
+// Error returned by every route, rendered as an RFC 9457 problem details body
+#[derive(Debug)]
+pub enum ApiError {
+    MissingParameter(&'static str),
+    InvalidParameter(String),
+    // The parameter doesn't name anything known, the valid values are listed in the response
+    UnknownValue {
+        parameter: &'static str,
+        value: String,
+        valid: Vec<String>,
+    },
+    NotFound(String),
+    // The cause is logged, clients only learn that the database can't be reached
+    Unavailable(String),
+    // The cause is logged, clients get a generic message
+    Internal(String),
+}
+
+impl ApiError {
+    fn problem(self) -> Problem {
+        let mut valid_values = None;
+        let (status, detail, cause) = match self {
+            ApiError::MissingParameter(name) => {
+                (StatusCode::BAD_REQUEST, format!("Query parameter `{}` is required", name), None)
+            },
+            ApiError::UnknownValue { parameter, value, valid } => {
+                valid_values = Some(valid);
+                (StatusCode::BAD_REQUEST, format!("Unknown {} `{}`", parameter, value), None)
+            },
+            ApiError::InvalidParameter(detail) => (StatusCode::BAD_REQUEST, detail, None),
+            ApiError::NotFound(detail) => (StatusCode::NOT_FOUND, detail, None),
+            ApiError::Unavailable(cause) => {
+                (StatusCode::SERVICE_UNAVAILABLE, "The database is unavailable, try again later".to_string(), Some(cause))
+            },
+            ApiError::Internal(cause) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "The request couldn't be completed".to_string(), Some(cause))
+            },
+        };
+
+        Problem {
+            kind: "about:blank",
+            title: status.canonical_reason().unwrap_or_default(),
+            status: status.as_u16(),
+            detail,
+            valid_values,
+            instance: None,
+            request_id: None,
+            cause,
+        }
+    }
+}
+
+impl From<RepositoryError> for ApiError {
+    fn from(e: RepositoryError) -> Self {
+        match e {
+            RepositoryError::Unavailable(_) => ApiError::Unavailable(e.to_string()),
+            RepositoryError::Query(_) => ApiError::Internal(e.to_string()),
+        }
+    }
+}
+
+impl From<JoinError> for ApiError {
+    fn from(e: JoinError) -> Self {
+        ApiError::Internal(format!("Handler task failed: {}", e))
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        self.problem().into_response()
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct Problem {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    valid_values: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) instance: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) request_id: Option<String>,
+    #[serde(skip)]
+    pub(crate) cause: Option<String>,
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let body = serde_json::to_vec(&self).unwrap_or_default();
+
+        // The problem travels along so the request id middleware can complete it
+        let mut response = (status, [(CONTENT_TYPE, PROBLEM_JSON)], body).into_response();
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+// Query string extractor that rejects malformed parameters with a problem response
+pub struct ApiQuery<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for ApiQuery<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Query::<T>::from_request_parts(parts, state)
+            .await
+            .map(|Query(params)| ApiQuery(params))
+            .map_err(|e| ApiError::InvalidParameter(e.body_text()))
+    }
+}
+
+// Fallback for paths no route matches
+pub async fn not_found(uri: Uri) -> ApiError {
+    ApiError::NotFound(format!("No endpoint at {}", uri.path()))
+}
@@ -1,86 +1,86 @@
 use std::sync::Arc;
-use axum::body::Body;
-use axum::extract::{Query, State};
-use axum::http::{Response, StatusCode};
-use axum::Json;
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
 use axum::response::IntoResponse;
-use diesel::{BoolExpressionMethods, ExpressionMethods, PgTextExpressionMethods, QueryDsl, RunQueryDsl};
-use db::models::Bar1min;
-use db::schema::bars_1min::dsl::bars_1min;
-use db::schema::bars_1min::{exchange, id, market};
+use axum::Json;
+use chrono::{TimeDelta, Utc};
+use prometheus::{Encoder, TextEncoder};
 use crate::{AppState};
-use crate::structs::{LastMinParams, LastMinResponse};
+use crate::error::{ApiError, ApiQuery};
+use crate::structs::{GapResponse, GapsParams, LastMinParams, LastMinResponse};
 
 // Get the list of all available exchanges
-pub async fn exchanges(State(state): State<Arc<AppState>>) -> Json<Vec<String>> {
-    let pool = state.pool.clone();
-
-    let result = tokio::task::spawn_blocking(move || {
-        let mut conn = pool.get().expect("Failed to get connection from pool");
+pub async fn exchanges(State(state): State<Arc<AppState>>) -> Result<Json<Vec<String>>, ApiError> {
+    let repository = state.repository.clone();
 
-        bars_1min
-            .select(exchange)
-            .distinct()
-            .order(exchange.asc())
-            .load::<String>(&mut conn)
-            .expect("Error loading bars")
-    }).await.expect("Error spawning exchanges endpoint task");
+    let exchanges = tokio::task::spawn_blocking(move || repository.exchanges()).await??;
 
-    Json(result)
+    Ok(Json(exchanges))
 }
 
 // Get the list of all available markets
-pub async fn markets(State(state): State<Arc<AppState>>) -> Json<Vec<String>> {
-    let pool = state.pool.clone();
+pub async fn markets(State(state): State<Arc<AppState>>) -> Result<Json<Vec<String>>, ApiError> {
+    let repository = state.repository.clone();
 
-    let result = tokio::task::spawn_blocking(move || {
-        let mut conn = pool.get().expect("Failed to get connection from pool");
+    let markets = tokio::task::spawn_blocking(move || repository.markets()).await??;
 
-        bars_1min
-            .select(market)
-            .distinct()
-            .order(market.asc())
-            .load::<String>(&mut conn)
-            .expect("Error loading bars")
-    }).await.expect("Error spawning exchanges endpoint task");
-
-    Json(result)
+    Ok(Json(markets))
 }
 
 // Get the last available bar for a given market on a given exchange
 pub async fn last_min(
     State(state): State<Arc<AppState>>,
-    Query(params): Query<LastMinParams>
-) -> Response<Body> {
-    let pool = state.pool.clone();
-
-    let result = tokio::task::spawn_blocking(move || {
-        let mut conn = pool.get().expect("Failed to get connection from pool");
-
-        bars_1min
-            .filter(
-                exchange.ilike(params.exchange.unwrap_or_default())
-                    .and(market.ilike(params.market.unwrap_or_default()))
-            )
-            .order(id.desc())
-            .first::<Bar1min>(&mut conn)
-    }).await.expect("Error spawning last_min endpoint task");
-
-    match result {
-        Ok(row) => {
-            let bar = LastMinResponse {
-                open: row.open,
-                close: row.close,
-                min: row.min,
-                max: row.max,
-            };
-            Json(bar).into_response()
-        },
-        Err(diesel::result::Error::NotFound) => {
-            (StatusCode::NOT_FOUND, "Last min for a given market on a given exchange not found").into_response()
-        },
-        Err(e) => {
-            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
-        }
+    ApiQuery(params): ApiQuery<LastMinParams>
+) -> Result<Json<LastMinResponse>, ApiError> {
+    let repository = state.repository.clone();
+    let (exchange, market) = state.names.resolve(&repository, params.exchange, params.market).await?;
+
+    let row = tokio::task::spawn_blocking(move || repository.last_bar(&exchange, &market))
+        .await??
+        .ok_or_else(|| ApiError::NotFound("Last min for a given market on a given exchange not found".to_string()))?;
+
+    Ok(Json(LastMinResponse {
+        open: row.open,
+        close: row.close,
+        min: row.min,
+        max: row.max,
+    }))
+}
+
+// Get the minutes without updates for a given market on a given exchange
+pub async fn gaps(
+    State(state): State<Arc<AppState>>,
+    ApiQuery(params): ApiQuery<GapsParams>
+) -> Result<Json<Vec<GapResponse>>, ApiError> {
+    let repository = state.repository.clone();
+    let (exchange, market) = state.names.resolve(&repository, params.exchange, params.market).await?;
+
+    let to = params.to.unwrap_or_else(|| Utc::now().naive_utc());
+    let from = params.from.unwrap_or(to - TimeDelta::days(1));
+    if from > to {
+        return Err(ApiError::InvalidParameter("`from` must not be after `to`".to_string()));
     }
-}
\ No newline at end of file
+
+    let rows = tokio::task::spawn_blocking(move || repository.gaps(&exchange, &market, from, to)).await??;
+
+    let gaps = rows
+        .into_iter()
+        .map(|row| GapResponse {
+            exchange: row.exchange,
+            market: row.market,
+            timestamp: row.timestamp,
+        })
+        .collect::<Vec<GapResponse>>();
+
+    Ok(Json(gaps))
+}
+
+// Metrics of the whole process in the Prometheus text format
+pub async fn metrics() -> Result<impl IntoResponse, ApiError> {
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    encoder.encode(&prometheus::gather(), &mut body)
+        .map_err(|e| ApiError::Internal(format!("Error encoding metrics: {}", e)))?;
+
+    Ok(([(CONTENT_TYPE, encoder.format_type().to_string())], body))
+}
@@ -0,0 +1,183 @@
+use std::sync::Arc;
+use std::time::Duration;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use chrono::{DateTime, TimeDelta, Utc};
+use db::repository::BarRepository;
+use serde::Serialize;
+use tracing::warn;
+
+// State of the scraper running next to the api, as reported by the engine
+pub trait ScraperProbe: Send + Sync {
+    fn health(&self) -> ScraperHealth;
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ScraperHealth {
+    pub running: bool,
+    pub started_at: Option<DateTime<Utc>>,
+    // leader, or standby when another HA instance writes the bars
+    pub role: String,
+    pub feeds: Vec<FeedHealth>,
+    pub markets: Vec<MarketHealth>,
+    pub flushes: Vec<FlushHealth>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FeedHealth {
+    pub exchange: String,
+    pub shard: usize,
+    // connected, reconnecting or closed
+    pub state: String,
+    pub connection: u64,
+    pub since: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MarketHealth {
+    pub exchange: String,
+    pub market: String,
+    pub last_message: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FlushHealth {
+    pub sink: String,
+    pub last_flush: DateTime<Utc>,
+}
+
+// What /ready checks. Without a scraper only the database is checked
+pub struct HealthChecks {
+    pub scraper: Option<Arc<dyn ScraperProbe>>,
+    // A market without updates for longer is stale
+    pub max_message_age: Duration,
+    // A sink without a successful write for longer is stale, bars are written once a minute
+    pub max_flush_age: Duration,
+}
+
+impl Default for HealthChecks {
+    fn default() -> Self {
+        Self {
+            scraper: None,
+            max_message_age: Duration::from_secs(60),
+            max_flush_age: Duration::from_secs(150),
+        }
+    }
+}
+
+pub(crate) struct HealthState {
+    pub(crate) repository: Option<Arc<dyn BarRepository>>,
+    pub(crate) checks: HealthChecks,
+}
+
+#[derive(Serialize)]
+struct Liveness {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    status: &'static str,
+    // Why the service isn't ready, empty when it is
+    problems: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    database: Option<DatabaseHealth>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scraper: Option<ScraperHealth>,
+}
+
+#[derive(Serialize)]
+struct DatabaseHealth {
+    connected: bool,
+}
+
+// Liveness, fails only when the scraper task stopped and the process should be restarted
+pub async fn health(State(state): State<Arc<HealthState>>) -> impl IntoResponse {
+    match &state.checks.scraper {
+        Some(scraper) if !scraper.health().running => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(Liveness { status: "down", reason: Some("The scraper stopped") }),
+        ),
+        _ => (StatusCode::OK, Json(Liveness { status: "ok", reason: None })),
+    }
+}
+
+// Readiness, fails while the database can't be reached, a feed isn't connected or data is stale
+pub async fn ready(State(state): State<Arc<HealthState>>) -> impl IntoResponse {
+    let mut problems = Vec::new();
+
+    let database = match state.repository.clone() {
+        Some(repository) => {
+            let connected = match tokio::task::spawn_blocking(move || repository.ping()).await {
+                Ok(Ok(())) => true,
+                Ok(Err(e)) => {
+                    warn!(error = %e, "Readiness check of the database failed");
+                    false
+                },
+                Err(e) => {
+                    warn!(error = %e, "Readiness check task failed");
+                    false
+                },
+            };
+            if !connected {
+                problems.push("The database is unavailable".to_string());
+            }
+            Some(DatabaseHealth { connected })
+        },
+        None => None,
+    };
+
+    let scraper = state.checks.scraper.as_ref().map(|scraper| scraper.health());
+    if let Some(scraper) = &scraper {
+        problems.extend(scraper_problems(scraper, &state.checks, Utc::now()));
+    }
+
+    let (status, code) = match problems.is_empty() {
+        true => ("ready", StatusCode::OK),
+        false => ("not_ready", StatusCode::SERVICE_UNAVAILABLE),
+    };
+    (code, Json(Readiness { status, problems, database, scraper }))
+}
+
+fn scraper_problems(scraper: &ScraperHealth, checks: &HealthChecks, now: DateTime<Utc>) -> Vec<String> {
+    if !scraper.running {
+        return vec!["The scraper stopped".to_string()];
+    }
+
+    let max_message_age = TimeDelta::from_std(checks.max_message_age).unwrap_or(TimeDelta::MAX);
+    let max_flush_age = TimeDelta::from_std(checks.max_flush_age).unwrap_or(TimeDelta::MAX);
+    let mut problems = Vec::new();
+
+    if scraper.feeds.is_empty() {
+        problems.push("No exchange connected yet".to_string());
+    }
+    for feed in scraper.feeds.iter().filter(|feed| feed.state != "connected") {
+        problems.push(format!("{} connection {} is {} since {}", feed.exchange, feed.shard, feed.state, feed.since));
+    }
+
+    for market in scraper.markets.iter().filter(|market| now - market.last_message > max_message_age) {
+        problems.push(format!("No update of {} on {} since {}", market.market, market.exchange, market.last_message));
+    }
+
+    // A standby doesn't write, its last writes date from before it lost the leadership
+    if scraper.role == "standby" {
+        return problems;
+    }
+
+    // Until the first write the scraper gets the same time from its start
+    if scraper.flushes.is_empty()
+        && let Some(started_at) = scraper.started_at
+        && now - started_at > max_flush_age
+    {
+        problems.push(format!("No bars written since the start at {}", started_at));
+    }
+    for flush in scraper.flushes.iter().filter(|flush| now - flush.last_flush > max_flush_age) {
+        problems.push(format!("No bars written to {} since {}", flush.sink, flush.last_flush));
+    }
+
+    problems
+}
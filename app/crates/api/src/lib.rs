@@ -1,19 +1,50 @@
+mod error;
 mod handlers;
+mod health;
+mod middleware;
+mod names;
 mod structs;
 
 use std::sync::Arc;
 use axum::routing::get;
 use axum::Router;
-use db::db::DbPool;
-use crate::handlers::{exchanges, last_min, markets};
+use db::repository::BarRepository;
+use crate::error::not_found;
+use crate::health::{health, ready, HealthState};
+use crate::middleware::trace_request;
+use crate::names::KnownNames;
+use crate::handlers::{exchanges, gaps, last_min, markets, metrics};
 use crate::structs::AppState;
 
-pub fn get_app(pool: DbPool) -> Router {
-    let state = Arc::new(AppState{pool});
-    Router::new()
+pub use crate::health::{FeedHealth, FlushHealth, HealthChecks, MarketHealth, ScraperHealth, ScraperProbe};
+
+pub fn get_app(repository: Arc<dyn BarRepository>, checks: HealthChecks) -> Router {
+    let state = Arc::new(AppState { repository: repository.clone(), names: KnownNames::default() });
+    let routes = Router::new()
         .route("/exchanges", get(exchanges))
         .route("/markets", get(markets))
         .route("/last_min", get(last_min))
-        .with_state(state)
+        .route("/gaps", get(gaps))
+        .with_state(state);
+
+    with_common_routes(routes, HealthState { repository: Some(repository), checks })
+}
+
+// Only the status endpoints, for running without the bars api.
+// /ready checks `repository` when the scraper writes to a database
+pub fn get_status_app(repository: Option<Arc<dyn BarRepository>>, checks: HealthChecks) -> Router {
+    with_common_routes(Router::new(), HealthState { repository, checks })
 }
 
+fn with_common_routes(routes: Router, health_state: HealthState) -> Router {
+    let health_routes = Router::new()
+        .route("/health", get(health))
+        .route("/ready", get(ready))
+        .with_state(Arc::new(health_state));
+
+    routes
+        .merge(health_routes)
+        .route("/metrics", get(metrics))
+        .fallback(not_found)
+        .layer(axum::middleware::from_fn(trace_request))
+}
@@ -0,0 +1,50 @@
+use std::time::Instant;
+use axum::extract::Request;
+use axum::http::{HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tracing::{error, info, info_span, Instrument};
+use uuid::Uuid;
+use crate::error::Problem;
+
+pub static REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+// Longest request id taken over from a client, longer ones are replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+// Tags every request with an id, taken from the x-request-id header or generated, and echoes it back
+// in that header. Handlers run in a span carrying the id, and every response is logged with its status
+// and latency. Problem responses get the id and path added to their body, and the causes of server
+// errors are logged under it
+pub async fn trace_request(request: Request, next: Next) -> Response {
+    let id = request.headers()
+        .get(&REQUEST_ID)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let path = request.uri().path().to_string();
+    let span = info_span!("request", request_id = %id, method = %request.method(), path = %path);
+
+    let started = Instant::now();
+    let mut response = next.run(request).instrument(span.clone()).await;
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    span.in_scope(|| {
+        if let Some(mut problem) = response.extensions_mut().remove::<Problem>() {
+            if let Some(cause) = problem.cause.as_ref() {
+                error!(cause = %cause, "Request failed");
+            }
+            problem.instance = Some(path);
+            problem.request_id = Some(id.clone());
+            response = problem.into_response();
+        }
+
+        info!(status = response.status().as_u16(), latency_ms, "Request finished");
+    });
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID.clone(), value);
+    }
+    response
+}
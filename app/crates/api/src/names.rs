@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use db::repository::BarRepository;
+use crate::error::ApiError;
+
+// How long the stored names are reused before they're loaded again
+const NAMES_TTL: Duration = Duration::from_secs(60);
+
+// A name that isn't cached reloads the names first, unless they were loaded this recently.
+// Keeps requests for made-up names from querying the database every time
+const NAMES_MISS_RELOAD: Duration = Duration::from_secs(1);
+
+struct Names {
+    exchanges: Vec<String>,
+    // Markets of each exchange, sorted
+    markets: BTreeMap<String, Vec<String>>,
+}
+
+// Exchange and market names as stored, used to turn request parameters into exact names
+#[derive(Default)]
+pub struct KnownNames {
+    cached: Mutex<Option<(Instant, Arc<Names>)>>,
+}
+
+impl KnownNames {
+    // Resolves the exchange and market parameters of a request to their stored names. Both are
+    // required and matched case-insensitively, as plain text. The market is checked against the
+    // markets of the exchange, so a market only stored for another exchange is unknown.
+    // Names that aren't cached are looked up again before they're reported as unknown
+    pub async fn resolve(
+        &self,
+        repository: &Arc<dyn BarRepository>,
+        exchange: Option<String>,
+        market: Option<String>,
+    ) -> Result<(String, String), ApiError> {
+        let exchange = exchange.ok_or(ApiError::MissingParameter("exchange"))?;
+        let market = market.ok_or(ApiError::MissingParameter("market"))?;
+        let names = self.load(repository, NAMES_TTL).await?;
+
+        match names.resolve(&exchange, &market) {
+            // A market that got its first bar or gap since the names were loaded
+            Err(ApiError::UnknownValue { .. }) => self.load(repository, NAMES_MISS_RELOAD).await?.resolve(&exchange, &market),
+            resolved => resolved,
+        }
+    }
+
+    // Returns the cached names unless they're older than `max_age`
+    async fn load(&self, repository: &Arc<dyn BarRepository>, max_age: Duration) -> Result<Arc<Names>, ApiError> {
+        if let Some((loaded_at, names)) = self.cached.lock().expect("Names lock poisoned").as_ref()
+            && loaded_at.elapsed() < max_age
+        {
+            return Ok(names.clone());
+        }
+
+        let repository = repository.clone();
+        let pairs = tokio::task::spawn_blocking(move || repository.exchange_markets()).await??;
+        let mut markets = BTreeMap::<String, Vec<String>>::new();
+        for (exchange, market) in pairs {
+            markets.entry(exchange).or_default().push(market);
+        }
+        let names = Names {
+            exchanges: markets.keys().cloned().collect(),
+            markets,
+        };
+
+        let names = Arc::new(names);
+        *self.cached.lock().expect("Names lock poisoned") = Some((Instant::now(), names.clone()));
+        Ok(names)
+    }
+}
+
+impl Names {
+    fn resolve(&self, exchange: &str, market: &str) -> Result<(String, String), ApiError> {
+        let exchange = canonical("exchange", exchange, &self.exchanges)?;
+        let market = canonical("market", market, &self.markets[&exchange])?;
+        Ok((exchange, market))
+    }
+}
+
+fn canonical(parameter: &'static str, value: &str, known: &[String]) -> Result<String, ApiError> {
+    match known.iter().find(|name| name.eq_ignore_ascii_case(value)) {
+        Some(name) => Ok(name.clone()),
+        None => Err(ApiError::UnknownValue { parameter, value: value.to_string(), valid: known.to_vec() }),
+    }
+}
@@ -1,11 +1,16 @@
+use std::sync::Arc;
 use bigdecimal::BigDecimal;
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
-use db::db::DbPool;
+use db::repository::BarRepository;
+use crate::names::KnownNames;
 
 pub struct AppState {
-    pub pool: DbPool
+    pub repository: Arc<dyn BarRepository>,
+    pub names: KnownNames,
 }
 
+// Both are required, they're checked against the known names so missing ones get a clear error
 #[derive(Deserialize)]
 pub struct LastMinParams {
     pub exchange: Option<String>,
@@ -18,4 +23,20 @@ pub struct LastMinResponse {
     pub close: BigDecimal,
     pub min: BigDecimal,
     pub max: BigDecimal,
+}
+
+#[derive(Deserialize)]
+pub struct GapsParams {
+    pub exchange: Option<String>,
+    pub market: Option<String>,
+    // Range of minutes to look at, defaults to the last 24 hours
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize)]
+pub struct GapResponse {
+    pub exchange: String,
+    pub market: String,
+    pub timestamp: NaiveDateTime,
 }
\ No newline at end of file
@@ -0,0 +1,335 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use api::{get_app, FeedHealth, FlushHealth, HealthChecks, MarketHealth, ScraperHealth, ScraperProbe};
+use axum::body::Body;
+use axum::http::{Request, Response, StatusCode};
+use axum::Router;
+use bigdecimal::BigDecimal;
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use db::models::{Bar1min, Gap1min, INTERVAL_1MIN};
+use db::repository::{BarRepository, InMemoryBarRepository, RepositoryError, RepositoryResult};
+use http_body_util::BodyExt;
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+fn minute(minute: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(&format!("2025-01-01 {}:00", minute), "%Y-%m-%d %H:%M:%S").expect("Invalid minute")
+}
+
+fn bar(exchange: &str, market: &str, at: &str, close: &str) -> Bar1min {
+    let price = BigDecimal::from_str(close).expect("Invalid price");
+    Bar1min {
+        id: 0,
+        exchange: exchange.to_string(),
+        market: market.to_string(),
+        timestamp: minute(at),
+        open: price.clone(),
+        close: price.clone(),
+        min: price.clone(),
+        max: price,
+        partial: false,
+        synthetic: false,
+        interval: INTERVAL_1MIN.to_string(),
+        backfilled: false,
+    }
+}
+
+fn gap(exchange: &str, market: &str, at: &str) -> Gap1min {
+    Gap1min {
+        id: 0,
+        exchange: exchange.to_string(),
+        market: market.to_string(),
+        timestamp: minute(at),
+    }
+}
+
+fn app() -> Router {
+    let repository = InMemoryBarRepository::new();
+    repository.insert_bar(bar("Binance", "BTCUSDT", "10:00", "100"));
+    repository.insert_bar(bar("Binance", "BTCUSDT", "10:02", "102"));
+    repository.insert_bar(bar("Binance", "BTCUSDT", "10:01", "101"));
+    repository.insert_bar(bar("ByBit", "ETHUSDT", "10:00", "10.5"));
+    repository.insert_gap(gap("Binance", "BTCUSDT", "09:59"));
+    repository.insert_gap(gap("Binance", "BTCUSDT", "10:03"));
+    repository.insert_gap(gap("ByBit", "BTCUSDT", "10:03"));
+
+    get_app(Arc::new(repository), HealthChecks::default())
+}
+
+// Repository whose database can't be reached for exchanges and fails the queries for markets
+struct Broken;
+
+impl BarRepository for Broken {
+    fn ping(&self) -> RepositoryResult<()> {
+        Err(RepositoryError::Unavailable("connection refused".to_string()))
+    }
+
+    fn exchanges(&self) -> RepositoryResult<Vec<String>> {
+        Err(RepositoryError::Unavailable("connection refused".to_string()))
+    }
+
+    fn markets(&self) -> RepositoryResult<Vec<String>> {
+        Err(RepositoryError::Query("relation \"bars_1min\" does not exist".to_string()))
+    }
+
+    fn exchange_markets(&self) -> RepositoryResult<Vec<(String, String)>> {
+        Err(RepositoryError::Unavailable("connection refused".to_string()))
+    }
+
+    fn last_bar(&self, _exchange: &str, _market: &str) -> RepositoryResult<Option<Bar1min>> {
+        Err(RepositoryError::Unavailable("connection refused".to_string()))
+    }
+
+    fn gaps(&self, _exchange: &str, _market: &str, _from: NaiveDateTime, _to: NaiveDateTime) -> RepositoryResult<Vec<Gap1min>> {
+        Err(RepositoryError::Unavailable("connection refused".to_string()))
+    }
+}
+
+// Scraper whose market was last updated and whose bars were last written `age` ago
+struct Scraper {
+    running: bool,
+    role: &'static str,
+    state: &'static str,
+    age: TimeDelta,
+}
+
+impl ScraperProbe for Scraper {
+    fn health(&self) -> ScraperHealth {
+        let now = Utc::now();
+        ScraperHealth {
+            running: self.running,
+            started_at: Some(now - TimeDelta::hours(1)),
+            role: self.role.to_string(),
+            feeds: vec![FeedHealth { exchange: "Binance".to_string(), shard: 0, state: self.state.to_string(), connection: 1, since: now }],
+            markets: vec![MarketHealth { exchange: "Binance".to_string(), market: "BTCUSDT".to_string(), last_message: now - self.age }],
+            flushes: vec![FlushHealth { sink: "postgres".to_string(), last_flush: now - self.age }],
+        }
+    }
+}
+
+fn checks(scraper: Scraper) -> HealthChecks {
+    HealthChecks { scraper: Some(Arc::new(scraper)), ..HealthChecks::default() }
+}
+
+async fn send(app: Router, request: Request<Body>) -> Response<Body> {
+    app.oneshot(request).await.expect("Request failed")
+}
+
+async fn json_body(response: Response<Body>) -> Value {
+    let body = response.into_body().collect().await.expect("Unreadable body").to_bytes();
+    serde_json::from_slice(&body).expect("Body isn't JSON")
+}
+
+async fn get(app: Router, uri: &str) -> (StatusCode, Value) {
+    let response = send(app, Request::get(uri).body(Body::empty()).expect("Invalid request")).await;
+    (response.status(), json_body(response).await)
+}
+
+#[tokio::test]
+async fn lists_exchanges_and_markets() {
+    assert_eq!(get(app(), "/exchanges").await, (StatusCode::OK, json!(["Binance", "ByBit"])));
+    assert_eq!(get(app(), "/markets").await, (StatusCode::OK, json!(["BTCUSDT", "ETHUSDT"])));
+}
+
+#[tokio::test]
+async fn last_min_returns_the_latest_bar() {
+    let (status, body) = get(app(), "/last_min?exchange=binance&market=btcusdt").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({"open": "102", "close": "102", "min": "102", "max": "102"}));
+}
+
+#[tokio::test]
+async fn markets_of_another_exchange_are_bad_requests() {
+    let (status, body) = get(app(), "/last_min?exchange=Binance&market=ETHUSDT").await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["detail"], "Unknown market `ETHUSDT`");
+    assert_eq!(body["valid_values"], json!(["BTCUSDT"]));
+    assert_eq!(body["instance"], "/last_min");
+
+    let (status, body) = get(app(), "/gaps?exchange=bybit&market=xrpusdt").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["valid_values"], json!(["BTCUSDT", "ETHUSDT"]));
+}
+
+#[tokio::test]
+async fn markets_with_only_gaps_are_known() {
+    let (status, body) = get(app(), "/gaps?exchange=bybit&market=btcusdt&from=2025-01-01T10:00:00&to=2025-01-01T11:00:00").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!([{"exchange": "ByBit", "market": "BTCUSDT", "timestamp": "2025-01-01T10:03:00"}]));
+}
+
+#[tokio::test]
+async fn new_markets_are_known_before_the_names_expire() {
+    let repository = Arc::new(InMemoryBarRepository::new());
+    repository.insert_bar(bar("Binance", "BTCUSDT", "10:00", "100"));
+    let app = get_app(repository.clone(), HealthChecks::default());
+    assert_eq!(get(app.clone(), "/last_min?exchange=Binance&market=BTCUSDT").await.0, StatusCode::OK);
+
+    repository.insert_bar(bar("Binance", "ETHUSDT", "10:00", "10"));
+    // Names loaded less than a second ago aren't reloaded for a miss
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let (status, body) = get(app, "/last_min?exchange=Binance&market=ETHUSDT").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["close"], "10");
+}
+
+#[tokio::test]
+async fn gaps_are_filtered_by_market_and_range() {
+    let (status, body) = get(app(), "/gaps?exchange=Binance&market=BTCUSDT&from=2025-01-01T10:00:00&to=2025-01-01T11:00:00").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!([{"exchange": "Binance", "market": "BTCUSDT", "timestamp": "2025-01-01T10:03:00"}]));
+}
+
+#[tokio::test]
+async fn missing_parameters_are_bad_requests() {
+    let (status, body) = get(app(), "/last_min?exchange=Binance").await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["detail"], "Query parameter `market` is required");
+
+    let (status, _) = get(app(), "/gaps?market=BTCUSDT").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn wildcards_are_not_patterns() {
+    let (status, body) = get(app(), "/last_min?exchange=Binance&market=%25").await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["detail"], "Unknown market `%`");
+    assert_eq!(body["valid_values"], json!(["BTCUSDT"]));
+
+    let (status, _) = get(app(), "/last_min?exchange=Bin_nce&market=BTCUSDT").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn unknown_exchanges_list_the_valid_ones() {
+    let (status, body) = get(app(), "/gaps?exchange=Kraken&market=BTCUSDT").await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["detail"], "Unknown exchange `Kraken`");
+    assert_eq!(body["valid_values"], json!(["Binance", "ByBit"]));
+}
+
+#[tokio::test]
+async fn malformed_parameters_are_bad_requests() {
+    let (status, body) = get(app(), "/gaps?exchange=Binance&market=BTCUSDT&from=yesterday").await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["type"], "about:blank");
+}
+
+#[tokio::test]
+async fn database_errors_are_not_leaked() {
+    let app = get_app(Arc::new(Broken), HealthChecks::default());
+
+    let (status, body) = get(app.clone(), "/exchanges").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["detail"], "The database is unavailable, try again later");
+
+    let (status, body) = get(app, "/markets").await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(!body.to_string().contains("bars_1min"));
+}
+
+#[tokio::test]
+async fn problems_carry_the_request_id() {
+    let request = Request::get("/last_min").header("x-request-id", "abc-123").body(Body::empty()).expect("Invalid request");
+    let response = send(app(), request).await;
+
+    assert_eq!(response.headers()["x-request-id"], "abc-123");
+    assert_eq!(response.headers()["content-type"], "application/problem+json");
+    assert_eq!(json_body(response).await["request_id"], "abc-123");
+}
+
+#[tokio::test]
+async fn every_response_gets_a_request_id() {
+    let response = send(app(), Request::get("/exchanges").body(Body::empty()).expect("Invalid request")).await;
+    assert!(!response.headers()["x-request-id"].is_empty());
+
+    let (status, body) = get(app(), "/nothing/here").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["detail"], "No endpoint at /nothing/here");
+}
+
+#[tokio::test]
+async fn metrics_are_served_as_prometheus_text() {
+    let response = send(api::get_status_app(None, HealthChecks::default()), Request::get("/metrics").body(Body::empty()).expect("Invalid request")).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()["content-type"].to_str().expect("Invalid header").starts_with("text/plain"));
+}
+
+#[tokio::test]
+async fn health_fails_only_when_the_scraper_stopped() {
+    let stale = Scraper { running: true, role: "leader", state: "reconnecting", age: TimeDelta::hours(1) };
+    assert_eq!(get(get_app(Arc::new(Broken), checks(stale)), "/health").await, (StatusCode::OK, json!({"status": "ok"})));
+
+    let stopped = Scraper { running: false, role: "leader", state: "closed", age: TimeDelta::zero() };
+    let (status, body) = get(api::get_status_app(None, checks(stopped)), "/health").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["status"], "down");
+}
+
+#[tokio::test]
+async fn ready_reports_the_database_feeds_and_flushes() {
+    let repository = Arc::new(InMemoryBarRepository::new());
+    let fresh = Scraper { running: true, role: "leader", state: "connected", age: TimeDelta::seconds(5) };
+    let (status, body) = get(get_app(repository, checks(fresh)), "/ready").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ready");
+    assert_eq!(body["database"], json!({"connected": true}));
+    assert_eq!(body["scraper"]["feeds"][0]["state"], "connected");
+    assert_eq!(body["scraper"]["markets"][0]["market"], "BTCUSDT");
+    assert_eq!(body["scraper"]["flushes"][0]["sink"], "postgres");
+}
+
+#[tokio::test]
+async fn stale_data_is_not_ready() {
+    let stale = Scraper { running: true, role: "leader", state: "reconnecting", age: TimeDelta::minutes(5) };
+    let (status, body) = get(api::get_status_app(None, checks(stale)), "/ready").await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["status"], "not_ready");
+    let problems = body["problems"].as_array().expect("Problems aren't a list");
+    assert_eq!(problems.len(), 3);
+    assert!(problems[0].as_str().expect("Problem isn't text").starts_with("Binance connection 0 is reconnecting"));
+    assert!(body.get("database").is_none());
+}
+
+#[tokio::test]
+async fn an_unreachable_database_is_not_ready() {
+    let (status, body) = get(get_app(Arc::new(Broken), HealthChecks::default()), "/ready").await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["database"], json!({"connected": false}));
+    assert_eq!(body["problems"], json!(["The database is unavailable"]));
+}
+
+#[tokio::test]
+async fn a_standby_is_ready_without_writing() {
+    let standby = Scraper { running: true, role: "standby", state: "connected", age: TimeDelta::seconds(5) };
+    let mut checks = checks(standby);
+    checks.max_flush_age = Duration::from_secs(1);
+    let (status, body) = get(api::get_status_app(None, checks), "/ready").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["scraper"]["role"], "standby");
+}
+
+#[tokio::test]
+async fn the_status_app_checks_the_scrapers_database() {
+    let (status, body) = get(api::get_status_app(Some(Arc::new(Broken)), HealthChecks::default()), "/ready").await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["database"], json!({"connected": false}));
+}
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE bars_1min
+DROP COLUMN partial
\ No newline at end of file
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE bars_1min
+ADD COLUMN partial BOOLEAN NOT NULL DEFAULT FALSE
\ No newline at end of file
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE gaps_1min;
+
+ALTER TABLE bars_1min
+DROP COLUMN synthetic;
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+ALTER TABLE bars_1min
+ADD COLUMN synthetic BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE gaps_1min (
+   id SERIAL PRIMARY KEY,
+   exchange VARCHAR NOT NULL,
+   market VARCHAR NOT NULL,
+   timestamp TIMESTAMP NOT NULL
+);
+
+CREATE INDEX gaps_1min_exchange_market_timestamp_idx
+ON gaps_1min (exchange, market, timestamp);
@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX gaps_1min_natural_key_idx;
+
+CREATE INDEX gaps_1min_exchange_market_timestamp_idx
+ON gaps_1min (exchange, market, timestamp);
+
+DROP INDEX bars_1min_latest_idx;
+
+DROP INDEX bars_1min_natural_key_idx;
+
+ALTER TABLE bars_1min
+DROP COLUMN "interval";
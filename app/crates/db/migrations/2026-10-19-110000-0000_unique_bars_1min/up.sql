@@ -0,0 +1,30 @@
+-- Your SQL goes here
+ALTER TABLE bars_1min
+ADD COLUMN "interval" VARCHAR NOT NULL DEFAULT '1m';
+
+-- Keep the first copy of bars written more than once before the constraint existed
+DELETE FROM bars_1min a
+USING bars_1min b
+WHERE a.exchange = b.exchange
+  AND a.market = b.market
+  AND a."interval" = b."interval"
+  AND a.timestamp = b.timestamp
+  AND a.id > b.id;
+
+CREATE UNIQUE INDEX bars_1min_natural_key_idx
+ON bars_1min (exchange, market, "interval", timestamp);
+
+CREATE INDEX bars_1min_latest_idx
+ON bars_1min (exchange, market, timestamp DESC);
+
+DELETE FROM gaps_1min a
+USING gaps_1min b
+WHERE a.exchange = b.exchange
+  AND a.market = b.market
+  AND a.timestamp = b.timestamp
+  AND a.id > b.id;
+
+DROP INDEX gaps_1min_exchange_market_timestamp_idx;
+
+CREATE UNIQUE INDEX gaps_1min_natural_key_idx
+ON gaps_1min (exchange, market, timestamp);
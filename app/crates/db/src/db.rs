@@ -1,15 +1,62 @@
-use std::env;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
 use diesel::PgConnection;
-use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Error, Pool, PoolError, PooledConnection};
+use crate::migrations::MigrationResult;
 
 pub type DbPool = Pool<ConnectionManager<PgConnection>>;
+pub type DbConnection = PooledConnection<ConnectionManager<PgConnection>>;
 
-pub fn init_pool() -> DbPool {
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+// How long a lazy pool waits for a connection, short so a write notices a database outage
+// long before the next minute's bars are due
+const LAZY_CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Fails when the database can't be reached within the pool's connection timeout
+pub fn init_pool(database_url: &str, max_size: u32) -> Result<DbPool, PoolError> {
     let manager = ConnectionManager::<PgConnection>::new(database_url);
 
     Pool::builder()
-        .max_size(15)
+        .max_size(max_size)
         .build(manager)
-        .expect("Can't create connection pool")
-}
\ No newline at end of file
+}
+
+// Check of the database a lazy pool runs before handing out connections, e.g. of its schema
+pub type ConnectionCheck = Box<dyn Fn(&mut PgConnection) -> MigrationResult<()> + Send + Sync>;
+
+// Doesn't connect up front, so it can be created while the database is down.
+// Connections are opened when first needed. Every new connection runs `check` until it passed once,
+// until then getting a connection fails as if the database were down
+pub fn init_lazy_pool(database_url: &str, max_size: u32, check: ConnectionCheck) -> DbPool {
+    let manager = ConnectionManager::<PgConnection>::new(database_url);
+
+    Pool::builder()
+        .max_size(max_size)
+        .min_idle(Some(0))
+        .connection_timeout(LAZY_CONNECTION_TIMEOUT)
+        .connection_customizer(Box::new(CheckOnce { check, passed: Mutex::new(false) }))
+        .build_unchecked(manager)
+}
+
+struct CheckOnce {
+    check: ConnectionCheck,
+    // Held while checking, so concurrent connections don't check, or migrate, at the same time
+    passed: Mutex<bool>,
+}
+
+impl fmt::Debug for CheckOnce {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CheckOnce").finish_non_exhaustive()
+    }
+}
+
+impl CustomizeConnection<PgConnection, Error> for CheckOnce {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), Error> {
+        let mut passed = self.passed.lock().expect("Connection check lock poisoned");
+        if !*passed {
+            (self.check)(conn).map_err(|e| Error::QueryError(diesel::result::Error::QueryBuilderError(e)))?;
+            *passed = true;
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,6 @@
 pub mod db;
+pub mod migrations;
 pub mod models;
+pub mod repository;
 
 pub mod schema;
\ No newline at end of file
@@ -0,0 +1,46 @@
+use std::collections::HashSet;
+use std::error::Error;
+use diesel::pg::Pg;
+use diesel::PgConnection;
+use diesel::migration::MigrationSource;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+
+// The migrations of crates/db/migrations, built into the binary
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+pub type MigrationResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+// Migrations of the database compared with the embedded ones
+#[derive(Debug, Default)]
+pub struct SchemaStatus {
+    // Embedded migrations not applied yet, oldest first
+    pub pending: Vec<String>,
+    // Applied migrations the binary doesn't embed, e.g. from a newer version of it
+    pub unknown: Vec<String>,
+}
+
+pub fn schema_status(conn: &mut PgConnection) -> MigrationResult<SchemaStatus> {
+    let embedded = MigrationSource::<Pg>::migrations(&MIGRATIONS)?
+        .iter()
+        .map(|migration| migration.name().version().to_string())
+        .collect::<HashSet<_>>();
+    let applied = conn.applied_migrations()?
+        .iter()
+        .map(|version| version.to_string())
+        .collect::<Vec<_>>();
+
+    let pending = conn.pending_migrations(MIGRATIONS)?
+        .iter()
+        .map(|migration| migration.name().version().to_string())
+        .collect();
+    let mut unknown = applied.into_iter().filter(|version| !embedded.contains(version)).collect::<Vec<_>>();
+    unknown.sort();
+
+    Ok(SchemaStatus { pending, unknown })
+}
+
+// Applies every pending migration in order, returns the versions applied
+pub fn run_pending_migrations(conn: &mut PgConnection) -> MigrationResult<Vec<String>> {
+    let applied = conn.run_pending_migrations(MIGRATIONS)?;
+    Ok(applied.iter().map(|version| version.to_string()).collect())
+}
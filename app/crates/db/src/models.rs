@@ -1,10 +1,13 @@
-use crate::schema::bars_1min;
+use crate::schema::{bars_1min, gaps_1min};
 use bigdecimal::BigDecimal;
 use chrono::NaiveDateTime;
 use diesel::{Insertable, Queryable};
 use serde::Serialize;
 
-#[derive(Queryable, Debug, Serialize)]
+// Interval of the bars stored in bars_1min
+pub const INTERVAL_1MIN: &str = "1m";
+
+#[derive(Queryable, Clone, Debug, Serialize)]
 pub struct Bar1min {
     pub id: i32,
     pub exchange: String,
@@ -14,6 +17,10 @@ pub struct Bar1min {
     pub close: BigDecimal,
     pub min: BigDecimal,
     pub max: BigDecimal,
+    pub partial: bool,
+    pub synthetic: bool,
+    pub interval: String,
+    pub backfilled: bool,
 }
 
 #[derive(Insertable)]
@@ -21,27 +28,72 @@ pub struct Bar1min {
 pub struct NewBar1min<'a> {
     pub exchange: &'a str,
     pub market: &'a str,
+    // Start of the minute the bar covers
+    pub timestamp: NaiveDateTime,
     pub open: BigDecimal,
     pub close: BigDecimal,
     pub min: BigDecimal,
     pub max: BigDecimal,
+    // Set for bars flushed before their minute was over, e.g. on shutdown
+    pub partial: bool,
+    // Set for flat bars carried forward from the previous close of a minute without updates
+    pub synthetic: bool,
+    pub interval: &'a str,
+    // Set for bars read from an exchange's klines instead of built from its quotes
+    pub backfilled: bool,
 }
 
 impl<'a> NewBar1min<'a> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(exchange: &'a str,
                market: &'a str,
+               timestamp: NaiveDateTime,
                open: BigDecimal,
                close: BigDecimal,
                min: BigDecimal,
-               max: BigDecimal) -> NewBar1min<'a> {
+               max: BigDecimal,
+               partial: bool,
+               synthetic: bool) -> NewBar1min<'a> {
 
         NewBar1min {
             exchange,
             market,
+            timestamp,
             open,
             close,
             min,
             max,
+            partial,
+            synthetic,
+            interval: INTERVAL_1MIN,
+            backfilled: false,
         }
     }
-}
\ No newline at end of file
+}
+
+// Minute in which a market received no updates
+#[derive(Queryable, Clone, Debug, Serialize)]
+pub struct Gap1min {
+    pub id: i32,
+    pub exchange: String,
+    pub market: String,
+    pub timestamp: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = gaps_1min)]
+pub struct NewGap1min<'a> {
+    pub exchange: &'a str,
+    pub market: &'a str,
+    pub timestamp: NaiveDateTime,
+}
+
+impl<'a> NewGap1min<'a> {
+    pub fn new(exchange: &'a str, market: &'a str, timestamp: NaiveDateTime) -> NewGap1min<'a> {
+        NewGap1min {
+            exchange,
+            market,
+            timestamp,
+        }
+    }
+}
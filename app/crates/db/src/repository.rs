@@ -0,0 +1,194 @@
+use crate::db::{DbConnection, DbPool};
+use crate::models::{Bar1min, Gap1min};
+use crate::schema::{bars_1min, gaps_1min};
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::RwLock;
+
+use chrono::NaiveDateTime;
+use diesel::{BoolExpressionMethods, CombineDsl, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+
+#[derive(Debug)]
+pub enum RepositoryError {
+    // No connection to the database could be made
+    Unavailable(String),
+    // The database was reached but the query failed
+    Query(String),
+}
+
+impl fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepositoryError::Unavailable(e) => write!(f, "Database unavailable: {}", e),
+            RepositoryError::Query(e) => write!(f, "Query failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RepositoryError {}
+
+impl From<diesel::result::Error> for RepositoryError {
+    fn from(e: diesel::result::Error) -> Self {
+        RepositoryError::Query(e.to_string())
+    }
+}
+
+pub type RepositoryResult<T> = Result<T, RepositoryError>;
+
+// Read access to the stored bars and gaps. Calls block, async callers run them on the blocking pool.
+// Exchanges and markets are matched exactly, callers resolve them to the stored names first
+pub trait BarRepository: Send + Sync {
+    // Checks the database can be reached and answers queries
+    fn ping(&self) -> RepositoryResult<()>;
+
+    // Exchanges with at least one stored bar, sorted
+    fn exchanges(&self) -> RepositoryResult<Vec<String>>;
+
+    // Markets with at least one stored bar on any exchange, sorted
+    fn markets(&self) -> RepositoryResult<Vec<String>>;
+
+    // Exchange and market pairs with at least one stored bar or gap, sorted. A market whose feed
+    // was down since it was subscribed only has gaps
+    fn exchange_markets(&self) -> RepositoryResult<Vec<(String, String)>>;
+
+    // Latest bar of a market on an exchange
+    fn last_bar(&self, exchange: &str, market: &str) -> RepositoryResult<Option<Bar1min>>;
+
+    // Gaps of a market on an exchange between `from` and `to` (inclusive), oldest first
+    fn gaps(&self, exchange: &str, market: &str, from: NaiveDateTime, to: NaiveDateTime) -> RepositoryResult<Vec<Gap1min>>;
+}
+
+pub struct PgBarRepository {
+    pool: DbPool,
+}
+
+impl PgBarRepository {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    fn connection(&self) -> RepositoryResult<DbConnection> {
+        self.pool.get().map_err(|e| RepositoryError::Unavailable(e.to_string()))
+    }
+}
+
+impl BarRepository for PgBarRepository {
+    fn ping(&self) -> RepositoryResult<()> {
+        diesel::sql_query("SELECT 1").execute(&mut self.connection()?)?;
+        Ok(())
+    }
+
+    fn exchanges(&self) -> RepositoryResult<Vec<String>> {
+        Ok(bars_1min::table
+            .select(bars_1min::exchange)
+            .distinct()
+            .order(bars_1min::exchange.asc())
+            .load::<String>(&mut self.connection()?)?)
+    }
+
+    fn markets(&self) -> RepositoryResult<Vec<String>> {
+        Ok(bars_1min::table
+            .select(bars_1min::market)
+            .distinct()
+            .order(bars_1min::market.asc())
+            .load::<String>(&mut self.connection()?)?)
+    }
+
+    fn exchange_markets(&self) -> RepositoryResult<Vec<(String, String)>> {
+        let pairs = bars_1min::table
+            .select((bars_1min::exchange, bars_1min::market))
+            .union(gaps_1min::table.select((gaps_1min::exchange, gaps_1min::market)))
+            .load::<(String, String)>(&mut self.connection()?)?;
+        Ok(pairs.into_iter().collect::<BTreeSet<_>>().into_iter().collect())
+    }
+
+    fn last_bar(&self, exchange: &str, market: &str) -> RepositoryResult<Option<Bar1min>> {
+        Ok(bars_1min::table
+            .filter(bars_1min::exchange.eq(exchange).and(bars_1min::market.eq(market)))
+            .order(bars_1min::timestamp.desc())
+            .first::<Bar1min>(&mut self.connection()?)
+            .optional()?)
+    }
+
+    fn gaps(&self, exchange: &str, market: &str, from: NaiveDateTime, to: NaiveDateTime) -> RepositoryResult<Vec<Gap1min>> {
+        Ok(gaps_1min::table
+            .filter(
+                gaps_1min::exchange.eq(exchange)
+                    .and(gaps_1min::market.eq(market))
+                    .and(gaps_1min::timestamp.between(from, to))
+            )
+            .order(gaps_1min::timestamp.asc())
+            .load::<Gap1min>(&mut self.connection()?)?)
+    }
+}
+
+// Bars and gaps kept in memory, for running the api without a database
+#[derive(Default)]
+pub struct InMemoryBarRepository {
+    bars: RwLock<Vec<Bar1min>>,
+    gaps: RwLock<Vec<Gap1min>>,
+}
+
+impl InMemoryBarRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert_bar(&self, bar: Bar1min) {
+        self.bars.write().expect("Bars lock poisoned").push(bar);
+    }
+
+    pub fn insert_gap(&self, gap: Gap1min) {
+        self.gaps.write().expect("Gaps lock poisoned").push(gap);
+    }
+}
+
+impl BarRepository for InMemoryBarRepository {
+    fn ping(&self) -> RepositoryResult<()> {
+        Ok(())
+    }
+
+    fn exchanges(&self) -> RepositoryResult<Vec<String>> {
+        let bars = self.bars.read().expect("Bars lock poisoned");
+        Ok(bars.iter().map(|bar| bar.exchange.clone()).collect::<BTreeSet<_>>().into_iter().collect())
+    }
+
+    fn markets(&self) -> RepositoryResult<Vec<String>> {
+        let bars = self.bars.read().expect("Bars lock poisoned");
+        Ok(bars.iter().map(|bar| bar.market.clone()).collect::<BTreeSet<_>>().into_iter().collect())
+    }
+
+    fn exchange_markets(&self) -> RepositoryResult<Vec<(String, String)>> {
+        let bars = self.bars.read().expect("Bars lock poisoned");
+        let gaps = self.gaps.read().expect("Gaps lock poisoned");
+        Ok(bars
+            .iter()
+            .map(|bar| (bar.exchange.clone(), bar.market.clone()))
+            .chain(gaps.iter().map(|gap| (gap.exchange.clone(), gap.market.clone())))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect())
+    }
+
+    fn last_bar(&self, exchange: &str, market: &str) -> RepositoryResult<Option<Bar1min>> {
+        let bars = self.bars.read().expect("Bars lock poisoned");
+        Ok(bars
+            .iter()
+            .filter(|bar| bar.exchange == exchange && bar.market == market)
+            .max_by_key(|bar| bar.timestamp)
+            .cloned())
+    }
+
+    fn gaps(&self, exchange: &str, market: &str, from: NaiveDateTime, to: NaiveDateTime) -> RepositoryResult<Vec<Gap1min>> {
+        let gaps = self.gaps.read().expect("Gaps lock poisoned");
+        let mut found = gaps
+            .iter()
+            .filter(|gap| gap.exchange == exchange && gap.market == market)
+            .filter(|gap| gap.timestamp >= from && gap.timestamp <= to)
+            .cloned()
+            .collect::<Vec<_>>();
+        found.sort_by_key(|gap| gap.timestamp);
+        Ok(found)
+    }
+}
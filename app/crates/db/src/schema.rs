@@ -10,7 +10,20 @@ diesel::table! {
         close -> Numeric,
         min -> Numeric,
         max -> Numeric,
+        partial -> Bool,
+        synthetic -> Bool,
+        interval -> Varchar,
+        backfilled -> Bool,
     }
 }
 
-diesel::allow_tables_to_appear_in_same_query!(bars_1min,);
+diesel::table! {
+    gaps_1min (id) {
+        id -> Int4,
+        exchange -> Varchar,
+        market -> Varchar,
+        timestamp -> Timestamp,
+    }
+}
+
+diesel::allow_tables_to_appear_in_same_query!(bars_1min, gaps_1min,);
@@ -0,0 +1,196 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use std::collections::HashMap;
+
+use bigdecimal::BigDecimal;
+use criterion::{criterion_group, criterion_main, Criterion};
+use exchange::Exchange;
+use exchange::exchanges::Binance;
+use exchange::structs::{Instrument, Orderbook, Price};
+use serde_json::{json, Value};
+
+// Counts heap allocations so both paths can be compared per tick, not only by latency
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+// Quotes as they come from the venues: Binance pads to 8 decimals, KuCoin trims zeros
+const TICKS: [&str; 8] = [
+    "101213.31000000",
+    "101213.3",
+    "101245.40000000",
+    "101205.2",
+    "101278.80000000",
+    "101240.01",
+    "101239.99000000",
+    "101241",
+];
+
+#[allow(dead_code)]
+struct BigBar {
+    open: BigDecimal,
+    close: BigDecimal,
+    min: BigDecimal,
+    max: BigDecimal,
+}
+
+#[allow(dead_code)]
+struct PriceBar {
+    open: Price,
+    close: Price,
+    min: Price,
+    max: Price,
+}
+
+// Mirrors the previous hot path: parse into BigDecimal and clone on every update
+#[allow(clippy::cmp_owned)]
+fn aggregate_bigdecimal(ticks: &[&str]) -> BigBar {
+    let mut bar: Option<BigBar> = None;
+
+    for tick in ticks {
+        let price: BigDecimal = tick.to_string().parse().unwrap_or(BigDecimal::from(-1));
+        if price < BigDecimal::from(0) {
+            continue;
+        }
+
+        match bar.as_mut() {
+            Some(bar) => {
+                bar.close = price.clone();
+                if bar.min > price {
+                    bar.min = price.clone();
+                }
+                if bar.max < price {
+                    bar.max = price.clone();
+                }
+            },
+            None => {
+                bar = Some(BigBar {
+                    open: price.clone(),
+                    close: price.clone(),
+                    min: price.clone(),
+                    max: price,
+                });
+            }
+        }
+    }
+
+    bar.unwrap()
+}
+
+fn aggregate_price(ticks: &[&str]) -> PriceBar {
+    let mut bar: Option<PriceBar> = None;
+
+    for tick in ticks {
+        let Some(price) = Price::parse(tick) else {
+            continue;
+        };
+
+        match bar.as_mut() {
+            Some(bar) => {
+                bar.close = price;
+                bar.min = bar.min.min(price);
+                bar.max = bar.max.max(price);
+            },
+            None => {
+                bar = Some(PriceBar { open: price, close: price, min: price, max: price });
+            }
+        }
+    }
+
+    bar.unwrap()
+}
+
+#[allow(dead_code)]
+struct BigOrderbook {
+    exchange: &'static str,
+    symbol: String,
+    bid: BigDecimal,
+    ask: BigDecimal,
+}
+
+// Mirrors the previous Orderbook::new: an owned symbol and a BigDecimal per quote
+fn orderbooks_bigdecimal(ticks: &[&str]) {
+    for tick in ticks {
+        black_box(BigOrderbook {
+            exchange: "Binance",
+            symbol: "BTCUSDT".to_string(),
+            bid: tick.to_string().parse().unwrap_or_default(),
+            ask: tick.to_string().parse().unwrap_or_default(),
+        });
+    }
+}
+
+// Quotes parsed at the scale of the instrument, the symbol is shared
+fn orderbooks_price(instrument: &Instrument, ticks: &[&str]) {
+    for tick in ticks {
+        black_box(Orderbook::new("Binance", instrument, tick, tick));
+    }
+}
+
+fn allocations_per_tick(f: impl Fn()) -> f64 {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    let after = ALLOCATIONS.load(Ordering::Relaxed);
+
+    (after - before) as f64 / TICKS.len() as f64
+}
+
+fn bench_aggregation(c: &mut Criterion) {
+    println!(
+        "allocations per tick: bigdecimal = {:.2}, price = {:.2}",
+        allocations_per_tick(|| { black_box(aggregate_bigdecimal(black_box(&TICKS))); }),
+        allocations_per_tick(|| { black_box(aggregate_price(black_box(&TICKS))); }),
+    );
+
+    let mut group = c.benchmark_group("aggregate_1min");
+    group.bench_function("bigdecimal", |b| b.iter(|| aggregate_bigdecimal(black_box(&TICKS))));
+    group.bench_function("price", |b| b.iter(|| aggregate_price(black_box(&TICKS))));
+    group.finish();
+
+    let price = Price::parse("101213.31").unwrap();
+    c.bench_function("price_to_bigdecimal", |b| b.iter(|| black_box(price).to_bigdecimal()));
+}
+
+fn bench_orderbook(c: &mut Criterion) {
+    // BTCUSDT has a tick size of 0.01 on Binance
+    let instrument = Instrument::new("BTCUSDT", 2);
+    let mut binance = Binance::new();
+    binance.instruments_mut().intern("BTCUSDT", Price::parse("0.01000000").unwrap());
+    let update = serde_json::from_value::<HashMap<String, Value>>(json!({
+        "stream": "btcusdt@bookTicker",
+        "data": {"u": 1, "s": "BTCUSDT", "b": "101213.31000000", "B": "1", "a": "101213.32000000", "A": "1"}
+    })).unwrap();
+
+    println!(
+        "allocations per orderbook: bigdecimal = {:.2}, price = {:.2}, binance update = {:.2}",
+        allocations_per_tick(|| orderbooks_bigdecimal(black_box(&TICKS))),
+        allocations_per_tick(|| orderbooks_price(&instrument, black_box(&TICKS))),
+        allocations_per_tick(|| for _ in TICKS { black_box(binance.parse_orderbook_data(black_box(&update))); }),
+    );
+
+    let mut group = c.benchmark_group("orderbook_new");
+    group.bench_function("bigdecimal", |b| b.iter(|| orderbooks_bigdecimal(black_box(&TICKS))));
+    group.bench_function("price", |b| b.iter(|| orderbooks_price(&instrument, black_box(&TICKS))));
+    group.finish();
+
+    c.bench_function("binance_parse_orderbook_data", |b| b.iter(|| binance.parse_orderbook_data(black_box(&update))));
+}
+
+criterion_group!(benches, bench_aggregation, bench_orderbook);
+criterion_main!(benches);
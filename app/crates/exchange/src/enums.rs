@@ -1,12 +1,18 @@
-use crate::{util, Exchange, ReadStream, WriteStream};
+use crate::{instruments, util, Exchange, ReadStream, WriteStream};
 
-use anyhow::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{anyhow, Result};
 use futures_util::{SinkExt, StreamExt};
-use serde_json::json;
+use serde_json::{json, Value};
 use tokio_tungstenite::connect_async;
 use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, instrument};
 use url::Url;
 
+// Id of the next subscription request, exchanges echo it in their ack
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
 pub enum AnyExchange {
     Binance,
     ByBit,
@@ -14,34 +20,99 @@ pub enum AnyExchange {
 }
 
 impl AnyExchange {
+    #[instrument(name = "connect", skip_all, fields(exchange = exchange.name(), markets = markets.len()), err)]
     pub async fn connect_orderbooks_async(exchange: &mut dyn Exchange, markets: Vec<String>) -> Result<()>{
-        let res;
-        
-        match exchange.get_type() {
+        Self::intern(exchange, &markets).await?;
+        let (read_stream, write_stream) = match exchange.get_type() {
             AnyExchange::Binance => {
-                res = Self::connect_orderbooks_binance_async(exchange, markets).await?;
+                Self::connect_orderbooks_binance_async(exchange, markets).await?
             },
             AnyExchange::ByBit => {
-                res = Self::connect_orderbooks_bybit_async(exchange, markets).await?;
+                Self::connect_orderbooks_bybit_async(exchange, markets).await?
             },
             AnyExchange::KuCoin => {
-                res = Self::connect_orderbooks_kucoin_async(exchange, markets).await?;
+                Self::connect_orderbooks_kucoin_async(exchange, markets).await?
             }
-        }
-        
-        let (read_stream, write_stream) = res;
+        };
+
         exchange.set_read_stream(read_stream);
         exchange.set_write_stream(write_stream);
-        
+        debug!("Connected");
+
+        Ok(())
+    }
+
+    // Subscribes a live connection to more markets
+    pub async fn subscribe(exchange: &mut dyn Exchange, markets: &[String]) -> Result<()> {
+        Self::intern(exchange, markets).await?;
+        Self::send_subscription(exchange, markets, true).await
+    }
+
+    // Looks up the tick sizes of the markets the exchange doesn't know yet, so their updates
+    // can be parsed at the scale of the tick size. Reconnecting doesn't look them up again
+    pub async fn intern(exchange: &mut dyn Exchange, markets: &[String]) -> Result<()> {
+        let mut symbols = markets.iter()
+            .map(|market| instruments::exchange_symbol(exchange.get_type(), market))
+            .filter(|symbol| !exchange.instruments().contains(symbol))
+            .collect::<Vec<String>>();
+        symbols.dedup();
+        if symbols.is_empty() {
+            return Ok(());
+        }
+
+        let tick_sizes = instruments::fetch_tick_sizes(exchange.get_type(), exchange.rest_url(), &symbols).await?;
+        for (symbol, tick_size) in tick_sizes {
+            let instrument = exchange.instruments_mut().intern(&symbol, tick_size);
+            debug!(market = %instrument.symbol, %tick_size, scale = instrument.scale, "Interned");
+        }
+
+        Ok(())
+    }
+
+    // Stops the updates of markets on a live connection
+    pub async fn unsubscribe(exchange: &mut dyn Exchange, markets: &[String]) -> Result<()> {
+        Self::send_subscription(exchange, markets, false).await
+    }
+
+    async fn send_subscription(exchange: &mut dyn Exchange, markets: &[String], subscribe: bool) -> Result<()> {
+        let name = exchange.name();
+        let message = Self::subscription_message(exchange.get_type(), markets, subscribe);
+        let write_stream = exchange.write_stream()
+            .as_mut()
+            .ok_or_else(|| anyhow!("{} isn't connected", name))?;
+
+        write_stream.send(Message::text(message.to_string())).await?;
         Ok(())
     }
-    
+
+    fn subscription_message(exchange_type: &AnyExchange, markets: &[String], subscribe: bool) -> Value {
+        let id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+        match exchange_type {
+            AnyExchange::Binance => json!({
+                "method": if subscribe { "SUBSCRIBE" } else { "UNSUBSCRIBE" },
+                "params": markets.iter().map(|m| format!("{}@bookTicker", m.to_lowercase())).collect::<Vec<String>>(),
+                "id": id,
+            }),
+            AnyExchange::ByBit => json!({
+                "op": if subscribe { "subscribe" } else { "unsubscribe" },
+                "args": markets.iter().map(|m| format!("orderbook.1.{}", m.to_uppercase())).collect::<Vec<String>>(),
+            }),
+            AnyExchange::KuCoin => json!({
+                "id": id,
+                "type": if subscribe { "subscribe" } else { "unsubscribe" },
+                "topic": format!("/spotMarket/level1:{}", markets.iter().map(|m| m.to_uppercase()).collect::<Vec<String>>().join(",")),
+                "response": true
+            }),
+        }
+    }
+
     async fn connect_orderbooks_binance_async(exchange: &mut dyn Exchange, markets: Vec<String>) -> Result<(ReadStream, WriteStream)> {
         let url = Url::parse_with_params(
             exchange.url(),
             &[("streams", markets.join("@bookTicker/") + "@bookTicker")]
         )?;
-        
+        markets.iter().for_each(|market| debug!(market = %market, "Subscribing"));
+
         let (ws_stream, _) = connect_async(url.to_string()).await?;
         let (write_stream,
             read_stream) = ws_stream.split();
@@ -54,15 +125,8 @@ impl AnyExchange {
         let (mut write_stream,
             read_stream) = ws_stream.split();
 
-        let markets = markets
-            .iter()
-            .map(|m| format!("orderbook.1.{}", m.to_uppercase()))
-            .collect::<Vec<String>>();
-
-        let msg = json!({
-            "op": "subscribe",
-            "args": markets,
-        });
+        markets.iter().for_each(|market| debug!(market = %market, "Subscribing"));
+        let msg = Self::subscription_message(&AnyExchange::ByBit, &markets, true);
 
         write_stream.send(Message::text(msg.to_string())).await?;
         
@@ -70,7 +134,8 @@ impl AnyExchange {
     }
 
     async fn connect_orderbooks_kucoin_async(exchange: &mut dyn Exchange, markets: Vec<String>) -> Result<(ReadStream, WriteStream)> {
-        let token = util::get_public_token_kucoin().await?;
+        let token_url = exchange.token_url().ok_or_else(|| anyhow!("No token url for {}", exchange.name()))?;
+        let token = util::get_public_token_kucoin(token_url).await?;
         let url = Url::parse_with_params(
             exchange.url(),
             &[("token", token)],
@@ -81,18 +146,8 @@ impl AnyExchange {
         let (mut write_stream,
             read_stream) = ws_stream.split();
 
-        let markets = markets
-            .iter()
-            .map(|m| {m.to_uppercase()})
-            .collect::<Vec<String>>()
-            .join(",");
-
-        let msg = json!({
-            "id": 1,
-            "type": "subscribe",
-            "topic": format!("/spotMarket/level1:{}", markets),
-            "response": true
-        });
+        markets.iter().for_each(|market| debug!(market = %market, "Subscribing"));
+        let msg = Self::subscription_message(&AnyExchange::KuCoin, &markets, true);
 
         write_stream.send(Message::text(msg.to_string())).await?;
         
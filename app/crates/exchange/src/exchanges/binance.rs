@@ -1,5 +1,5 @@
 use crate::traits::Exchange;
-use crate::structs::Orderbook;
+use crate::structs::{Instruments, Orderbook};
 use crate::{ReadStream, WriteStream};
 
 use std::collections::HashMap;
@@ -8,19 +8,47 @@ use serde_json::Value;
 use crate::enums::AnyExchange;
 
 pub struct Binance {
+    url: String,
+    rest_url: String,
     read_stream: Option<ReadStream>,
     write_stream: Option<WriteStream>,
+    instruments: Instruments,
     exchange_type: AnyExchange,
 }
 
 impl Binance {
+    // Production websocket stream
+    pub const URL: &'static str = "wss://stream.binance.com/stream";
+    // Production REST api, serves the tick sizes and the klines of the backfill
+    pub const REST_URL: &'static str = "https://api.binance.com";
+
     pub fn new() -> Self {
+        Self::with_url(Self::URL)
+    }
+
+    // Connects to another server speaking the same protocol, e.g. a mock in tests
+    pub fn with_url(url: impl Into<String>) -> Self {
         Self {
+            url: url.into(),
+            rest_url: Self::REST_URL.to_string(),
             read_stream: None,
             write_stream: None,
+            instruments: Instruments::default(),
             exchange_type: AnyExchange::Binance
         }
     }
+
+    // Reads the tick sizes from another REST api, e.g. a mock in tests
+    pub fn with_rest_url(mut self, rest_url: impl Into<String>) -> Self {
+        self.rest_url = rest_url.into();
+        self
+    }
+}
+
+impl Default for Binance {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Exchange for Binance {
@@ -28,8 +56,12 @@ impl Exchange for Binance {
         "Binance"
     }
 
-    fn url(&self) -> &'static str {
-        "wss://stream.binance.com/stream"
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn rest_url(&self) -> &str {
+        &self.rest_url
     }
 
     fn get_type(&self) -> &AnyExchange {
@@ -53,16 +85,22 @@ impl Exchange for Binance {
             .get("a")?
             .as_str()?;
 
-        Some(
-            Orderbook::new(
-                self.name(),
-                symbol,
-                bid,
-                ask
-            )
+        Orderbook::new(
+            self.name(),
+            self.instruments.get(symbol)?,
+            bid,
+            ask
         )
     }
 
+    fn instruments(&self) -> &Instruments {
+        &self.instruments
+    }
+
+    fn instruments_mut(&mut self) -> &mut Instruments {
+        &mut self.instruments
+    }
+
     fn read_stream(&mut self) -> &mut Option<ReadStream>{
         &mut self.read_stream
     }
@@ -1,5 +1,5 @@
 use crate::Exchange;
-use crate::structs::Orderbook;
+use crate::structs::{Instruments, Orderbook};
 use crate::{ReadStream, WriteStream};
 
 use std::collections::HashMap;
@@ -8,19 +8,47 @@ use serde_json::Value;
 use crate::enums::AnyExchange;
 
 pub struct ByBit {
+    url: String,
+    rest_url: String,
     read_stream: Option<ReadStream>,
     write_stream: Option<WriteStream>,
+    instruments: Instruments,
     exchange_type: AnyExchange,
 }
 
 impl ByBit {
+    // Production websocket stream
+    pub const URL: &'static str = "wss://stream.bybit.com/v5/public/spot";
+    // Production REST api, serves the tick sizes and the klines of the backfill
+    pub const REST_URL: &'static str = "https://api.bybit.com";
+
     pub fn new() -> Self {
+        Self::with_url(Self::URL)
+    }
+
+    // Connects to another server speaking the same protocol, e.g. a mock in tests
+    pub fn with_url(url: impl Into<String>) -> Self {
         Self {
+            url: url.into(),
+            rest_url: Self::REST_URL.to_string(),
             read_stream: None,
             write_stream: None,
+            instruments: Instruments::default(),
             exchange_type: AnyExchange::ByBit
         }
     }
+
+    // Reads the tick sizes from another REST api, e.g. a mock in tests
+    pub fn with_rest_url(mut self, rest_url: impl Into<String>) -> Self {
+        self.rest_url = rest_url.into();
+        self
+    }
+}
+
+impl Default for ByBit {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Exchange for ByBit {
@@ -28,8 +56,12 @@ impl Exchange for ByBit {
         "ByBit"
     }
 
-    fn url(&self) -> &'static str {
-        "wss://stream.bybit.com/v5/public/spot"
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn rest_url(&self) -> &str {
+        &self.rest_url
     }
 
     fn get_type(&self) -> &AnyExchange {
@@ -48,29 +80,35 @@ impl Exchange for ByBit {
         let bid = data
             .get("b")?
             .as_array()?
-            .get(0)?
+            .first()?
             .as_array()?
-            .get(0)?
+            .first()?
             .as_str()?;
 
         let ask = data
             .get("a")?
             .as_array()?
-            .get(0)?
+            .first()?
             .as_array()?
-            .get(0)?
+            .first()?
             .as_str()?;
 
-        Some(
-            Orderbook::new(
-                self.name(),
-                symbol,
-                bid,
-                ask
-            )
+        Orderbook::new(
+            self.name(),
+            self.instruments.get(symbol)?,
+            bid,
+            ask
         )
     }
 
+    fn instruments(&self) -> &Instruments {
+        &self.instruments
+    }
+
+    fn instruments_mut(&mut self) -> &mut Instruments {
+        &mut self.instruments
+    }
+
     fn read_stream(&mut self) -> &mut Option<ReadStream>{
         &mut self.read_stream
     }
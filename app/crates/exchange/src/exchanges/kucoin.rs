@@ -1,5 +1,5 @@
 use crate::Exchange;
-use crate::structs::Orderbook;
+use crate::structs::{Instruments, Orderbook};
 use crate::{ReadStream, WriteStream};
 
 use std::collections::HashMap;
@@ -8,20 +8,51 @@ use serde_json::Value;
 use crate::enums::AnyExchange;
 
 pub struct KuCoin {
+    url: String,
+    rest_url: String,
+    token_url: String,
     read_stream: Option<ReadStream>,
     write_stream: Option<WriteStream>,
+    instruments: Instruments,
     exchange_type: AnyExchange,
 }
 
 impl KuCoin {
-    
+    // Production websocket stream
+    pub const URL: &'static str = "wss://ws-api-spot.kucoin.com/";
+    // Production REST endpoint handing out the public websocket token
+    pub const TOKEN_URL: &'static str = "https://api.kucoin.com/api/v1/bullet-public";
+    // Production REST api, serves the tick sizes and the klines of the backfill
+    pub const REST_URL: &'static str = "https://api.kucoin.com";
+
     pub fn new() -> Self {
+        Self::with_urls(Self::URL, Self::TOKEN_URL)
+    }
+
+    // Connects to another server speaking the same protocol, e.g. a mock in tests
+    pub fn with_urls(url: impl Into<String>, token_url: impl Into<String>) -> Self {
         Self {
+            url: url.into(),
+            rest_url: Self::REST_URL.to_string(),
+            token_url: token_url.into(),
             read_stream: None,
             write_stream: None,
+            instruments: Instruments::default(),
             exchange_type: AnyExchange::KuCoin,
         }
     }
+
+    // Reads the tick sizes from another REST api, e.g. a mock in tests
+    pub fn with_rest_url(mut self, rest_url: impl Into<String>) -> Self {
+        self.rest_url = rest_url.into();
+        self
+    }
+}
+
+impl Default for KuCoin {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Exchange for KuCoin {
@@ -29,8 +60,16 @@ impl Exchange for KuCoin {
         "KuCoin"
     }
 
-    fn url(&self) -> &'static str {
-        "wss://ws-api-spot.kucoin.com/"
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn rest_url(&self) -> &str {
+        &self.rest_url
+    }
+
+    fn token_url(&self) -> Option<&str> {
+        Some(&self.token_url)
     }
 
     fn get_type(&self) -> &AnyExchange {
@@ -38,12 +77,11 @@ impl Exchange for KuCoin {
     }
 
     fn parse_orderbook_data(&self, raw_data: &HashMap<String, Value>) -> Option<Orderbook> {
-        let symbol = raw_data
+        // /spotMarket/level1:BTC-USDT
+        let (_, symbol) = raw_data
             .get("topic")?
             .as_str()?
-            .split(":")
-            .collect::<Vec<&str>>()[1]
-            .replace("-", "");
+            .split_once(':')?;
 
         let data = raw_data
             .get("data")?
@@ -52,25 +90,31 @@ impl Exchange for KuCoin {
         let bid = data
             .get("bids")?
             .as_array()?
-            .get(0)?
+            .first()?
             .as_str()?;
 
         let ask = data
             .get("asks")?
             .as_array()?
-            .get(0)?
+            .first()?
             .as_str()?;
 
-        Some(
-            Orderbook::new(
-                self.name(),
-                symbol.as_str(),
-                bid,
-                ask
-            )
+        Orderbook::new(
+            self.name(),
+            self.instruments.get(symbol)?,
+            bid,
+            ask
         )
     }
 
+    fn instruments(&self) -> &Instruments {
+        &self.instruments
+    }
+
+    fn instruments_mut(&mut self) -> &mut Instruments {
+        &mut self.instruments
+    }
+
     fn read_stream(&mut self) -> &mut Option<ReadStream>{
         &mut self.read_stream
     }
@@ -0,0 +1,160 @@
+use crate::enums::AnyExchange;
+use crate::structs::Price;
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::Value;
+use tracing::debug;
+use url::Url;
+
+// Symbol the updates and the REST api of an exchange use for a configured market,
+// e.g. btc-usdt is BTCUSDT on Binance and ByBit but BTC-USDT on KuCoin
+pub fn exchange_symbol(exchange: &AnyExchange, market: &str) -> String {
+    match exchange {
+        AnyExchange::Binance | AnyExchange::ByBit => market.replace('-', "").to_uppercase(),
+        AnyExchange::KuCoin => market.to_uppercase(),
+    }
+}
+
+// Tick sizes of `symbols` (as given by exchange_symbol) from the REST api of an exchange.
+// Fails if the exchange doesn't list one of them
+pub async fn fetch_tick_sizes(exchange: &AnyExchange, base_url: &str, symbols: &[String]) -> Result<Vec<(String, Price)>> {
+    let base_url = base_url.trim_end_matches('/');
+    let client = reqwest::Client::new();
+
+    let mut tick_sizes = Vec::with_capacity(symbols.len());
+    match exchange {
+        // One request lists them all: /api/v3/exchangeInfo?symbols=["BTCUSDT","ETHUSDT"]
+        AnyExchange::Binance => {
+            let url = Url::parse_with_params(&format!("{}/api/v3/exchangeInfo", base_url), &[
+                ("symbols", serde_json::to_string(symbols)?),
+            ])?;
+            tick_sizes.extend(parse_binance(&get(&client, url).await?)?);
+        },
+        // /v5/market/instruments-info?category=spot&symbol=BTCUSDT
+        AnyExchange::ByBit => {
+            for symbol in symbols {
+                let url = Url::parse_with_params(&format!("{}/v5/market/instruments-info", base_url), &[
+                    ("category", "spot"),
+                    ("symbol", symbol.as_str()),
+                ])?;
+                tick_sizes.extend(parse_bybit(&get(&client, url).await?)?);
+            }
+        },
+        // /api/v2/symbols/BTC-USDT
+        AnyExchange::KuCoin => {
+            for symbol in symbols {
+                let url = Url::parse(&format!("{}/api/v2/symbols/{}", base_url, symbol))?;
+                tick_sizes.push(parse_kucoin(&get(&client, url).await?)?);
+            }
+        },
+    }
+
+    if let Some(missing) = symbols.iter().find(|symbol| !tick_sizes.iter().any(|(listed, _)| listed == *symbol)) {
+        bail!("{} isn't listed by the exchange", missing);
+    }
+    Ok(tick_sizes)
+}
+
+async fn get(client: &reqwest::Client, url: Url) -> Result<Value> {
+    debug!(url = %url, "Requesting instruments");
+
+    let response = client.get(url).send().await?;
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        bail!("Instruments request failed with {}: {}", status, body);
+    }
+
+    Ok(serde_json::from_str(&body)?)
+}
+
+// {"symbols": [{"symbol": "BTCUSDT", "filters": [{"filterType": "PRICE_FILTER", "tickSize": "0.01000000", ...}, ...]}, ...]}
+fn parse_binance(response: &Value) -> Result<Vec<(String, Price)>> {
+    list(response, &["symbols"])?
+        .iter()
+        .map(|instrument| {
+            let tick_size = instrument.get("filters")
+                .and_then(|filters| filters.as_array())
+                .and_then(|filters| filters.iter().find(|filter| filter.get("filterType").and_then(|kind| kind.as_str()) == Some("PRICE_FILTER")))
+                .and_then(|filter| filter.get("tickSize"));
+            entry(instrument.get("symbol"), tick_size)
+        })
+        .collect()
+}
+
+// {"retCode": 0, "retMsg": "OK", "result": {"list": [{"symbol": "BTCUSDT", "priceFilter": {"tickSize": "0.01"}, ...}]}}
+fn parse_bybit(response: &Value) -> Result<Vec<(String, Price)>> {
+    if let Some(code) = response.get("retCode").and_then(|code| code.as_i64())
+        && code != 0
+    {
+        bail!("Instruments request failed with retCode {}: {}", code, response.get("retMsg").unwrap_or(&Value::Null));
+    }
+
+    list(response, &["result", "list"])?
+        .iter()
+        .map(|instrument| entry(instrument.get("symbol"), instrument.get("priceFilter").and_then(|filter| filter.get("tickSize"))))
+        .collect()
+}
+
+// {"code": "200000", "data": {"symbol": "BTC-USDT", "priceIncrement": "0.1", ...}}, data is null for an unknown symbol
+fn parse_kucoin(response: &Value) -> Result<(String, Price)> {
+    let instrument = response.get("data")
+        .filter(|data| data.is_object())
+        .ok_or_else(|| anyhow!("No instrument in the response: {}", response))?;
+
+    entry(instrument.get("symbol"), instrument.get("priceIncrement"))
+}
+
+fn list<'a>(response: &'a Value, path: &[&str]) -> Result<&'a Vec<Value>> {
+    path.iter()
+        .try_fold(response, |value, key| value.get(key))
+        .and_then(|list| list.as_array())
+        .ok_or_else(|| anyhow!("No {} in the instruments response", path.join(".")))
+}
+
+fn entry(symbol: Option<&Value>, tick_size: Option<&Value>) -> Result<(String, Price)> {
+    let symbol = symbol.and_then(|symbol| symbol.as_str()).ok_or_else(|| anyhow!("Instrument without symbol"))?;
+    let tick_size = tick_size
+        .and_then(|tick_size| tick_size.as_str())
+        .and_then(Price::parse)
+        .filter(|tick_size| tick_size.mantissa() > 0)
+        .ok_or_else(|| anyhow!("{} has no valid tick size", symbol))?;
+
+    Ok((symbol.to_string(), tick_size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_binance, parse_bybit, parse_kucoin};
+    use crate::structs::Price;
+
+    use serde_json::json;
+
+    #[test]
+    fn reads_the_price_filter_of_binance() {
+        let response = json!({"symbols": [{"symbol": "BTCUSDT", "filters": [
+            {"filterType": "LOT_SIZE", "stepSize": "0.00001000"},
+            {"filterType": "PRICE_FILTER", "minPrice": "0.01000000", "tickSize": "0.01000000"},
+        ]}]});
+
+        let tick_sizes = parse_binance(&response).expect("Invalid response");
+        assert_eq!(tick_sizes, vec![("BTCUSDT".to_string(), Price::new(1, 2).unwrap())]);
+    }
+
+    #[test]
+    fn reads_the_tick_sizes_of_bybit_and_kucoin() {
+        let bybit = json!({"retCode": 0, "result": {"list": [{"symbol": "BTCUSDT", "priceFilter": {"tickSize": "0.1"}}]}});
+        assert_eq!(parse_bybit(&bybit).expect("Invalid response"), vec![("BTCUSDT".to_string(), Price::new(1, 1).unwrap())]);
+
+        let kucoin = json!({"code": "200000", "data": {"symbol": "BTC-USDT", "priceIncrement": "10"}});
+        assert_eq!(parse_kucoin(&kucoin).expect("Invalid response"), ("BTC-USDT".to_string(), Price::new(10, 0).unwrap()));
+    }
+
+    #[test]
+    fn rejects_missing_or_invalid_tick_sizes() {
+        assert!(parse_bybit(&json!({"retCode": 10001, "retMsg": "params error"})).is_err());
+        assert!(parse_kucoin(&json!({"code": "200000", "data": null})).is_err());
+        assert!(parse_binance(&json!({"symbols": [{"symbol": "BTCUSDT", "filters": []}]})).is_err());
+        assert!(parse_bybit(&json!({"retCode": 0, "result": {"list": [{"symbol": "BTCUSDT", "priceFilter": {"tickSize": "0"}}]}})).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use crate::enums::AnyExchange;
+use crate::structs::Price;
+
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+use serde_json::Value;
+use tracing::debug;
+use url::Url;
+
+const MINUTE_MS: i64 = 60_000;
+
+// 1-minute candle of an exchange's REST api, built from its trades
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Kline {
+    // Start of the minute, milliseconds since the epoch
+    pub open_time: i64,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+}
+
+// The exchange refused a request for exceeding its rate limit
+#[derive(Debug)]
+pub struct RateLimited {
+    // How long the exchange asked to wait, when it said
+    pub retry_after: Option<Duration>,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Rate limited by the exchange")
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+// Reads 1-minute klines from the REST api of an exchange
+pub struct KlineClient {
+    exchange: AnyExchange,
+    base_url: String,
+    client: reqwest::Client,
+}
+
+impl KlineClient {
+    // `base_url` is the REST api root, e.g. Binance::REST_URL or a mock in tests
+    pub fn new(exchange: AnyExchange, base_url: impl Into<String>) -> Self {
+        Self {
+            exchange,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    // Minutes requested at once, the most klines the exchange returns for one request.
+    // KuCoin returns 1500, the request asks for one more minute than the range (see `url`)
+    pub fn page_size(&self) -> usize {
+        match self.exchange {
+            AnyExchange::Binance => 1000,
+            AnyExchange::ByBit => 1000,
+            AnyExchange::KuCoin => 1499,
+        }
+    }
+
+    // Klines of `market` opened from `start` to `end` (inclusive, milliseconds), oldest first.
+    // Ranges longer than page_size() minutes are cut short, the caller pages through them.
+    // Fails with RateLimited when the exchange asks to slow down
+    pub async fn fetch(&self, market: &str, start: i64, end: i64) -> Result<Vec<Kline>> {
+        let url = self.url(market, start, end)?;
+        debug!(url = %url, "Requesting klines");
+
+        let response = self.client.get(url).send().await?;
+        let status = response.status();
+        // Binance answers 418 once it banned a client that ignored its 429s
+        if status == StatusCode::TOO_MANY_REQUESTS || status.as_u16() == 418 {
+            let retry_after = response.headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .map(Duration::from_secs);
+            return Err(RateLimited { retry_after }.into());
+        }
+        let body = response.text().await?;
+        if !status.is_success() {
+            bail!("Klines request failed with {}: {}", status, body);
+        }
+
+        let mut klines = match self.exchange {
+            AnyExchange::Binance => parse_binance(&body)?,
+            AnyExchange::ByBit => parse_bybit(&body)?,
+            AnyExchange::KuCoin => parse_kucoin(&body)?,
+        };
+        // ByBit and KuCoin list the newest first
+        klines.retain(|kline| kline.open_time >= start && kline.open_time <= end);
+        klines.sort_by_key(|kline| kline.open_time);
+        Ok(klines)
+    }
+
+    fn url(&self, market: &str, start: i64, end: i64) -> Result<Url> {
+        let url = match self.exchange {
+            // /api/v3/klines?symbol=BTCUSDT&interval=1m&startTime=..&endTime=..&limit=1000
+            AnyExchange::Binance => Url::parse_with_params(&format!("{}/api/v3/klines", self.base_url), &[
+                ("symbol", market.replace('-', "").to_uppercase()),
+                ("interval", "1m".to_string()),
+                ("startTime", start.to_string()),
+                ("endTime", end.to_string()),
+                ("limit", self.page_size().to_string()),
+            ])?,
+            // /v5/market/kline?category=spot&symbol=BTCUSDT&interval=1&start=..&end=..&limit=1000
+            AnyExchange::ByBit => Url::parse_with_params(&format!("{}/v5/market/kline", self.base_url), &[
+                ("category", "spot".to_string()),
+                ("symbol", market.replace('-', "").to_uppercase()),
+                ("interval", "1".to_string()),
+                ("start", start.to_string()),
+                ("end", end.to_string()),
+                ("limit", self.page_size().to_string()),
+            ])?,
+            // /api/v1/market/candles?type=1min&symbol=BTC-USDT&startAt=..&endAt=.., in seconds.
+            // Ends a minute later so `end` is included however the bound is treated
+            AnyExchange::KuCoin => Url::parse_with_params(&format!("{}/api/v1/market/candles", self.base_url), &[
+                ("type", "1min".to_string()),
+                ("symbol", market.to_uppercase()),
+                ("startAt", (start / 1000).to_string()),
+                ("endAt", ((end + MINUTE_MS) / 1000).to_string()),
+            ])?,
+        };
+        Ok(url)
+    }
+}
+
+// [[1499040000000, "0.01634790", "0.80000000", "0.01575800", "0.01577100", ...], ...]
+// as open time, open, high, low, close
+fn parse_binance(body: &str) -> Result<Vec<Kline>> {
+    let rows = serde_json::from_str::<Vec<Vec<Value>>>(body)?;
+    rows.iter()
+        .map(|row| {
+            let open_time = row.first().and_then(|time| time.as_i64()).ok_or_else(|| anyhow!("Kline without open time"))?;
+            kline(open_time, row.get(1..5), [0, 1, 2, 3])
+        })
+        .collect()
+}
+
+// {"retCode": 0, "retMsg": "OK", "result": {"list": [["1670608800000", "17071", "17073", "17027", "17055.5", ...], ...]}}
+// as open time, open, high, low, close
+fn parse_bybit(body: &str) -> Result<Vec<Kline>> {
+    let response = serde_json::from_str::<Value>(body)?;
+    match response.get("retCode").and_then(|code| code.as_i64()) {
+        Some(0) => {},
+        // Too many visits
+        Some(10006) => return Err(RateLimited { retry_after: None }.into()),
+        code => bail!("ByBit klines request failed with {:?}: {}", code, response.get("retMsg").unwrap_or(&Value::Null)),
+    }
+
+    rows(&response, &["result", "list"])?
+        .iter()
+        .map(|row| kline(string_time(row, 1)?, row.get(1..5), [0, 1, 2, 3]))
+        .collect()
+}
+
+// {"code": "200000", "data": [["1545904980", "0.058", "0.049", "0.058", "0.049", ...], ...]}
+// as open time in seconds, open, close, high, low
+fn parse_kucoin(body: &str) -> Result<Vec<Kline>> {
+    let response = serde_json::from_str::<Value>(body)?;
+    match response.get("code").and_then(|code| code.as_str()) {
+        Some("200000") => {},
+        Some("429000") => return Err(RateLimited { retry_after: None }.into()),
+        code => bail!("KuCoin klines request failed with {:?}: {}", code, response.get("msg").unwrap_or(&Value::Null)),
+    }
+
+    rows(&response, &["data"])?
+        .iter()
+        .map(|row| kline(string_time(row, 1000)?, row.get(1..5), [0, 2, 3, 1]))
+        .collect()
+}
+
+fn rows<'a>(response: &'a Value, path: &[&str]) -> Result<Vec<&'a [Value]>> {
+    let list = path.iter()
+        .try_fold(response, |value, key| value.get(key))
+        .and_then(|list| list.as_array())
+        .ok_or_else(|| anyhow!("No {} in the klines response", path.join(".")))?;
+
+    list.iter()
+        .map(|row| row.as_array().map(|row| row.as_slice()).ok_or_else(|| anyhow!("Kline isn't an array")))
+        .collect()
+}
+
+// Open time sent as a string of `unit` milliseconds
+fn string_time(row: &[Value], unit: i64) -> Result<i64> {
+    row.first()
+        .and_then(|time| time.as_str())
+        .and_then(|time| time.parse::<i64>().ok())
+        .map(|time| time * unit)
+        .ok_or_else(|| anyhow!("Kline without open time"))
+}
+
+// `order` gives the positions of open, high, low and close among `prices`
+fn kline(open_time: i64, prices: Option<&[Value]>, order: [usize; 4]) -> Result<Kline> {
+    let prices = prices.ok_or_else(|| anyhow!("Kline without prices"))?;
+    let price = |index: usize| {
+        prices[order[index]]
+            .as_str()
+            .and_then(Price::parse)
+            .ok_or_else(|| anyhow!("Invalid kline price {}", prices[order[index]]))
+    };
+
+    Ok(Kline {
+        open_time,
+        open: price(0)?,
+        high: price(1)?,
+        low: price(2)?,
+        close: price(3)?,
+    })
+}
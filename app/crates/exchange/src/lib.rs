@@ -2,6 +2,8 @@ pub mod structs;
 pub mod traits;
 pub mod enums;
 pub mod exchanges;
+pub mod instruments;
+pub mod klines;
 mod util;
 
 pub use exchanges::binance::Binance;
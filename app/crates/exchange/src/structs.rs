@@ -1,21 +1,421 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
 use bigdecimal::BigDecimal;
+use bigdecimal::num_bigint::BigInt;
 
 // DTO for orderbook
 #[derive(Debug)]
 pub struct Orderbook {
-    pub exchange: String,
-    pub symbol: String,
-    pub bid: BigDecimal,
-    pub ask: BigDecimal,
+    pub exchange: &'static str,
+    pub symbol: Arc<str>,
+    pub bid: Price,
+    pub ask: Price,
 }
 
 impl Orderbook {
-    pub fn new(exchange: &str, symbol: &str, bid: &str, ask: &str) -> Orderbook {
-        Self {
-            exchange: exchange.to_string(),
-            symbol: symbol.to_string(),
-            bid: bid.to_string().parse().unwrap_or(BigDecimal::from(-1)),
-            ask: ask.to_string().parse().unwrap_or(BigDecimal::from(-1)),
+    // Parses the quotes at the scale of the instrument, shares its symbol instead of copying it
+    pub fn new(exchange: &'static str, instrument: &Instrument, bid: &str, ask: &str) -> Option<Orderbook> {
+        Some(Self {
+            exchange,
+            symbol: instrument.symbol.clone(),
+            bid: Price::parse_at(bid, instrument.scale)?,
+            ask: Price::parse_at(ask, instrument.scale)?,
+        })
+    }
+}
+
+// Market of an exchange as its updates are parsed
+#[derive(Clone, Debug)]
+pub struct Instrument {
+    // Symbol the bars are stored under, e.g. BTCUSDT
+    pub symbol: Arc<str>,
+    // Decimal places of the tick size, every quote is parsed to it
+    pub scale: u8,
+}
+
+impl Instrument {
+    pub fn new(symbol: &str, scale: u8) -> Self {
+        Self { symbol: Arc::from(market_symbol(symbol)), scale }
+    }
+}
+
+// Instruments of an exchange by the symbol its updates carry, e.g. BTC-USDT on KuCoin
+#[derive(Debug, Default)]
+pub struct Instruments {
+    by_symbol: HashMap<String, Instrument>,
+}
+
+impl Instruments {
+    // Registers a market with the scale of its tick size ("0.01000000" is 2 decimals)
+    pub fn intern(&mut self, symbol: &str, tick_size: Price) -> &Instrument {
+        let instrument = Instrument::new(symbol, tick_size.normalized().scale);
+        self.by_symbol.insert(symbol.to_string(), instrument);
+        &self.by_symbol[symbol]
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<&Instrument> {
+        self.by_symbol.get(symbol)
+    }
+
+    pub fn contains(&self, symbol: &str) -> bool {
+        self.by_symbol.contains_key(symbol)
+    }
+}
+
+// Symbol the updates of a configured market carry, e.g. btc-usdt is BTCUSDT
+pub fn market_symbol(market: &str) -> String {
+    market.replace('-', "").to_uppercase()
+}
+
+// Compact fixed-point decimal: value = mantissa * 10^-scale.
+// Quotes are parsed at the fixed scale of their instrument, so the prices of a market compare
+// by mantissa alone. Equal values are equal and hash the same whatever their scale.
+#[derive(Clone, Copy, Debug)]
+pub struct Price {
+    mantissa: i64,
+    scale: u8,
+}
+
+impl Price {
+    // Max number of decimal places a price can carry
+    pub const MAX_SCALE: u8 = 18;
+
+    pub fn new(mantissa: i64, scale: u8) -> Option<Self> {
+        if scale > Self::MAX_SCALE {
+            return None;
+        }
+
+        Some(Self { mantissa, scale }.normalized())
+    }
+
+    // Parses a plain decimal string ("101213.31000000") without allocating
+    pub fn parse(raw: &str) -> Option<Self> {
+        let bytes = raw.as_bytes();
+        let (negative, digits) = match bytes.first()? {
+            b'-' => (true, &bytes[1..]),
+            _ => (false, bytes),
+        };
+
+        let mut mantissa: i64 = 0;
+        let mut scale: u8 = 0;
+        // Fraction zeros are only applied once a non-zero digit follows them,
+        // so zero-padded quotes ("0.10000000") never overflow the mantissa
+        let mut pending_zeros: u8 = 0;
+        let mut seen_digit = false;
+        let mut seen_point = false;
+
+        for &b in digits {
+            match b {
+                b'0' if seen_point => {
+                    pending_zeros = pending_zeros.checked_add(1)?;
+                    seen_digit = true;
+                },
+                b'0'..=b'9' => {
+                    if seen_point {
+                        scale = scale.checked_add(pending_zeros)?.checked_add(1)?;
+                        if scale > Self::MAX_SCALE {
+                            return None;
+                        }
+                        mantissa = mantissa.checked_mul(10i64.checked_pow(pending_zeros as u32)?)?;
+                        pending_zeros = 0;
+                    }
+                    // Negative prices are accumulated as such so i64::MIN parses too
+                    let digit = (b - b'0') as i64;
+                    mantissa = match negative {
+                        true => mantissa.checked_mul(10)?.checked_sub(digit)?,
+                        false => mantissa.checked_mul(10)?.checked_add(digit)?,
+                    };
+                    seen_digit = true;
+                },
+                b'.' if !seen_point => seen_point = true,
+                _ => return None,
+            }
+        }
+
+        if !seen_digit {
+            return None;
+        }
+
+        Some(Self { mantissa, scale })
+    }
+
+    // Parses a plain decimal string at exactly `scale` decimals without allocating: shorter fractions
+    // are padded, longer ones only if the extra digits are zeros. A quote finer than the tick size
+    // of its instrument doesn't parse
+    pub fn parse_at(raw: &str, scale: u8) -> Option<Self> {
+        if scale > Self::MAX_SCALE {
+            return None;
+        }
+
+        let bytes = raw.as_bytes();
+        let (negative, digits) = match bytes.first()? {
+            b'-' => (true, &bytes[1..]),
+            _ => (false, bytes),
+        };
+
+        let mut mantissa: i64 = 0;
+        let mut decimals: u8 = 0;
+        let mut seen_digit = false;
+        let mut seen_point = false;
+
+        for &b in digits {
+            match b {
+                b'0'..=b'9' if seen_point && decimals == scale => {
+                    if b != b'0' {
+                        return None;
+                    }
+                    seen_digit = true;
+                },
+                b'0'..=b'9' => {
+                    // Negative prices are accumulated as such so i64::MIN parses too
+                    let digit = (b - b'0') as i64;
+                    mantissa = match negative {
+                        true => mantissa.checked_mul(10)?.checked_sub(digit)?,
+                        false => mantissa.checked_mul(10)?.checked_add(digit)?,
+                    };
+                    if seen_point {
+                        decimals += 1;
+                    }
+                    seen_digit = true;
+                },
+                b'.' if !seen_point => seen_point = true,
+                _ => return None,
+            }
+        }
+
+        if !seen_digit {
+            return None;
+        }
+
+        mantissa = mantissa.checked_mul(10i64.checked_pow((scale - decimals) as u32)?)?;
+        Some(Self { mantissa, scale })
+    }
+
+    pub fn mantissa(&self) -> i64 {
+        self.mantissa
+    }
+
+    pub fn scale(&self) -> u8 {
+        self.scale
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.mantissa < 0
+    }
+
+    // Strips trailing zeros so that equal values always have the same representation
+    fn normalized(mut self) -> Self {
+        while self.scale > 0 && self.mantissa % 10 == 0 {
+            self.mantissa /= 10;
+            self.scale -= 1;
+        }
+        self
+    }
+
+    // Mantissa expressed at a bigger scale, widened so it can't overflow
+    fn widened(&self, scale: u8) -> i128 {
+        self.mantissa as i128 * 10i128.pow((scale - self.scale) as u32)
+    }
+
+    pub fn to_bigdecimal(self) -> BigDecimal {
+        let price = self.normalized();
+        BigDecimal::new(BigInt::from(price.mantissa), price.scale as i64)
+    }
+}
+
+impl PartialEq for Price {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Price {}
+
+impl Hash for Price {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let price = self.normalized();
+        (price.mantissa, price.scale).hash(state);
+    }
+}
+
+impl Ord for Price {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if self.scale == other.scale {
+            return self.mantissa.cmp(&other.mantissa);
+        }
+
+        let scale = self.scale.max(other.scale);
+        self.widened(scale).cmp(&other.widened(scale))
+    }
+}
+
+impl PartialOrd for Price {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl From<Price> for BigDecimal {
+    fn from(price: Price) -> Self {
+        price.to_bigdecimal()
+    }
+}
+
+// Shown without the trailing zeros of the scale, "101213.31000000" is 101213.31
+impl fmt::Display for Price {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let price = self.normalized();
+        if price.scale == 0 {
+            return write!(f, "{}", price.mantissa);
+        }
+
+        let divisor = 10u64.pow(price.scale as u32);
+        let sign = if price.mantissa < 0 { "-" } else { "" };
+        let abs = price.mantissa.unsigned_abs();
+        write!(f, "{}{}.{:0width$}", sign, abs / divisor, abs % divisor, width = price.scale as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Instruments, Orderbook, Price};
+
+    fn price(raw: &str) -> Price {
+        Price::parse(raw).unwrap_or_else(|| panic!("`{}` should parse", raw))
+    }
+
+    #[test]
+    fn parses_signed_decimals() {
+        assert_eq!((price("101213.31").mantissa(), price("101213.31").scale()), (10121331, 2));
+        assert_eq!((price("-0.5").mantissa(), price("-0.5").scale()), (-5, 1));
+        assert!(price("-0.5").is_negative());
+        assert_eq!(price("42"), Price::new(42, 0).unwrap());
+    }
+
+    #[test]
+    fn strips_leading_and_trailing_zeros() {
+        assert_eq!(price("000123.4500"), Price::new(12345, 2).unwrap());
+        assert_eq!(price("0.10000000"), Price::new(1, 1).unwrap());
+        assert_eq!(price("100"), Price::new(100, 0).unwrap());
+        assert_eq!(price("0.000"), Price::new(0, 0).unwrap());
+        assert_eq!(price("-0"), price("0"));
+        // Padding past the max scale is fine as long as it's only zeros
+        assert_eq!(price("1.0000000000000000000000"), Price::new(1, 0).unwrap());
+    }
+
+    #[test]
+    fn accepts_a_point_on_either_side() {
+        assert_eq!(price("1."), Price::new(1, 0).unwrap());
+        assert_eq!(price(".5"), Price::new(5, 1).unwrap());
+    }
+
+    #[test]
+    fn rejects_malformed_quotes() {
+        for raw in ["", "-", ".", "-.", "1.2.3", "+1", "1e5", " 1", "1,5", "--1", "abc"] {
+            assert_eq!(Price::parse(raw), None, "`{}` shouldn't parse", raw);
+        }
+    }
+
+    #[test]
+    fn rejects_mantissa_overflow() {
+        assert_eq!(price("9223372036854775807"), Price::new(i64::MAX, 0).unwrap());
+        assert_eq!(Price::parse("9223372036854775808"), None);
+        assert_eq!(Price::parse("92233720368547758.080"), None);
+        assert_eq!(Price::parse("-99999999999999999999"), None);
+    }
+
+    #[test]
+    fn rejects_scale_over_max() {
+        assert_eq!(price("0.000000000000000001"), Price::new(1, 18).unwrap());
+        assert_eq!(Price::parse("0.0000000000000000001"), None);
+        assert_eq!(Price::new(1, 19), None);
+    }
+
+    #[test]
+    fn orders_across_scales() {
+        assert!(price("1.5") > price("1.49999"));
+        assert!(price("-1.5") < price("-1.49"));
+        assert!(price("100") > price("99.9999999999999999"));
+        assert!(price("0.000000000000000001") > price("0"));
+        assert_eq!(price("2.50").cmp(&price("2.5")), std::cmp::Ordering::Equal);
+        // The widest gap in scale doesn't overflow the comparison
+        assert!(Price::new(i64::MAX, 0).unwrap() > Price::new(i64::MAX, 18).unwrap());
+        assert!(Price::new(i64::MIN, 0).unwrap() < Price::new(i64::MIN, 18).unwrap());
+    }
+
+    #[test]
+    fn equal_values_are_equal_whatever_the_padding() {
+        assert_eq!(price("2.50"), price("2.5"));
+        assert_eq!(price("0002.5"), price("2.500000"));
+        assert_eq!(Price::new(2500, 3), Some(price("2.5")));
+        assert_ne!(price("2.5"), price("25"));
+    }
+
+    #[test]
+    fn displays_what_it_parsed() {
+        for (raw, shown) in [
+            ("101213.31000000", "101213.31"),
+            ("0.00012", "0.00012"),
+            ("-0.5", "-0.5"),
+            ("-12.05", "-12.05"),
+            ("100", "100"),
+            ("1.", "1"),
+            (".5", "0.5"),
+            ("0.000000000000000001", "0.000000000000000001"),
+        ] {
+            assert_eq!(price(raw).to_string(), shown);
+            assert_eq!(price(shown), price(raw), "`{}` doesn't round-trip", shown);
         }
+        assert_eq!(price(&Price::new(i64::MIN, 18).unwrap().to_string()), Price::new(i64::MIN, 18).unwrap());
+    }
+
+    #[test]
+    fn converts_to_bigdecimal() {
+        assert_eq!(price("-12.05").to_bigdecimal().to_string(), "-12.05");
+    }
+
+    #[test]
+    fn parses_at_the_scale_of_the_tick_size() {
+        for (raw, mantissa) in [("101213.31000000", 10121331), ("101213.3", 10121330), ("101241", 10124100), ("-0.5", -50), (".01", 1)] {
+            let parsed = Price::parse_at(raw, 2).unwrap_or_else(|| panic!("`{}` should parse", raw));
+            assert_eq!((parsed.mantissa(), parsed.scale()), (mantissa, 2));
+            assert_eq!(parsed, price(raw));
+        }
+    }
+
+    #[test]
+    fn rejects_quotes_finer_than_the_tick_size() {
+        assert_eq!(Price::parse_at("101213.315", 2), None);
+        assert_eq!(Price::parse_at("0.5", 0), None);
+        assert_eq!(Price::parse_at("92233720368547758.07", 3), None);
+        for raw in ["", "-", ".", "1.2.3", "+1", "1e5", "abc"] {
+            assert_eq!(Price::parse_at(raw, 2), None, "`{}` shouldn't parse", raw);
+        }
+        assert_eq!(Price::parse_at("1", 19), None);
+    }
+
+    #[test]
+    fn shows_prices_without_the_padding_of_their_scale() {
+        let padded = Price::parse_at("2.5", 8).unwrap();
+        assert_eq!(padded.to_string(), "2.5");
+        assert_eq!(padded.to_bigdecimal().to_string(), "2.5");
+    }
+
+    #[test]
+    fn orderbooks_take_the_scale_of_their_instrument() {
+        let mut instruments = Instruments::default();
+        instruments.intern("BTC-USDT", price("0.10000000"));
+
+        let instrument = instruments.get("BTC-USDT").expect("Instrument isn't interned");
+        assert_eq!((&*instrument.symbol, instrument.scale), ("BTCUSDT", 1));
+
+        let orderbook = Orderbook::new("KuCoin", instrument, "101213.3", "101213.40").expect("Invalid orderbook");
+        assert_eq!((orderbook.ask.mantissa(), orderbook.ask.scale()), (1012134, 1));
+        assert!(Orderbook::new("KuCoin", instrument, "101213.3", "101213.45").is_none());
+        assert!(instruments.get("BTCUSDT").is_none());
     }
 }
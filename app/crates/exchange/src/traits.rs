@@ -1,4 +1,4 @@
-use crate::structs::Orderbook;
+use crate::structs::{Instruments, Orderbook};
 use crate::{ReadStream, WriteStream};
 use crate::enums::AnyExchange;
 
@@ -11,13 +11,27 @@ pub trait Exchange: Send {
     fn name(&self) -> &'static str;
 
     // Returns the url of exchange's websocket stream
-    fn url(&self) -> &'static str;
+    fn url(&self) -> &str;
+
+    // Returns the root url of the exchange's REST api, which lists the tick sizes of its markets
+    fn rest_url(&self) -> &str;
+
+    // Returns the url of the REST endpoint handing out websocket tokens, for exchanges that need one
+    fn token_url(&self) -> Option<&str> {
+        None
+    }
 
     // Returns a vector of orderbooks parsed from websocket server message
     fn get_type(&self) -> &AnyExchange;
 
+    // Updates of markets that aren't interned in instruments() are dropped
     fn parse_orderbook_data(&self, raw_data: &HashMap<String, Value>) -> Option<Orderbook>;
 
+    // Markets of the connection by the symbol their updates carry, with the scale of their tick size
+    fn instruments(&self) -> &Instruments;
+
+    fn instruments_mut(&mut self) -> &mut Instruments;
+
     // Getters and setters for r/w streams fields
     fn read_stream(&mut self) -> &mut Option<ReadStream>;
 
@@ -1,11 +1,13 @@
 use std::collections::HashMap;
 use anyhow::bail;
 use serde_json::Value;
+use tracing::debug;
 
 // Returns the token required for Websocket to establish a Spot/Margin connection
-pub async fn get_public_token_kucoin() -> anyhow::Result<String> {
+pub async fn get_public_token_kucoin(token_url: &str) -> anyhow::Result<String> {
+    debug!(token_url, "Requesting websocket token");
     let client = reqwest::Client::new();
-    let response = client.post("https://api.kucoin.com/api/v1/bullet-public")
+    let response = client.post(token_url)
         .send()
         .await?
         .text()
@@ -13,14 +15,13 @@ pub async fn get_public_token_kucoin() -> anyhow::Result<String> {
 
     let map = serde_json::from_str::<HashMap<String, Value>>(&response)?;
 
-    if let Some(data) = map.get("data") {
-
-        if let Some(data_map) = data.as_object() {
-
-            if let Some(token) = data_map.get("token") {
-                return Ok(token.as_str().unwrap().to_string());
-            }
-        }
+    if let Some(token) = map
+        .get("data")
+        .and_then(|data| data.as_object())
+        .and_then(|data_map| data_map.get("token"))
+        .and_then(|token| token.as_str())
+    {
+        return Ok(token.to_string());
     }
 
     bail!("No token found in the response");
@@ -0,0 +1,690 @@
+// Local stand-ins for the exchange servers, speaking just enough of each venue's protocol
+// (subscribe, heartbeat and best bid/ask data) to run the engine end to end without network,
+// and serving the tick sizes and klines of their REST apis
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::extract::{Path, Query};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response as HttpResponse};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, Notify};
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::{Bytes, Message};
+use tokio_util::sync::CancellationToken;
+
+// Token the KuCoin mock hands out and expects back on the websocket url
+pub const KUCOIN_TOKEN: &str = "mock-token";
+
+// How often the Binance mock pings its clients, like the real server does
+const SERVER_PING_INTERVAL: Duration = Duration::from_millis(500);
+
+// Tick size of the markets without one set, fine enough for any quote of the tests
+const DEFAULT_TICK_SIZE: &str = "0.00000001";
+
+// Most klines KuCoin returns for one request, Binance and ByBit take a `limit`
+const KUCOIN_KLINE_LIMIT: usize = 1500;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Venue {
+    Binance,
+    ByBit,
+    KuCoin,
+}
+
+#[derive(Clone, Debug)]
+struct Quote {
+    market: String,
+    bid: String,
+    ask: String,
+}
+
+// Frame sent to the connections
+#[derive(Clone, Debug)]
+enum Outbound {
+    // To the connections subscribed to its market, in the venue's format
+    Quote(Quote),
+    // To every connection as is
+    Raw(String),
+}
+
+#[derive(Default)]
+struct State {
+    // Markets subscribed over all connections, uppercased, in subscription order
+    subscriptions: Mutex<Vec<String>>,
+    // Markets unsubscribed over all connections, uppercased, in order
+    unsubscriptions: Mutex<Vec<String>>,
+    connections: AtomicUsize,
+    token_requests: AtomicUsize,
+    // Heartbeats sent by clients, websocket pings or the venue's JSON ping
+    pings: AtomicUsize,
+    // Pongs answering the server's pings
+    pongs: AtomicUsize,
+    // Close frames sent by clients
+    closed: AtomicUsize,
+    // Open, high, low and close by open time in milliseconds, by uppercased market
+    klines: Mutex<HashMap<String, BTreeMap<i64, [String; 4]>>>,
+    kline_requests: AtomicUsize,
+    // Tick sizes set by the tests, by uppercased market
+    tick_sizes: Mutex<HashMap<String, String>>,
+    instrument_requests: AtomicUsize,
+    // Kline requests still to be refused for exceeding the rate limit
+    rate_limited: AtomicUsize,
+    changed: Notify,
+}
+
+pub struct MockExchange {
+    venue: Venue,
+    url: String,
+    rest_url: String,
+    token_url: Option<String>,
+    state: Arc<State>,
+    quotes: broadcast::Sender<Outbound>,
+    disconnect: broadcast::Sender<()>,
+    shutdown: CancellationToken,
+}
+
+impl MockExchange {
+    pub async fn binance() -> Self {
+        Self::start(Venue::Binance).await
+    }
+
+    pub async fn bybit() -> Self {
+        Self::start(Venue::ByBit).await
+    }
+
+    pub async fn kucoin() -> Self {
+        Self::start(Venue::KuCoin).await
+    }
+
+    pub async fn start(venue: Venue) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("Error binding mock exchange");
+        let address = listener.local_addr().expect("Error reading mock exchange address");
+        let url = match venue {
+            Venue::Binance => format!("ws://{}/stream", address),
+            Venue::ByBit => format!("ws://{}/v5/public/spot", address),
+            Venue::KuCoin => format!("ws://{}/", address),
+        };
+
+        let state = Arc::new(State::default());
+        let (quotes, _) = broadcast::channel(1024);
+        let (disconnect, _) = broadcast::channel(16);
+        let shutdown = CancellationToken::new();
+
+        let rest_url = Self::serve_rest(venue, url.clone(), state.clone(), shutdown.clone()).await;
+        let token_url = match venue {
+            Venue::KuCoin => Some(format!("{}/api/v1/bullet-public", rest_url)),
+            _ => None,
+        };
+
+        tokio::spawn(Self::accept(
+            listener,
+            venue,
+            state.clone(),
+            quotes.clone(),
+            disconnect.clone(),
+            shutdown.clone(),
+        ));
+
+        Self {
+            venue,
+            url,
+            rest_url,
+            token_url,
+            state,
+            quotes,
+            disconnect,
+            shutdown,
+        }
+    }
+
+    pub fn venue(&self) -> Venue {
+        self.venue
+    }
+
+    // Websocket url to connect to
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    // Root of the REST api serving the klines
+    pub fn rest_url(&self) -> &str {
+        &self.rest_url
+    }
+
+    // REST url handing out websocket tokens, only the KuCoin mock has one
+    pub fn token_url(&self) -> &str {
+        self.token_url.as_deref().expect("Only the KuCoin mock serves tokens")
+    }
+
+    // Sends a best bid/ask update to every connection subscribed to `market`, case-insensitive
+    pub fn send_quote(&self, market: &str, bid: &str, ask: &str) {
+        let _ = self.quotes.send(Outbound::Quote(Quote {
+            market: market.to_uppercase(),
+            bid: bid.to_string(),
+            ask: ask.to_string(),
+        }));
+    }
+
+    // Sends `text` as is to every connection, e.g. a frame that isn't JSON
+    pub fn send_raw(&self, text: &str) {
+        let _ = self.quotes.send(Outbound::Raw(text.to_string()));
+    }
+
+    // Adds the kline of `market` opened at `open_time` (milliseconds) as open, high, low and close
+    pub fn add_kline(&self, market: &str, open_time: i64, prices: [&str; 4]) {
+        self.state.klines
+            .lock()
+            .expect("Klines lock poisoned")
+            .entry(market.to_uppercase())
+            .or_default()
+            .insert(open_time, prices.map(|price| price.to_string()));
+    }
+
+    // Lists `market` with `tick_size` instead of DEFAULT_TICK_SIZE, quotes finer than that are dropped
+    pub fn set_tick_size(&self, market: &str, tick_size: &str) {
+        self.state.tick_sizes
+            .lock()
+            .expect("Tick sizes lock poisoned")
+            .insert(market.to_uppercase(), tick_size.to_string());
+    }
+
+    pub fn instrument_requests(&self) -> usize {
+        self.state.instrument_requests.load(Ordering::SeqCst)
+    }
+
+    // Refuses the next `count` kline requests for exceeding the rate limit
+    pub fn rate_limit_next(&self, count: usize) {
+        self.state.rate_limited.store(count, Ordering::SeqCst);
+    }
+
+    pub fn kline_requests(&self) -> usize {
+        self.state.kline_requests.load(Ordering::SeqCst)
+    }
+
+    // Sends a Close frame to every connected client
+    pub fn disconnect_all(&self) {
+        let _ = self.disconnect.send(());
+    }
+
+    pub fn subscriptions(&self) -> Vec<String> {
+        self.state.subscriptions.lock().expect("Subscriptions lock poisoned").clone()
+    }
+
+    pub fn unsubscriptions(&self) -> Vec<String> {
+        self.state.unsubscriptions.lock().expect("Unsubscriptions lock poisoned").clone()
+    }
+
+    pub fn connections(&self) -> usize {
+        self.state.connections.load(Ordering::SeqCst)
+    }
+
+    pub fn token_requests(&self) -> usize {
+        self.state.token_requests.load(Ordering::SeqCst)
+    }
+
+    pub fn pings(&self) -> usize {
+        self.state.pings.load(Ordering::SeqCst)
+    }
+
+    pub fn pongs(&self) -> usize {
+        self.state.pongs.load(Ordering::SeqCst)
+    }
+
+    pub fn closed(&self) -> usize {
+        self.state.closed.load(Ordering::SeqCst)
+    }
+
+    // Waits until `condition` holds, checked after every change of the mock's state
+    pub async fn wait_until(&self, condition: impl Fn(&MockExchange) -> bool) {
+        loop {
+            let changed = self.state.changed.notified();
+            if condition(self) {
+                return;
+            }
+            changed.await;
+        }
+    }
+
+    // Waits until `count` markets were subscribed over all connections
+    pub async fn wait_for_subscriptions(&self, count: usize) {
+        self.wait_until(|mock| mock.subscriptions().len() >= count).await
+    }
+
+    // Serves the venue's instruments and kline endpoints and KuCoin's websocket tokens, returns the root url
+    async fn serve_rest(venue: Venue, ws_url: String, state: Arc<State>, shutdown: CancellationToken) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("Error binding mock REST server");
+        let address = listener.local_addr().expect("Error reading mock REST server address");
+
+        let path = match venue {
+            Venue::Binance => "/api/v3/klines",
+            Venue::ByBit => "/v5/market/kline",
+            Venue::KuCoin => "/api/v1/market/candles",
+        };
+        let klines_state = state.clone();
+        let mut app = Router::new().route(path, get(move |Query(query): Query<HashMap<String, String>>| {
+            let response = klines_response(venue, &klines_state, &query);
+            async move { response }
+        }));
+
+        let instruments_state = state.clone();
+        app = match venue {
+            Venue::Binance => app.route("/api/v3/exchangeInfo", get(move |Query(query): Query<HashMap<String, String>>| {
+                let symbols = query.get("symbols")
+                    .and_then(|symbols| serde_json::from_str::<Vec<String>>(symbols).ok())
+                    .unwrap_or_default();
+                let response = instruments_response(venue, &instruments_state, &symbols);
+                async move { Json(response) }
+            })),
+            Venue::ByBit => app.route("/v5/market/instruments-info", get(move |Query(query): Query<HashMap<String, String>>| {
+                let symbols = query.get("symbol").cloned().into_iter().collect::<Vec<_>>();
+                let response = instruments_response(venue, &instruments_state, &symbols);
+                async move { Json(response) }
+            })),
+            Venue::KuCoin => app.route("/api/v2/symbols/{symbol}", get(move |Path(symbol): Path<String>| {
+                let response = instruments_response(venue, &instruments_state, &[symbol]);
+                async move { Json(response) }
+            })),
+        };
+        if venue == Venue::KuCoin {
+            app = app.route("/api/v1/bullet-public", post(move || {
+                state.token_requests.fetch_add(1, Ordering::SeqCst);
+                state.changed.notify_waiters();
+
+                let response = token_response(&ws_url);
+                async move { Json(response) }
+            }));
+        }
+
+        tokio::spawn(async move {
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown.cancelled_owned())
+                .await
+                .expect("Error serving mock REST api");
+        });
+
+        format!("http://{}", address)
+    }
+
+    async fn accept(
+        listener: TcpListener,
+        venue: Venue,
+        state: Arc<State>,
+        quotes: broadcast::Sender<Outbound>,
+        disconnect: broadcast::Sender<()>,
+        shutdown: CancellationToken,
+    ) {
+        loop {
+            let stream = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(_) => continue,
+                },
+                _ = shutdown.cancelled() => return,
+            };
+
+            let connection = Connection {
+                venue,
+                state: state.clone(),
+                quotes: quotes.subscribe(),
+                disconnect: disconnect.subscribe(),
+                shutdown: shutdown.clone(),
+                markets: HashSet::new(),
+            };
+            tokio::spawn(connection.serve(stream));
+        }
+    }
+}
+
+impl Drop for MockExchange {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
+}
+
+struct Connection {
+    venue: Venue,
+    state: Arc<State>,
+    quotes: broadcast::Receiver<Outbound>,
+    disconnect: broadcast::Receiver<()>,
+    shutdown: CancellationToken,
+    // Markets this connection subscribed to
+    markets: HashSet<String>,
+}
+
+impl Connection {
+    // The handshake callback's error type is set by tungstenite
+    #[allow(clippy::result_large_err)]
+    async fn serve(mut self, stream: TcpStream) {
+        let mut query = None;
+        let handshake = tokio_tungstenite::accept_hdr_async(stream, |request: &Request, response: Response| {
+            query = request.uri().query().map(|query| query.to_string());
+            Ok(response)
+        }).await;
+        let Ok(ws_stream) = handshake else {
+            return;
+        };
+        let (mut write_stream, mut read_stream) = ws_stream.split();
+
+        self.state.connections.fetch_add(1, Ordering::SeqCst);
+        self.state.changed.notify_waiters();
+
+        match self.venue {
+            // Binance subscribes through the url: /stream?streams=btcusdt@bookTicker/ethusdt@bookTicker
+            Venue::Binance => {
+                let streams = query_param(query.as_deref(), "streams").unwrap_or_default();
+                self.subscribe(streams.split('/').map(|stream| stream.trim_end_matches("@bookTicker")));
+            },
+            // KuCoin expects the token from the REST endpoint and greets the client
+            Venue::KuCoin => {
+                if query_param(query.as_deref(), "token").as_deref() != Some(KUCOIN_TOKEN) {
+                    let _ = write_stream.close().await;
+                    return;
+                }
+                let welcome = json!({"id": "welcome", "type": "welcome"});
+                if write_stream.send(Message::text(welcome.to_string())).await.is_err() {
+                    return;
+                }
+            },
+            Venue::ByBit => {}
+        }
+
+        let mut ping = tokio::time::interval(SERVER_PING_INTERVAL);
+        loop {
+            tokio::select! {
+                message = read_stream.next() => {
+                    let reply = match message {
+                        Some(Ok(Message::Text(text))) => self.handle_text(&text),
+                        Some(Ok(Message::Ping(_))) => {
+                            // tungstenite answers the ping by itself
+                            self.count(&self.state.pings);
+                            None
+                        },
+                        Some(Ok(Message::Pong(_))) => {
+                            self.count(&self.state.pongs);
+                            None
+                        },
+                        Some(Ok(Message::Close(_))) => {
+                            self.count(&self.state.closed);
+                            return;
+                        },
+                        Some(Ok(_)) => None,
+                        Some(Err(_)) | None => return,
+                    };
+
+                    if let Some(reply) = reply
+                        && write_stream.send(Message::text(reply.to_string())).await.is_err()
+                    {
+                        return;
+                    }
+                },
+                outbound = self.quotes.recv() => {
+                    let text = match outbound {
+                        Ok(Outbound::Quote(quote)) if self.markets.contains(&quote.market) => self.format_quote(&quote).to_string(),
+                        Ok(Outbound::Raw(text)) => text,
+                        _ => continue,
+                    };
+                    if write_stream.send(Message::text(text)).await.is_err() {
+                        return;
+                    }
+                },
+                _ = ping.tick(), if self.venue == Venue::Binance => {
+                    if write_stream.send(Message::Ping(Bytes::new())).await.is_err() {
+                        return;
+                    }
+                },
+                _ = self.disconnect.recv() => {
+                    let _ = write_stream.close().await;
+                    return;
+                },
+                _ = self.shutdown.cancelled() => return,
+            }
+        }
+    }
+
+    // Handles a client request, returns the reply to send if any
+    fn handle_text(&mut self, text: &str) -> Option<Value> {
+        let request = serde_json::from_str::<Value>(text).ok()?;
+
+        match self.venue {
+            // Live changes to the url streams: {"method": "SUBSCRIBE", "params": ["btcusdt@bookTicker"], "id": 1}
+            Venue::Binance => {
+                let streams = request.get("params")?.as_array()?;
+                let markets = streams.iter().filter_map(|stream| stream.as_str()?.strip_suffix("@bookTicker"));
+                match request.get("method")?.as_str()? {
+                    "SUBSCRIBE" => self.subscribe(markets),
+                    "UNSUBSCRIBE" => self.unsubscribe(markets),
+                    _ => return None,
+                }
+                Some(json!({"result": null, "id": request.get("id").cloned().unwrap_or(Value::Null)}))
+            },
+            // {"op": "subscribe", "args": ["orderbook.1.BTCUSDT"]}, the same with "unsubscribe" and {"op": "ping"}
+            Venue::ByBit => match request.get("op")?.as_str()? {
+                op @ ("subscribe" | "unsubscribe") => {
+                    let topics = request.get("args")?.as_array()?;
+                    let markets = topics.iter().filter_map(|topic| topic.as_str()?.strip_prefix("orderbook.1."));
+                    match op {
+                        "subscribe" => self.subscribe(markets),
+                        _ => self.unsubscribe(markets),
+                    }
+                    Some(json!({"success": true, "ret_msg": "", "conn_id": "mock", "op": op}))
+                },
+                "ping" => {
+                    self.count(&self.state.pings);
+                    Some(json!({"success": true, "ret_msg": "pong", "conn_id": "mock", "op": "ping"}))
+                },
+                _ => None,
+            },
+            // {"id": 1, "type": "subscribe", "topic": "/spotMarket/level1:BTC-USDT,ETH-USDT", "response": true}
+            // the same with "unsubscribe" and {"id": "1", "type": "ping"}
+            Venue::KuCoin => {
+                let id = request.get("id").cloned().unwrap_or(Value::Null);
+                match request.get("type")?.as_str()? {
+                    kind @ ("subscribe" | "unsubscribe") => {
+                        let markets = request.get("topic")?.as_str()?.strip_prefix("/spotMarket/level1:")?;
+                        match kind {
+                            "subscribe" => self.subscribe(markets.split(',')),
+                            _ => self.unsubscribe(markets.split(',')),
+                        }
+                        request
+                            .get("response")
+                            .and_then(|response| response.as_bool())
+                            .filter(|response| *response)
+                            .map(|_| json!({"id": id, "type": "ack"}))
+                    },
+                    "ping" => {
+                        self.count(&self.state.pings);
+                        Some(json!({"id": id, "type": "pong"}))
+                    },
+                    _ => None,
+                }
+            },
+        }
+    }
+
+    fn format_quote(&self, quote: &Quote) -> Value {
+        match self.venue {
+            Venue::Binance => json!({
+                "stream": format!("{}@bookTicker", quote.market.to_lowercase()),
+                "data": {
+                    "u": 1,
+                    "s": quote.market,
+                    "b": quote.bid,
+                    "B": "1",
+                    "a": quote.ask,
+                    "A": "1"
+                }
+            }),
+            Venue::ByBit => json!({
+                "topic": format!("orderbook.1.{}", quote.market),
+                "type": "snapshot",
+                "ts": 0,
+                "data": {
+                    "s": quote.market,
+                    "b": [[quote.bid, "1"]],
+                    "a": [[quote.ask, "1"]],
+                    "u": 1,
+                    "seq": 1
+                }
+            }),
+            Venue::KuCoin => json!({
+                "type": "message",
+                "topic": format!("/spotMarket/level1:{}", quote.market),
+                "subject": "level1",
+                "data": {
+                    "asks": [quote.ask, "1"],
+                    "bids": [quote.bid, "1"],
+                    "timestamp": 0
+                }
+            }),
+        }
+    }
+
+    fn subscribe<'a>(&mut self, markets: impl Iterator<Item = &'a str>) {
+        let mut subscriptions = self.state.subscriptions.lock().expect("Subscriptions lock poisoned");
+        for market in markets.filter(|market| !market.is_empty()) {
+            let market = market.to_uppercase();
+            subscriptions.push(market.clone());
+            self.markets.insert(market);
+        }
+        drop(subscriptions);
+
+        self.state.changed.notify_waiters();
+    }
+
+    fn unsubscribe<'a>(&mut self, markets: impl Iterator<Item = &'a str>) {
+        let mut unsubscriptions = self.state.unsubscriptions.lock().expect("Unsubscriptions lock poisoned");
+        for market in markets.filter(|market| !market.is_empty()) {
+            let market = market.to_uppercase();
+            self.markets.remove(&market);
+            unsubscriptions.push(market);
+        }
+        drop(unsubscriptions);
+
+        self.state.changed.notify_waiters();
+    }
+
+    fn count(&self, counter: &AtomicUsize) {
+        counter.fetch_add(1, Ordering::SeqCst);
+        self.state.changed.notify_waiters();
+    }
+}
+
+// Body of KuCoin's bullet-public response
+fn token_response(ws_url: &str) -> Value {
+    json!({
+        "code": "200000",
+        "data": {
+            "token": KUCOIN_TOKEN,
+            "instanceServers": [{
+                "endpoint": ws_url,
+                "encrypt": false,
+                "protocol": "websocket",
+                "pingInterval": 18000,
+                "pingTimeout": 10000
+            }]
+        }
+    })
+}
+
+// Tick sizes of the requested markets in the venue's format, every market is listed
+fn instruments_response(venue: Venue, state: &State, symbols: &[String]) -> Value {
+    state.instrument_requests.fetch_add(1, Ordering::SeqCst);
+    state.changed.notify_waiters();
+
+    let tick_sizes = state.tick_sizes.lock().expect("Tick sizes lock poisoned");
+    let tick_size = |symbol: &String| tick_sizes.get(&symbol.to_uppercase()).map(|tick_size| tick_size.as_str()).unwrap_or(DEFAULT_TICK_SIZE);
+
+    match venue {
+        Venue::Binance => json!({
+            "symbols": symbols.iter().map(|symbol| json!({
+                "symbol": symbol,
+                "filters": [{"filterType": "PRICE_FILTER", "minPrice": tick_size(symbol), "tickSize": tick_size(symbol)}]
+            })).collect::<Vec<_>>()
+        }),
+        Venue::ByBit => json!({
+            "retCode": 0,
+            "retMsg": "OK",
+            "result": {
+                "category": "spot",
+                "list": symbols.iter().map(|symbol| json!({
+                    "symbol": symbol,
+                    "priceFilter": {"tickSize": tick_size(symbol)}
+                })).collect::<Vec<_>>()
+            }
+        }),
+        Venue::KuCoin => json!({
+            "code": "200000",
+            "data": symbols.first().map(|symbol| json!({"symbol": symbol, "priceIncrement": tick_size(symbol)}))
+        }),
+    }
+}
+
+// Klines of the requested market and range in the venue's format, or its rate limit error.
+// Binance lists them oldest first, ByBit and KuCoin newest first, each up to its page size
+fn klines_response(venue: Venue, state: &State, query: &HashMap<String, String>) -> HttpResponse {
+    state.kline_requests.fetch_add(1, Ordering::SeqCst);
+    state.changed.notify_waiters();
+
+    let limited = state.rate_limited
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |remaining| remaining.checked_sub(1))
+        .is_ok();
+    if limited {
+        return match venue {
+            Venue::Binance => (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, "1")], "Too many requests").into_response(),
+            Venue::ByBit => Json(json!({"retCode": 10006, "retMsg": "Too many visits!", "result": {}})).into_response(),
+            Venue::KuCoin => (StatusCode::TOO_MANY_REQUESTS, Json(json!({"code": "429000", "msg": "Too Many Requests"}))).into_response(),
+        };
+    }
+
+    let number = |key: &str| query.get(key).and_then(|value| value.parse::<i64>().ok());
+    let symbol = query.get("symbol").map(|symbol| symbol.to_uppercase()).unwrap_or_default();
+    let (start, end, limit) = match venue {
+        Venue::Binance => (number("startTime"), number("endTime"), number("limit")),
+        Venue::ByBit => (number("start"), number("end"), number("limit")),
+        // Seconds, both ends included
+        Venue::KuCoin => (number("startAt").map(|start| start * 1000), number("endAt").map(|end| end * 1000), None),
+    };
+    let limit = limit.map(|limit| limit as usize).unwrap_or(KUCOIN_KLINE_LIMIT);
+
+    let klines = state.klines.lock().expect("Klines lock poisoned");
+    let in_range = klines.get(&symbol)
+        .map(|klines| klines.range(start.unwrap_or(i64::MIN)..=end.unwrap_or(i64::MAX)).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    match venue {
+        Venue::Binance => {
+            let rows = in_range.iter().take(limit).map(|(time, [open, high, low, close])| json!([
+                time, open, high, low, close, "1", **time + 59_999, "1", 1, "1", "1", "0"
+            ])).collect::<Vec<_>>();
+            Json(Value::Array(rows)).into_response()
+        },
+        Venue::ByBit => {
+            let rows = in_range.iter().rev().take(limit).map(|(time, [open, high, low, close])| json!([
+                time.to_string(), open, high, low, close, "1", "1"
+            ])).collect::<Vec<_>>();
+            Json(json!({"retCode": 0, "retMsg": "OK", "result": {"category": "spot", "symbol": symbol, "list": rows}})).into_response()
+        },
+        Venue::KuCoin => {
+            let rows = in_range.iter().rev().take(limit).map(|(time, [open, high, low, close])| json!([
+                (**time / 1000).to_string(), open, close, high, low, "1", "1"
+            ])).collect::<Vec<_>>();
+            Json(json!({"code": "200000", "data": rows})).into_response()
+        },
+    }
+}
+
+fn query_param(query: Option<&str>, name: &str) -> Option<String> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.replace("%2F", "/").replace("%40", "@").replace("%2C", ","))
+}
@@ -0,0 +1,349 @@
+use crate::queue::{FeedId, FeedStats, FeedStatsSnapshot, Feeds, OverloadPolicy, ShardCommand, ShardQueue, Tick};
+use crate::structs::{ClosedBar, OLHC};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, RwLock};
+
+use chrono::NaiveDateTime;
+use exchange::structs::{market_symbol, Orderbook};
+use tokio::sync::oneshot;
+
+// Interned (exchange, market) pair, cheap to hash and copy around
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct InstrumentId(pub(crate) u32);
+
+#[derive(Clone, Debug)]
+pub struct Instrument {
+    pub exchange: &'static str,
+    pub market: Arc<str>,
+}
+
+// Registry of all instruments seen so far. Ids are never reused, lookups by id are an index.
+// Markets are keyed per exchange so a lookup borrows the &str and only a new instrument allocates
+#[derive(Default)]
+struct Instruments {
+    ids: HashMap<&'static str, HashMap<Arc<str>, InstrumentId>>,
+    list: Vec<Instrument>,
+}
+
+impl Instruments {
+    fn get(&self, exchange: &'static str, market: &str) -> Option<InstrumentId> {
+        self.ids.get(exchange)?.get(market).copied()
+    }
+
+    fn intern(&mut self, exchange: &'static str, market: &str) -> InstrumentId {
+        if let Some(id) = self.get(exchange, market) {
+            return id;
+        }
+
+        let id = InstrumentId(self.list.len() as u32);
+        let market: Arc<str> = Arc::from(market);
+        self.ids.entry(exchange).or_default().insert(market.clone(), id);
+        self.list.push(Instrument { exchange, market });
+        id
+    }
+}
+
+type Flush = oneshot::Sender<Vec<(InstrumentId, OLHC)>>;
+
+// Routes ticks to the shard that owns the instrument and collects closed bars from all shards
+#[derive(Clone)]
+pub struct Aggregator {
+    shards: Arc<Vec<Arc<ShardQueue<Flush>>>>,
+    instruments: Arc<RwLock<Instruments>>,
+    feeds: Feeds,
+    // Markets the feeds subscribed to, tracked for gaps before their first update
+    expected: Arc<RwLock<HashSet<InstrumentId>>>,
+    // Markets removed from the config while running, their silent minutes aren't gaps
+    retired: Arc<RwLock<HashSet<InstrumentId>>>,
+    // Markets whose feed lost its connection during the minute being built
+    interrupted: Arc<Mutex<HashSet<InstrumentId>>>,
+}
+
+impl Aggregator {
+    // Each shard buffers at most `capacity` ticks, `policy` decides what happens beyond that
+    pub fn new(shards_count: usize, capacity: usize, policy: OverloadPolicy) -> Self {
+        let feeds = Feeds::default();
+        let shards = (0..shards_count.max(1))
+            .map(|_| {
+                let queue = Arc::new(ShardQueue::new(capacity, policy, feeds.clone()));
+                tokio::spawn(Self::run_shard(queue.clone()));
+                queue
+            })
+            .collect::<Vec<_>>();
+
+        Self {
+            shards: Arc::new(shards),
+            instruments: Arc::new(RwLock::new(Instruments::default())),
+            feeds,
+            expected: Arc::default(),
+            retired: Arc::default(),
+            interrupted: Arc::default(),
+        }
+    }
+
+    // Returns a publisher for one exchange feed, it caches instrument ids locally
+    // so the registry lock is only taken the first time a market is seen
+    pub fn publisher(&self, exchange: &'static str) -> Publisher {
+        let stats = Arc::new(FeedStats::default());
+        let feed = {
+            let mut feeds = self.feeds.write().expect("Feeds lock poisoned");
+            feeds.push((exchange, stats.clone()));
+            feeds.len() - 1
+        };
+
+        Publisher {
+            exchange,
+            feed,
+            stats,
+            ids: HashMap::new(),
+            aggregator: self.clone(),
+        }
+    }
+
+    pub fn feed_stats(&self) -> Vec<(&'static str, FeedStatsSnapshot)> {
+        self.feeds.read().expect("Feeds lock poisoned")
+            .iter()
+            .map(|(exchange, stats)| (*exchange, stats.snapshot()))
+            .collect()
+    }
+
+    // Number of ticks waiting in every shard
+    pub fn queue_depth(&self) -> usize {
+        self.shards.iter().map(|shard| shard.len()).sum()
+    }
+
+    pub fn instrument(&self, id: InstrumentId) -> Instrument {
+        self.instruments.read().expect("Instruments lock poisoned").list[id.0 as usize].clone()
+    }
+
+    // Marks a market as subscribed, `market` is the symbol its updates carry
+    pub fn expect(&self, exchange: &'static str, market: &str) {
+        let id = self.intern(exchange, market);
+        self.expected.write().expect("Expected lock poisoned").insert(id);
+    }
+
+    // Subscribed markets, whether or not they sent an update yet
+    pub fn expected(&self) -> Vec<(InstrumentId, Instrument)> {
+        let expected = self.expected.read().expect("Expected lock poisoned");
+        let instruments = self.instruments.read().expect("Instruments lock poisoned");
+        expected.iter().map(|id| (*id, instruments.list[id.0 as usize].clone())).collect()
+    }
+
+    // Marks a market as no longer scraped, `market` is the symbol its updates carried
+    pub fn retire(&self, exchange: &'static str, market: &str) {
+        let id = self.intern(exchange, market);
+        self.expected.write().expect("Expected lock poisoned").remove(&id);
+        self.retired.write().expect("Retired lock poisoned").insert(id);
+    }
+
+    // Undoes `retire` for a market that's scraped again
+    pub fn reinstate(&self, exchange: &'static str, market: &str) {
+        let id = self.intern(exchange, market);
+        self.expected.write().expect("Expected lock poisoned").insert(id);
+        self.retired.write().expect("Retired lock poisoned").remove(&id);
+    }
+
+    // Marks the bars of the current minute as partial, their feed missed part of it.
+    // `market` is the symbol the updates carry
+    pub fn interrupt(&self, exchange: &'static str, market: &str) {
+        let id = self.intern(exchange, market);
+        self.interrupted.lock().expect("Interrupted lock poisoned").insert(id);
+    }
+
+    pub fn retired(&self) -> HashSet<InstrumentId> {
+        self.retired.read().expect("Retired lock poisoned").clone()
+    }
+
+    // Only takes the write lock for an instrument it hasn't seen yet
+    fn intern(&self, exchange: &'static str, market: &str) -> InstrumentId {
+        if let Some(id) = self.instruments.read().expect("Instruments lock poisoned").get(exchange, market) {
+            return id;
+        }
+        self.instruments.write().expect("Instruments lock poisoned").intern(exchange, market)
+    }
+
+    fn shard(&self, id: InstrumentId) -> &ShardQueue<Flush> {
+        &self.shards[id.0 as usize % self.shards.len()]
+    }
+
+    // Takes the bars built so far out of every shard, each shard starts a new bar afterwards.
+    // `timestamp` is the start of the minute the bars cover
+    pub async fn flush(&self, timestamp: NaiveDateTime) -> Vec<ClosedBar> {
+        let interrupted = std::mem::take(&mut *self.interrupted.lock().expect("Interrupted lock poisoned"));
+        let mut replies = Vec::with_capacity(self.shards.len());
+        for shard in self.shards.iter() {
+            let (tx, rx) = oneshot::channel();
+            if shard.push_control(tx) {
+                replies.push(rx);
+            }
+        }
+
+        let mut bars = Vec::new();
+        for reply in replies {
+            if let Ok(shard_bars) = reply.await {
+                bars.extend(shard_bars.into_iter().map(|(id, olhc)| {
+                    let instrument = self.instrument(id);
+                    ClosedBar {
+                        instrument: id,
+                        exchange: instrument.exchange,
+                        market: instrument.market,
+                        timestamp,
+                        olhc,
+                        partial: interrupted.contains(&id),
+                        synthetic: false,
+                    }
+                }));
+            }
+        }
+
+        bars
+    }
+
+    async fn run_shard(queue: Arc<ShardQueue<Flush>>) {
+        let mut bars = HashMap::<InstrumentId, OLHC>::new();
+
+        while let Some(command) = queue.pop().await {
+            match command {
+                ShardCommand::Tick(tick) => {
+                    bars.entry(tick.instrument)
+                        .and_modify(|olhc| olhc.update(tick.price))
+                        .or_insert_with(|| OLHC::new(tick.price));
+                },
+                ShardCommand::Control(reply) => {
+                    let _ = reply.send(bars.drain().collect());
+                }
+            }
+        }
+    }
+}
+
+pub struct Publisher {
+    exchange: &'static str,
+    feed: FeedId,
+    stats: Arc<FeedStats>,
+    ids: HashMap<Arc<str>, InstrumentId>,
+    aggregator: Aggregator,
+}
+
+impl Publisher {
+    // Marks the bars the feed's `markets` build this minute as partial, e.g. after a disconnect
+    pub fn interrupt(&self, markets: &[String]) {
+        for market in markets {
+            self.aggregator.interrupt(self.exchange, &market_symbol(market));
+        }
+    }
+
+    // Returns false once the aggregator is gone
+    pub async fn publish(&mut self, orderbook: Orderbook) -> bool {
+        self.stats.received.fetch_add(1, Ordering::Relaxed);
+        let price = orderbook.ask;
+
+        if price.is_negative() {
+            return true;
+        }
+
+        let id = match self.ids.get(&orderbook.symbol) {
+            Some(id) => *id,
+            None => {
+                let id = self.aggregator.intern(self.exchange, &orderbook.symbol);
+                self.ids.insert(orderbook.symbol, id);
+                id
+            }
+        };
+
+        let tick = Tick {
+            instrument: id,
+            price,
+            feed: self.feed,
+        };
+        self.aggregator.shard(id).push_tick(tick, &self.stats).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Aggregator;
+    use crate::queue::OverloadPolicy;
+
+    use std::collections::HashSet;
+
+    use chrono::DateTime;
+    use exchange::structs::{Instrument, Orderbook};
+
+    const MARKETS: [&str; 8] = ["BTCUSDT", "ETHUSDT", "SOLUSDT", "XRPUSDT", "ADAUSDT", "DOGEUSDT", "BNBUSDT", "TRXUSDT"];
+
+    #[tokio::test]
+    async fn instruments_stay_on_their_shard() {
+        let aggregator = Aggregator::new(3, 100, OverloadPolicy::Block);
+
+        let ids = MARKETS.map(|market| aggregator.intern("Binance", market));
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), MARKETS.len(), "Ids aren't unique");
+        for (market, id) in MARKETS.iter().zip(ids) {
+            assert_eq!(aggregator.intern("Binance", market), id, "{} got another id", market);
+            assert!(std::ptr::eq(aggregator.shard(id), aggregator.shard(aggregator.intern("Binance", market))));
+            assert!(std::ptr::eq(aggregator.shard(id), &*aggregator.shards[id.0 as usize % 3]));
+        }
+
+        // The same market on another exchange is another instrument
+        assert!(!ids.contains(&aggregator.intern("ByBit", "BTCUSDT")));
+        // Every shard gets some of the instruments
+        let used = ids.iter().map(|id| id.0 as usize % 3).collect::<HashSet<_>>();
+        assert_eq!(used.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn flush_drains_every_shard() {
+        let aggregator = Aggregator::new(4, 100, OverloadPolicy::Block);
+        let mut publisher = aggregator.publisher("Binance");
+        for market in MARKETS {
+            for ask in ["10", "12", "9", "11"] {
+                let orderbook = Orderbook::new("Binance", &Instrument::new(market, 8), "1", ask).expect("Invalid orderbook");
+                assert!(publisher.publish(orderbook).await);
+            }
+        }
+
+        let timestamp = DateTime::from_timestamp(1_735_689_600, 0).expect("Invalid time").naive_utc();
+        let bars = aggregator.flush(timestamp).await;
+
+        let mut markets = bars.iter().map(|bar| bar.market.to_string()).collect::<Vec<_>>();
+        markets.sort();
+        let mut expected = MARKETS.map(String::from).to_vec();
+        expected.sort();
+        assert_eq!(markets, expected);
+        for bar in &bars {
+            assert_eq!(bar.timestamp, timestamp);
+            assert_eq!(
+                [bar.olhc.open, bar.olhc.max, bar.olhc.min, bar.olhc.close].map(|price| price.to_string()),
+                ["10", "12", "9", "11"],
+                "Unexpected bar of {}", bar.market
+            );
+        }
+        assert_eq!(aggregator.queue_depth(), 0);
+
+        // The shards start new bars afterwards
+        assert!(aggregator.flush(timestamp).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn interrupted_markets_get_partial_bars_for_one_minute() {
+        let aggregator = Aggregator::new(2, 100, OverloadPolicy::Block);
+        let mut publisher = aggregator.publisher("Binance");
+        let timestamp = DateTime::from_timestamp(1_735_689_600, 0).expect("Invalid time").naive_utc();
+        let publish = async |publisher: &mut super::Publisher| {
+            for market in ["BTCUSDT", "ETHUSDT"] {
+                assert!(publisher.publish(Orderbook::new("Binance", &Instrument::new(market, 8), "1", "10").expect("Invalid orderbook")).await);
+            }
+        };
+
+        publish(&mut publisher).await;
+        publisher.interrupt(&["btc-usdt".to_string()]);
+        let mut bars = aggregator.flush(timestamp).await.into_iter().map(|bar| (bar.market.to_string(), bar.partial)).collect::<Vec<_>>();
+        bars.sort();
+        assert_eq!(bars, [("BTCUSDT".to_string(), true), ("ETHUSDT".to_string(), false)]);
+
+        publish(&mut publisher).await;
+        assert!(aggregator.flush(timestamp).await.iter().all(|bar| !bar.partial), "The next minute is partial too");
+    }
+}
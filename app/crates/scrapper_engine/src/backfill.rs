@@ -0,0 +1,275 @@
+use crate::config::{Config, EXCHANGES};
+use crate::leader::Leadership;
+use crate::metrics;
+use crate::structs::{upsert_backfilled, INSERT_CHUNK, OLHC};
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, DurationRound, NaiveDateTime, TimeDelta, Utc};
+use diesel::{Connection, ExpressionMethods, PgConnection, QueryResult, RunQueryDsl};
+use db::db::DbPool;
+use db::models::NewBar1min;
+use db::schema::gaps_1min;
+use exchange::enums::AnyExchange;
+use exchange::exchanges::{Binance, ByBit, KuCoin};
+use exchange::klines::{KlineClient, RateLimited};
+use exchange::structs::market_symbol;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+// Time the writer gets to write the last minute of an outage before it's backfilled
+const SETTLE_TIME: Duration = Duration::from_secs(10);
+
+// Retries of a request the exchange refused for its rate limit, waiting twice as long each time
+// unless the exchange said how long to wait
+const MAX_RETRIES: u32 = 5;
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+// Minutes a feed missed while it reconnected, from the minute it lost the connection to the one it got it back
+#[derive(Clone, Debug)]
+pub struct Outage {
+    pub exchange: &'static str,
+    pub markets: Vec<String>,
+    pub from: NaiveDateTime,
+    pub to: NaiveDateTime,
+}
+
+// Bar read from a kline of an exchange
+#[derive(Clone, Debug)]
+pub struct BackfilledBar {
+    pub exchange: &'static str,
+    pub market: String,
+    // Start of the minute the bar covers
+    pub timestamp: NaiveDateTime,
+    pub olhc: OLHC,
+}
+
+struct Venue {
+    name: &'static str,
+    klines: KlineClient,
+    // Time between two requests to the exchange
+    interval: Duration,
+    next_request: Mutex<Instant>,
+}
+
+impl Venue {
+    // Waits until the rate limit allows the next request
+    async fn wait_turn(&self) {
+        let mut next_request = self.next_request.lock().await;
+        tokio::time::sleep_until(*next_request).await;
+        *next_request = Instant::now() + self.interval;
+    }
+}
+
+// Reads the 1-minute klines of the exchanges' REST apis into bars_1min, flagged as backfilled
+pub struct Backfill {
+    venues: BTreeMap<&'static str, Venue>,
+}
+
+impl Backfill {
+    // A client per known exchange, on the rest_url of its section when it has one
+    pub fn new(config: &Config) -> Self {
+        let interval = Duration::from_secs(1) / config.backfill.requests_per_second.max(1);
+        let venues = EXCHANGES.into_iter()
+            .map(|key| {
+                let (name, exchange, default_url) = match key {
+                    "binance" => ("Binance", AnyExchange::Binance, Binance::REST_URL),
+                    "bybit" => ("ByBit", AnyExchange::ByBit, ByBit::REST_URL),
+                    "kucoin" => ("KuCoin", AnyExchange::KuCoin, KuCoin::REST_URL),
+                    _ => unreachable!("Unknown exchange {}", key),
+                };
+                let url = config.exchanges
+                    .get(key)
+                    .and_then(|section| section.rest_url.clone())
+                    .unwrap_or_else(|| default_url.to_string());
+                let venue = Venue {
+                    name,
+                    klines: KlineClient::new(exchange, url),
+                    interval,
+                    next_request: Mutex::new(Instant::now()),
+                };
+                (key, venue)
+            })
+            .collect();
+
+        Self { venues }
+    }
+
+    // Bars of `market` for the minutes from `from` to `to` (inclusive), a request per page of klines.
+    // `exchange` is named as in the config or as the exchange calls itself
+    pub async fn fetch(&self, exchange: &str, market: &str, from: NaiveDateTime, to: NaiveDateTime) -> Result<Vec<BackfilledBar>> {
+        let venue = self.venues
+            .get(exchange.to_lowercase().as_str())
+            .ok_or_else(|| anyhow!("Unknown exchange `{}`, expected one of {}", exchange, EXCHANGES.join(", ")))?;
+        let page = TimeDelta::minutes(venue.klines.page_size() as i64);
+        let symbol = market_symbol(market);
+
+        let mut bars = Vec::new();
+        let mut start = from;
+        while start <= to {
+            let end = (start + page - TimeDelta::minutes(1)).min(to);
+            let klines = self.request(venue, market, start, end).await
+                .with_context(|| format!("Error reading the klines of {} on {} from {} to {}", market, venue.name, start, end))?;
+            debug!(exchange = venue.name, market, from = %start, to = %end, klines = klines.len(), "Read klines");
+            // Minutes without trades have no kline, but a page cut short by the exchange looks the same
+            let minutes = (end - start).num_minutes() + 1;
+            if (klines.len() as i64) < minutes {
+                warn!(exchange = venue.name, market, from = %start, to = %end, minutes, klines = klines.len(), "Klines missing from the page");
+            }
+
+            for kline in klines {
+                let timestamp = DateTime::from_timestamp_millis(kline.open_time)
+                    .ok_or_else(|| anyhow!("Invalid kline open time {}", kline.open_time))?
+                    .naive_utc();
+                bars.push(BackfilledBar {
+                    exchange: venue.name,
+                    market: symbol.clone(),
+                    timestamp,
+                    olhc: OLHC {
+                        open: kline.open,
+                        close: kline.close,
+                        min: kline.low,
+                        max: kline.high,
+                    },
+                });
+            }
+            start = end + TimeDelta::minutes(1);
+        }
+
+        Ok(bars)
+    }
+
+    async fn request(&self, venue: &Venue, market: &str, start: NaiveDateTime, end: NaiveDateTime) -> Result<Vec<exchange::klines::Kline>> {
+        let mut backoff = RETRY_BACKOFF;
+        let mut retries = 0;
+        loop {
+            venue.wait_turn().await;
+            let error = match venue.klines.fetch(market, start.and_utc().timestamp_millis(), end.and_utc().timestamp_millis()).await {
+                Ok(klines) => return Ok(klines),
+                Err(e) => e,
+            };
+            let Some(limited) = error.downcast_ref::<RateLimited>() else {
+                return Err(error);
+            };
+            if retries == MAX_RETRIES {
+                return Err(error);
+            }
+
+            let wait = limited.retry_after.unwrap_or(backoff);
+            warn!(exchange = venue.name, wait_secs = wait.as_secs_f64(), "Rate limited, retrying the klines request");
+            tokio::time::sleep(wait).await;
+            backoff *= 2;
+            retries += 1;
+        }
+    }
+
+    // Fetches the bars of `market` from `from` to `to` and writes them, returns the number of bars written.
+    // Minutes that aren't over yet are left to the scraper
+    pub async fn backfill(&self, pool: &DbPool, exchange: &str, market: &str, from: NaiveDateTime, to: NaiveDateTime) -> Result<usize> {
+        let to = to.min(last_complete_minute());
+        if from > to {
+            return Ok(0);
+        }
+
+        let bars = self.fetch(exchange, market, from, to).await?;
+        let Some(name) = bars.first().map(|bar| bar.exchange) else {
+            return Ok(0);
+        };
+
+        let pool = pool.clone();
+        let written = tokio::task::spawn_blocking(move || -> Result<usize> {
+            let mut conn = pool.get()?;
+            Ok(conn.transaction(|conn| save_backfilled(&bars, conn))?)
+        }).await??;
+
+        metrics::BACKFILLED_BARS.with_label_values(&[name]).inc_by(written as u64);
+        Ok(written)
+    }
+}
+
+// Upserts the bars and removes the gaps recorded for their minutes, returns the number of bars written
+pub fn save_backfilled(bars: &[BackfilledBar], conn: &mut PgConnection) -> QueryResult<usize> {
+    let mut saved = 0;
+
+    for chunk in bars.chunks(INSERT_CHUNK) {
+        let rows = chunk.iter()
+            .map(|bar| NewBar1min {
+                backfilled: true,
+                ..NewBar1min::new(
+                    bar.exchange,
+                    &bar.market,
+                    bar.timestamp,
+                    bar.olhc.open.into(),
+                    bar.olhc.close.into(),
+                    bar.olhc.min.into(),
+                    bar.olhc.max.into(),
+                    false,
+                    false,
+                )
+            })
+            .collect::<Vec<NewBar1min>>();
+        saved += upsert_backfilled(&rows, conn)?;
+
+        let mut minutes = BTreeMap::<(&str, &str), Vec<NaiveDateTime>>::new();
+        for bar in chunk {
+            minutes.entry((bar.exchange, &bar.market)).or_default().push(bar.timestamp);
+        }
+        for ((exchange, market), timestamps) in minutes {
+            diesel::delete(gaps_1min::table)
+                .filter(gaps_1min::exchange.eq(exchange))
+                .filter(gaps_1min::market.eq(market))
+                .filter(gaps_1min::timestamp.eq_any(timestamps))
+                .execute(conn)?;
+        }
+    }
+
+    Ok(saved)
+}
+
+// Backfills the outages of the feeds until shutdown, once the writer wrote their minutes.
+// Only the instance writing the bars backfills
+pub async fn backfill_outages(
+    backfill: Backfill,
+    pool: DbPool,
+    leadership: Leadership,
+    mut outages: mpsc::Receiver<Outage>,
+    shutdown: CancellationToken,
+) {
+    loop {
+        let outage = tokio::select! {
+            Some(outage) = outages.recv() => outage,
+            _ = shutdown.cancelled() => return,
+        };
+
+        // The last minute of the outage is written when the next one starts
+        let written_at = (outage.to + TimeDelta::minutes(1)).and_utc();
+        let wait = (written_at - Utc::now()).to_std().unwrap_or_default() + SETTLE_TIME;
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {},
+            _ = shutdown.cancelled() => return,
+        }
+
+        if !leadership.is_leader() {
+            debug!(exchange = outage.exchange, "Standing by, outage left to the leader");
+            continue;
+        }
+        for market in outage.markets.iter() {
+            match backfill.backfill(&pool, outage.exchange, market, outage.from, outage.to).await {
+                Ok(written) => info!(exchange = outage.exchange, market = %market, from = %outage.from, to = %outage.to, bars = written, "Backfilled outage"),
+                Err(e) => warn!(exchange = outage.exchange, market = %market, error = format!("{:#}", e), "Error backfilling outage"),
+            }
+        }
+    }
+}
+
+// Start of the last minute that's over
+pub fn last_complete_minute() -> NaiveDateTime {
+    Utc::now()
+        .duration_trunc(TimeDelta::minutes(1))
+        .expect("Error truncating time to minute")
+        .naive_utc() - TimeDelta::minutes(1)
+}
@@ -0,0 +1,182 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::{Error, Message};
+use tracing::{info, warn};
+
+// Frames buffered between the feeds and the capture writer, feeds wait when it's full
+const CAPTURE_CAPACITY: usize = 10_000;
+
+// The encoder is flushed every this many frames so a crash loses at most that many
+const FLUSH_EVERY: usize = 1_000;
+
+static CONNECTIONS: AtomicU64 = AtomicU64::new(1);
+
+// Id of a new websocket connection, unique within the process
+pub fn next_connection_id() -> u64 {
+    CONNECTIONS.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameKind {
+    Text,
+    Binary,
+    Ping,
+    Pong,
+    Close,
+    // Reading the frame failed, `data` holds the error
+    Error,
+}
+
+// One inbound websocket frame as received
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CapturedFrame {
+    // Microseconds since the epoch
+    pub received_at: i64,
+    pub exchange: String,
+    pub connection: u64,
+    pub kind: FrameKind,
+    pub data: String,
+}
+
+impl CapturedFrame {
+    fn new(exchange: &str, connection: u64, frame: &Result<Message, Error>) -> Self {
+        let (kind, data) = match frame {
+            Ok(Message::Text(text)) => (FrameKind::Text, text.to_string()),
+            Ok(Message::Binary(bytes)) => (FrameKind::Binary, String::from_utf8_lossy(bytes).into_owned()),
+            Ok(Message::Ping(bytes)) => (FrameKind::Ping, String::from_utf8_lossy(bytes).into_owned()),
+            Ok(Message::Pong(bytes)) => (FrameKind::Pong, String::from_utf8_lossy(bytes).into_owned()),
+            Ok(Message::Close(frame)) => (FrameKind::Close, frame.as_ref().map(|f| f.reason.to_string()).unwrap_or_default()),
+            Ok(Message::Frame(_)) => (FrameKind::Binary, String::new()),
+            Err(e) => (FrameKind::Error, e.to_string()),
+        };
+
+        Self {
+            received_at: Utc::now().timestamp_micros(),
+            exchange: exchange.to_string(),
+            connection,
+            kind,
+            data,
+        }
+    }
+
+    pub fn received_at(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp_micros(self.received_at).unwrap_or_default()
+    }
+}
+
+// Records every inbound frame to zstd-compressed NDJSON files, one per hour:
+// <dir>/frames-<yyyymmdd>-<hh>.ndjson.zst. Cheap to clone, one clone per feed
+#[derive(Clone)]
+pub struct Capture {
+    frames: mpsc::Sender<CapturedFrame>,
+}
+
+impl Capture {
+    // Starts the writer, it finishes the current file and returns once every clone is dropped
+    pub fn start(dir: impl Into<PathBuf>) -> Result<(Capture, JoinHandle<Result<()>>)> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        let (frames, receiver) = mpsc::channel(CAPTURE_CAPACITY);
+        let writer = tokio::task::spawn_blocking(move || Self::write_frames(&dir, receiver));
+
+        Ok((Capture { frames }, writer))
+    }
+
+    pub async fn record(&self, exchange: &str, connection: u64, frame: &Result<Message, Error>) {
+        if self.frames.send(CapturedFrame::new(exchange, connection, frame)).await.is_err() {
+            warn!(exchange, "Capture writer stopped, frame not recorded");
+        }
+    }
+
+    fn write_frames(dir: &Path, mut receiver: mpsc::Receiver<CapturedFrame>) -> Result<()> {
+        let mut current: Option<(String, zstd::Encoder<'static, BufWriter<File>>)> = None;
+        let mut unflushed = 0;
+
+        while let Some(frame) = receiver.blocking_recv() {
+            let hour = frame.received_at().format("%Y%m%d-%H").to_string();
+
+            if current.as_ref().is_none_or(|(current_hour, _)| *current_hour != hour) {
+                if let Some((_, encoder)) = current.take() {
+                    encoder.finish()?.flush()?;
+                }
+
+                // A restart within the same hour appends a new zstd frame, decoders read them back to back
+                let path = dir.join(format!("frames-{}.ndjson.zst", hour));
+                let file = OpenOptions::new().create(true).append(true).open(&path)?;
+                info!(path = %path.display(), "Capturing frames");
+                current = Some((hour, zstd::Encoder::new(BufWriter::new(file), 0)?));
+            }
+
+            if let Some((_, encoder)) = current.as_mut() {
+                serde_json::to_writer(&mut *encoder, &frame)?;
+                encoder.write_all(b"\n")?;
+
+                unflushed += 1;
+                if unflushed >= FLUSH_EVERY {
+                    encoder.flush()?;
+                    unflushed = 0;
+                }
+            }
+        }
+
+        if let Some((_, encoder)) = current.take() {
+            encoder.finish()?.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+// Capture files in `path` in the order they were written, `path` can also be a single file
+pub fn capture_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = fs::read_dir(path)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.to_string_lossy().ends_with(".ndjson.zst"))
+        .collect::<Vec<PathBuf>>();
+    files.sort();
+
+    Ok(files)
+}
+
+// Reads the captured frames of all files in order. A truncated file, e.g. after a crash,
+// is read up to the last complete frame
+pub fn read_frames(files: Vec<PathBuf>, frames: mpsc::Sender<CapturedFrame>) -> Result<()> {
+    for path in files {
+        let decoder = zstd::Decoder::new(File::open(&path)?)?;
+
+        for line in BufReader::new(decoder).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    warn!(path = %path.display(), error = %e, "Stopped reading capture early");
+                    break;
+                }
+            };
+
+            match serde_json::from_str::<CapturedFrame>(&line) {
+                Ok(frame) => {
+                    if frames.blocking_send(frame).is_err() {
+                        return Ok(());
+                    }
+                },
+                Err(e) => warn!(path = %path.display(), error = %e, "Skipping unreadable frame"),
+            }
+        }
+    }
+
+    Ok(())
+}
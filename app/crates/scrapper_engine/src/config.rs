@@ -0,0 +1,655 @@
+use crate::gaps::{GapPolicies, GapPolicy};
+use crate::logging::LogFormat;
+use crate::queue::OverloadPolicy;
+use crate::sinks::SinkKind;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread::available_parallelism;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use exchange::structs::market_symbol;
+use serde::{Deserialize, Deserializer};
+use tracing_subscriber::EnvFilter;
+
+// Exchanges the scraper can connect to, as named in the config file and the env variables
+pub const EXCHANGES: [&str; 3] = ["binance", "bybit", "kucoin"];
+
+// Read when CONFIG_FILE isn't set and it exists in the working directory
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    fn from_path(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Ok(ConfigFormat::Toml),
+            Some("yaml") | Some("yml") => Ok(ConfigFormat::Yaml),
+            _ => bail!("Unknown config file format {}, expected a .toml, .yaml or .yml file", path.display()),
+        }
+    }
+}
+
+// Settings of the whole process. Every value has a default, the file only needs what differs
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // Sections by exchange, an exchange without a section isn't scraped
+    pub exchanges: BTreeMap<String, ExchangeConfig>,
+    pub api: ApiConfig,
+    pub database: DatabaseConfig,
+    pub sinks: SinksConfig,
+    pub aggregator: AggregatorConfig,
+    pub health: HealthConfig,
+    pub gaps: GapsConfig,
+    pub ha: HaConfig,
+    pub backfill: BackfillConfig,
+    pub capture: CaptureConfig,
+    pub logging: LoggingConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExchangeConfig {
+    pub enabled: bool,
+    pub markets: Vec<String>,
+    // Websocket url, the production one when unset
+    pub url: Option<String>,
+    // REST url handing out websocket tokens, only used by KuCoin
+    pub token_url: Option<String>,
+    // Root of the REST api serving the tick sizes and the klines of the backfill, the production one when unset
+    pub rest_url: Option<String>,
+    // Seconds between the pings keeping a connection alive
+    pub heartbeat_secs: u64,
+    // Max markets per websocket connection, more markets are spread over more connections
+    pub shard_size: usize,
+    // Gap policies of single markets, overriding gaps.policy
+    #[serde(deserialize_with = "parsed_map")]
+    pub gap_policies: BTreeMap<String, GapPolicy>,
+}
+
+impl Default for ExchangeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            markets: Vec::new(),
+            url: None,
+            token_url: None,
+            rest_url: None,
+            heartbeat_secs: 20,
+            shard_size: 100,
+            gap_policies: BTreeMap::new(),
+        }
+    }
+}
+
+impl ExchangeConfig {
+    pub fn heartbeat(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_secs)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    pub bind: String,
+    // Serves https when set
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1:8000".to_string(),
+            tls: None,
+        }
+    }
+}
+
+impl ApiConfig {
+    pub fn address(&self) -> Result<SocketAddr> {
+        self.bind.parse::<SocketAddr>()
+            .map_err(|_| anyhow!("api.bind `{}` isn't an address like 127.0.0.1:8000", self.bind))
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    // PEM files of the certificate chain and its private key
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    // Primary database, the scraper writes and migrations run here
+    pub url: Option<String>,
+    // Size of each pool unless set per side below
+    pub pool_size: u32,
+    // Applies pending migrations on startup instead of refusing to start
+    pub auto_migrate: bool,
+    pub scraper_pool_size: Option<u32>,
+    // Database the api reads from, e.g. a read replica. Defaults to `url`
+    pub api_url: Option<String>,
+    pub api_pool_size: Option<u32>,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            pool_size: 15,
+            auto_migrate: false,
+            scraper_pool_size: None,
+            api_url: None,
+            api_pool_size: None,
+        }
+    }
+}
+
+impl DatabaseConfig {
+    pub fn url(&self) -> Result<&str> {
+        self.url.as_deref().ok_or_else(|| anyhow!("No database configured, set database.url or DATABASE_URL"))
+    }
+
+    // Url and size of the pool the scraper writes through
+    pub fn scraper_pool(&self) -> Result<(&str, u32)> {
+        Ok((self.url()?, self.scraper_pool_size.unwrap_or(self.pool_size)))
+    }
+
+    // Url and size of the pool the api reads through
+    pub fn api_pool(&self) -> Result<(&str, u32)> {
+        let url = match &self.api_url {
+            Some(url) => url.as_str(),
+            None => self.url()?,
+        };
+        Ok((url, self.api_pool_size.unwrap_or(self.pool_size)))
+    }
+
+    // Whether the api reads from another database than the primary
+    pub fn api_uses_replica(&self) -> bool {
+        self.api_url.is_some()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SinksConfig {
+    // Sinks the bars are written to
+    #[serde(deserialize_with = "parsed_list")]
+    pub enabled: Vec<SinkKind>,
+    // Directory of the csv and ndjson sink files
+    pub dir: String,
+    // Root directory of the parquet archive
+    pub parquet_dir: String,
+    // File bars are spilled to while the database is unavailable
+    pub spill_path: String,
+    // Size cap of the spill file
+    pub spill_max_bytes: u64,
+}
+
+impl Default for SinksConfig {
+    fn default() -> Self {
+        Self {
+            enabled: vec![SinkKind::Postgres],
+            dir: "data".to_string(),
+            parquet_dir: "data/parquet".to_string(),
+            spill_path: "spill/bars_1min.ndjson".to_string(),
+            spill_max_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AggregatorConfig {
+    // Defaults to the number of available cores
+    pub shards: Option<usize>,
+    // Max number of updates buffered per shard
+    pub capacity: usize,
+    #[serde(deserialize_with = "parsed")]
+    pub overload_policy: OverloadPolicy,
+}
+
+impl Default for AggregatorConfig {
+    fn default() -> Self {
+        Self {
+            shards: None,
+            capacity: 10_000,
+            overload_policy: OverloadPolicy::Block,
+        }
+    }
+}
+
+impl AggregatorConfig {
+    pub fn shard_count(&self) -> Result<usize> {
+        match self.shards {
+            Some(shards) => Ok(shards),
+            None => Ok(available_parallelism()?.get()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    // Seconds without an update of a market before /ready fails
+    pub max_message_age_secs: u64,
+    // Seconds without a successful write to a sink before /ready fails
+    pub max_flush_age_secs: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            max_message_age_secs: 60,
+            max_flush_age_secs: 150,
+        }
+    }
+}
+
+// What's written for the minutes in which a market got no updates
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GapsConfig {
+    // Policy of every market without its own in exchanges.<name>.gap_policies
+    #[serde(deserialize_with = "parsed")]
+    pub policy: GapPolicy,
+}
+
+// Hot standby. Every instance scrapes, only the one holding the advisory lock writes the bars
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HaConfig {
+    pub enabled: bool,
+    // Instances with the same key elect one leader, deployments sharing a database need their own key
+    pub lock_key: i64,
+}
+
+impl Default for HaConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            lock_key: 1_234_567_001,
+        }
+    }
+}
+
+// Filling missed minutes from the klines of the exchanges' REST apis
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackfillConfig {
+    // Backfills the minutes a feed missed while it reconnected
+    pub auto: bool,
+    // Max kline requests per second to each exchange
+    pub requests_per_second: u32,
+}
+
+impl Default for BackfillConfig {
+    fn default() -> Self {
+        Self {
+            auto: true,
+            requests_per_second: 5,
+        }
+    }
+}
+
+// Recording of the raw websocket frames, for replay
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    // Directory the frames are captured to, capture is off when unset
+    pub dir: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    // Filter in the tracing directive syntax, e.g. `info,scrapper_engine=debug`
+    pub filter: String,
+    #[serde(deserialize_with = "parsed")]
+    pub format: LogFormat,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            filter: "info".to_string(),
+            format: LogFormat::Text,
+        }
+    }
+}
+
+impl Config {
+    // Reads the file named by CONFIG_FILE, or config.toml when it exists, applies the env overrides and validates
+    pub fn load() -> Result<Self> {
+        Self::load_from(Self::file_path().as_deref())
+    }
+
+    // File the config is read from, if any
+    pub fn file_path() -> Option<PathBuf> {
+        match env::var("CONFIG_FILE") {
+            Ok(path) => Some(PathBuf::from(path)),
+            Err(_) => Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.exists()),
+        }
+    }
+
+    // Reads `path`, or only the env variables without one
+    pub fn load_from(path: Option<&Path>) -> Result<Self> {
+        let mut config = match path {
+            Some(path) => Self::read(path)?,
+            None => Self::default(),
+        };
+
+        config.apply_overrides(|key| env::var(key).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("Error reading config file {}", path.display()))?;
+        Self::parse(&text, ConfigFormat::from_path(path)?).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    pub fn parse(text: &str, format: ConfigFormat) -> Result<Self> {
+        let mut config: Config = match format {
+            ConfigFormat::Toml => toml::from_str(text)?,
+            ConfigFormat::Yaml => serde_yaml::from_str(text)?,
+        };
+
+        // Sections may be named like the exchanges, e.g. [exchanges.KuCoin]
+        let mut exchanges = BTreeMap::new();
+        for (name, exchange) in config.exchanges {
+            let key = name.to_lowercase();
+            if exchanges.insert(key.clone(), exchange).is_some() {
+                bail!("Exchange {} is configured twice", key);
+            }
+        }
+        config.exchanges = exchanges;
+
+        Ok(config)
+    }
+
+    // Applies the env variables that override the file, `var` looks them up
+    pub fn apply_overrides(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<()> {
+        for name in EXCHANGES {
+            let upper = name.to_uppercase();
+            if let Some(markets) = var(&format!("MARKETS_{}", upper)) {
+                self.exchanges.entry(name.to_string()).or_default().markets = split_list(&markets);
+            }
+
+            let Some(exchange) = self.exchanges.get_mut(name) else {
+                continue;
+            };
+            override_parsed(&var, &format!("ENABLED_{}", upper), &mut exchange.enabled)?;
+            override_option(&var, &format!("URL_{}", upper), &mut exchange.url);
+            override_option(&var, &format!("TOKEN_URL_{}", upper), &mut exchange.token_url);
+            override_option(&var, &format!("REST_URL_{}", upper), &mut exchange.rest_url);
+            override_parsed(&var, "PING_INTERVAL", &mut exchange.heartbeat_secs)?;
+            for market in exchange.markets.clone() {
+                let key = format!("GAP_POLICY_{}_{}", upper, market_symbol(&market));
+                if let Some(policy) = var(&key) {
+                    exchange.gap_policies.insert(market, parse_var(&key, &policy)?);
+                }
+            }
+        }
+
+        override_parsed(&var, "API_BIND", &mut self.api.bind)?;
+        if let Some(cert) = var("API_TLS_CERT") {
+            self.api.tls.get_or_insert_with(TlsConfig::default).cert = PathBuf::from(cert);
+        }
+        if let Some(key) = var("API_TLS_KEY") {
+            self.api.tls.get_or_insert_with(TlsConfig::default).key = PathBuf::from(key);
+        }
+
+        override_option(&var, "DATABASE_URL", &mut self.database.url);
+        override_parsed(&var, "DB_POOL_SIZE", &mut self.database.pool_size)?;
+        override_parsed(&var, "AUTO_MIGRATE", &mut self.database.auto_migrate)?;
+        override_parsed(&var, "HA_ENABLED", &mut self.ha.enabled)?;
+        override_parsed(&var, "HA_LOCK_KEY", &mut self.ha.lock_key)?;
+        if let Some(size) = var("SCRAPER_DB_POOL_SIZE") {
+            self.database.scraper_pool_size = Some(parse_var("SCRAPER_DB_POOL_SIZE", &size)?);
+        }
+        override_option(&var, "API_DATABASE_URL", &mut self.database.api_url);
+        if let Some(size) = var("API_DB_POOL_SIZE") {
+            self.database.api_pool_size = Some(parse_var("API_DB_POOL_SIZE", &size)?);
+        }
+
+        if let Some(sinks) = var("SINKS") {
+            self.sinks.enabled = split_list(&sinks)
+                .iter()
+                .map(|sink| sink.parse::<SinkKind>())
+                .collect::<Result<_>>()
+                .context("Invalid SINKS")?;
+        }
+        override_parsed(&var, "SINK_DIR", &mut self.sinks.dir)?;
+        override_parsed(&var, "PARQUET_DIR", &mut self.sinks.parquet_dir)?;
+        override_parsed(&var, "SPILL_PATH", &mut self.sinks.spill_path)?;
+        override_parsed(&var, "SPILL_MAX_BYTES", &mut self.sinks.spill_max_bytes)?;
+
+        if let Some(shards) = var("AGGREGATOR_SHARDS") {
+            self.aggregator.shards = Some(parse_var("AGGREGATOR_SHARDS", &shards)?);
+        }
+        override_parsed(&var, "AGGREGATOR_CAPACITY", &mut self.aggregator.capacity)?;
+        override_parsed(&var, "OVERLOAD_POLICY", &mut self.aggregator.overload_policy)?;
+
+        override_parsed(&var, "READY_MAX_MESSAGE_AGE", &mut self.health.max_message_age_secs)?;
+        override_parsed(&var, "READY_MAX_FLUSH_AGE", &mut self.health.max_flush_age_secs)?;
+        override_parsed(&var, "GAP_POLICY", &mut self.gaps.policy)?;
+
+        override_option(&var, "CAPTURE_DIR", &mut self.capture.dir);
+        // LOG_FILTER wins over RUST_LOG
+        override_parsed(&var, "RUST_LOG", &mut self.logging.filter)?;
+        override_parsed(&var, "LOG_FILTER", &mut self.logging.filter)?;
+        override_parsed(&var, "LOG_FORMAT", &mut self.logging.format)?;
+
+        override_parsed(&var, "BACKFILL_AUTO", &mut self.backfill.auto)?;
+        override_parsed(&var, "BACKFILL_REQUESTS_PER_SECOND", &mut self.backfill.requests_per_second)?;
+
+        Ok(())
+    }
+
+    // Checks the whole config and reports every problem at once
+    pub fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+
+        for (name, exchange) in &self.exchanges {
+            if !EXCHANGES.contains(&name.as_str()) {
+                errors.push(format!("Unknown exchange `{}`, expected one of {}", name, EXCHANGES.join(", ")));
+                continue;
+            }
+            if !exchange.enabled {
+                continue;
+            }
+
+            if exchange.markets.is_empty() {
+                errors.push(format!("exchanges.{} has no markets, list them in `markets` or MARKETS_{}", name, name.to_uppercase()));
+            }
+            if exchange.markets.iter().any(|market| market.trim().is_empty()) {
+                errors.push(format!("exchanges.{}.markets contains an empty market", name));
+            }
+            if exchange.heartbeat_secs == 0 {
+                errors.push(format!("exchanges.{}.heartbeat_secs must be at least 1", name));
+            }
+            if exchange.shard_size == 0 {
+                errors.push(format!("exchanges.{}.shard_size must be at least 1", name));
+            }
+            if let Some(url) = &exchange.url
+                && !(url.starts_with("ws://") || url.starts_with("wss://"))
+            {
+                errors.push(format!("exchanges.{}.url `{}` isn't a ws:// or wss:// url", name, url));
+            }
+            if let Some(token_url) = &exchange.token_url {
+                if name != "kucoin" {
+                    errors.push(format!("exchanges.{}.token_url is only used by kucoin", name));
+                } else if !(token_url.starts_with("http://") || token_url.starts_with("https://")) {
+                    errors.push(format!("exchanges.{}.token_url `{}` isn't a http:// or https:// url", name, token_url));
+                }
+            }
+            if let Some(rest_url) = &exchange.rest_url
+                && !(rest_url.starts_with("http://") || rest_url.starts_with("https://"))
+            {
+                errors.push(format!("exchanges.{}.rest_url `{}` isn't a http:// or https:// url", name, rest_url));
+            }
+            let symbols = exchange.markets.iter().map(|market| market_symbol(market)).collect::<Vec<_>>();
+            for market in exchange.gap_policies.keys() {
+                if !symbols.contains(&market_symbol(market)) {
+                    errors.push(format!("exchanges.{}.gap_policies names `{}`, which isn't one of its markets", name, market));
+                }
+            }
+        }
+
+        if let Err(e) = self.api.address() {
+            errors.push(e.to_string());
+        }
+        if let Some(tls) = &self.api.tls {
+            for (field, path) in [("cert", &tls.cert), ("key", &tls.key)] {
+                if path.as_os_str().is_empty() {
+                    errors.push(format!("api.tls.{} is required with TLS", field));
+                } else if !path.is_file() {
+                    errors.push(format!("api.tls.{} {} doesn't exist", field, path.display()));
+                }
+            }
+        }
+
+        for (field, size) in [
+            ("pool_size", Some(self.database.pool_size)),
+            ("scraper_pool_size", self.database.scraper_pool_size),
+            ("api_pool_size", self.database.api_pool_size),
+        ] {
+            if size == Some(0) {
+                errors.push(format!("database.{} must be at least 1", field));
+            }
+        }
+        if self.sinks.enabled.is_empty() {
+            errors.push("sinks.enabled must name at least one sink".to_string());
+        }
+        if self.sinks.enabled.contains(&SinkKind::Postgres) && self.database.url.is_none() {
+            errors.push("The postgres sink needs database.url or DATABASE_URL".to_string());
+        }
+        if self.ha.enabled && self.database.url.is_none() {
+            errors.push("ha needs database.url or DATABASE_URL to elect the leader".to_string());
+        }
+
+        if self.aggregator.shards == Some(0) {
+            errors.push("aggregator.shards must be at least 1".to_string());
+        }
+        if self.aggregator.capacity == 0 {
+            errors.push("aggregator.capacity must be at least 1".to_string());
+        }
+        if self.backfill.requests_per_second == 0 {
+            errors.push("backfill.requests_per_second must be at least 1".to_string());
+        }
+        if let Some(dir) = &self.capture.dir
+            && dir.trim().is_empty()
+        {
+            errors.push("capture.dir is empty, leave it unset to turn capture off".to_string());
+        }
+        if let Err(e) = EnvFilter::try_new(&self.logging.filter) {
+            errors.push(format!("logging.filter `{}` is invalid: {}", self.logging.filter, e));
+        }
+
+        if !errors.is_empty() {
+            bail!("Invalid configuration:\n  - {}", errors.join("\n  - "));
+        }
+        Ok(())
+    }
+
+    // Enabled exchange sections by name
+    pub fn enabled_exchanges(&self) -> impl Iterator<Item = (&str, &ExchangeConfig)> {
+        self.exchanges.iter()
+            .filter(|(_, exchange)| exchange.enabled)
+            .map(|(name, exchange)| (name.as_str(), exchange))
+    }
+
+    // Gap policy of every configured market
+    pub fn gap_policies(&self) -> GapPolicies {
+        let mut policies = GapPolicies::new(self.gaps.policy);
+        for (name, exchange) in &self.exchanges {
+            for (market, policy) in &exchange.gap_policies {
+                policies.set(name, market, *policy);
+            }
+        }
+        policies
+    }
+
+    pub fn uses_database(&self) -> bool {
+        self.sinks.enabled.contains(&SinkKind::Postgres)
+    }
+}
+
+// Lists in env variables are separated by commas, semicolons are accepted as well
+fn split_list(list: &str) -> Vec<String> {
+    list.split([',', ';'])
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn parse_var<T>(key: &str, value: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value.trim().parse::<T>().map_err(|e| anyhow!("Invalid {} `{}`: {}", key, value, e))
+}
+
+fn override_parsed<T>(var: &impl Fn(&str) -> Option<String>, key: &str, target: &mut T) -> Result<()>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Some(value) = var(key) {
+        *target = parse_var(key, &value)?;
+    }
+    Ok(())
+}
+
+fn override_option(var: &impl Fn(&str) -> Option<String>, key: &str, target: &mut Option<String>) {
+    if let Some(value) = var(key) {
+        *target = Some(value);
+    }
+}
+
+fn parsed<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = anyhow::Error>,
+{
+    let value = String::deserialize(deserializer)?;
+    value.parse::<T>().map_err(serde::de::Error::custom)
+}
+
+fn parsed_map<'de, D, T>(deserializer: D) -> Result<BTreeMap<String, T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = anyhow::Error>,
+{
+    BTreeMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(key, value)| Ok((key, value.parse::<T>().map_err(serde::de::Error::custom)?)))
+        .collect()
+}
+
+fn parsed_list<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = anyhow::Error>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|value| value.parse::<T>().map_err(serde::de::Error::custom))
+        .collect()
+}
@@ -1,24 +1,44 @@
-use crate::utils::{load_markets, load_ping_interval};
-use crate::{MapOLHC, ReadStream};
-use crate::structs::OLHC;
+use crate::config::ExchangeConfig;
+use crate::ReadStream;
+use crate::aggregator::Aggregator;
+use crate::backfill::Outage;
+use crate::capture::{next_connection_id, read_frames, Capture, FrameKind};
+use crate::gaps::GapPolicies;
+use crate::leader::Leadership;
+use crate::metrics;
+use crate::reload::{FeedCommand, MarketControl};
+use crate::status::FeedGuard;
+use crate::sinks::BarSink;
+use crate::writer::BarWriter;
 use crate::engine::MessageType::Closed;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::atomic::AtomicI64;
 use std::time::Duration;
 use exchange::Exchange;
-use exchange::structs::Orderbook;
+use exchange::structs::market_symbol;
 
-use anyhow::Result;
-use futures_util::lock::Mutex;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, DurationRound, NaiveDateTime, TimeDelta, Utc};
 use futures_util::{SinkExt, StreamExt};
 use serde_json::Value;
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinHandle;
 use tokio::time::Instant;
-use tokio_tungstenite::tungstenite::{Bytes, Message};
-use db::db::DbPool;
+use tokio_util::sync::CancellationToken;
+use tokio_tungstenite::tungstenite::{Bytes, Error as WsError, Message};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 use exchange::enums::AnyExchange;
 
+// Outages waiting for the backfill, more are dropped
+const OUTAGE_QUEUE: usize = 64;
+
+// Pause between two attempts to reconnect a closed connection
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
 enum MessageType {
     Data(HashMap<String, Value>),
     Ping(Bytes),
@@ -26,158 +46,480 @@ enum MessageType {
     Closed
 }
 
+// Connection to an exchange with the markets it's subscribed to
+pub struct Feed {
+    pub exchange: Box<dyn Exchange>,
+    pub markets: Vec<String>,
+    // Interval of the pings keeping the connection alive
+    pub heartbeat: Duration,
+    // Index of the connection among the connections to the same exchange
+    pub shard: usize,
+    // Market changes of a config reload
+    pub commands: mpsc::Receiver<FeedCommand>,
+    // Reports the minutes missed while reconnecting
+    pub outages: mpsc::Sender<Outage>,
+}
+
 pub struct Engine {
-    pub exchanges: Vec<Box<dyn Exchange>>,
+    pub feeds: Vec<Feed>,
+    // Changes the markets of the feeds once they're running
+    pub control: MarketControl,
+    // Minutes the feeds missed while reconnecting, nothing is reported once it's dropped
+    pub outages: mpsc::Receiver<Outage>,
+    outage_sender: mpsc::Sender<Outage>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Engine {
     pub fn new() -> Self {
+        let (outage_sender, outages) = mpsc::channel(OUTAGE_QUEUE);
 
         Self {
-            exchanges: Vec::new(),
+            feeds: Vec::new(),
+            control: MarketControl::default(),
+            outages,
+            outage_sender,
+        }
+    }
+
+    // Connects to an exchange section of the config, `shard_size` markets per connection.
+    // `new_exchange` creates the client of each connection
+    pub async fn add<E: Exchange + 'static>(mut self, config: &ExchangeConfig, new_exchange: impl Fn() -> E) -> Result<Self> {
+        for markets in config.markets.chunks(config.shard_size) {
+            self = self.add_markets(new_exchange(), markets.to_vec(), config.heartbeat()).await?;
         }
+
+        Ok(self)
     }
 
-    pub async fn add(mut self, mut exchange: impl Exchange + 'static) -> Self {
-        Engine::connect_to(&mut exchange).await;
-        self.exchanges.push(Box::new(exchange));
+    pub async fn add_markets(mut self, mut exchange: impl Exchange + 'static, markets: Vec<String>, heartbeat: Duration) -> Result<Self> {
+        Engine::connect_to(&mut exchange, &markets).await?;
+        let shard = self.feeds.iter().filter(|feed| feed.exchange.name() == exchange.name()).count();
+        let commands = self.control.register(exchange.name(), shard, &markets);
+        let outages = self.outage_sender.clone();
+        self.feeds.push(Feed { exchange: Box::new(exchange), markets, heartbeat, shard, commands, outages });
 
-        self
+        Ok(self)
     }
 
-    async fn connect_to(exchange: &mut dyn Exchange) {
+    async fn connect_to(exchange: &mut dyn Exchange, markets: &[String]) -> Result<()> {
         let name = exchange.name();
-        println!("Connecting to {}", name);
-        AnyExchange::connect_orderbooks_async(
-            exchange,
-            load_markets(name).expect(format!("Error loading markets for {}", name).as_str()),
-        ).await.expect(format!("Error connecting to {}", name).as_str());
+        info!(exchange = name, "Connecting");
+        AnyExchange::connect_orderbooks_async(exchange, markets.to_vec())
+            .await
+            .with_context(|| format!("Error connecting to {}", name))
     }
 
-    pub async fn save_bars_1min(mut rx: UnboundedReceiver<Orderbook>, pool: DbPool) -> Result<()> {
-        let map_olhc = Arc::new(Mutex::new(MapOLHC::new()));
-        let writer_map = map_olhc.clone();
-        let saver_map = map_olhc.clone();
+    // Retries until the connection is back, returns false if shutdown came first
+    async fn reconnect(exchange: &mut dyn Exchange, markets: &[String], shutdown: &CancellationToken) -> bool {
+        loop {
+            match Self::connect_to(exchange, markets).await {
+                Ok(()) => return true,
+                Err(e) => warn!(error = format!("{:#}", e), retry_in_secs = RECONNECT_DELAY.as_secs(), "Reconnecting failed"),
+            }
 
-        let writer = tokio::spawn(async move {
-            loop {
-                if let Some(orderbook) = rx.recv().await {
-                    OLHC::update_map(writer_map.clone(), orderbook).await;
-                }
+            tokio::select! {
+                _ = tokio::time::sleep(RECONNECT_DELAY) => {},
+                _ = shutdown.cancelled() => return false,
             }
-        });
+        }
+    }
 
-        tokio::spawn(async move {
-            let mut ticker = tokio::time::interval(Duration::from_secs(60));
-            ticker.tick().await;
-            loop {
-                ticker.tick().await;
+    // Writes the bars to every sink at each minute boundary until shutdown, while `leadership` allows it.
+    // On shutdown waits for the feeds to stop, flushes the unfinished bars as partial and waits for all
+    // writes still in flight
+    pub async fn save_bars_1min(
+        aggregator: Aggregator,
+        sinks: Vec<Arc<dyn BarSink>>,
+        gap_policies: GapPolicies,
+        leadership: Leadership,
+        shutdown: CancellationToken,
+        feeds: Vec<JoinHandle<()>>,
+    ) -> Result<()> {
+        let mut writer = BarWriter::new(sinks, gap_policies, leadership);
 
-                let guard = {
-                    let mut guard = saver_map.lock().await;
-                    let data = guard.clone();
+        // The first minute is only partially covered since the engine started in the middle of it
+        let mut minute = minute_start(Utc::now());
+        let mut partial = true;
+        loop {
+            let next_minute = minute + TimeDelta::minutes(1);
+            let until_next_minute = (next_minute - Utc::now().naive_utc())
+                .to_std()
+                .unwrap_or_default();
+
+            tokio::select! {
+                _ = tokio::time::sleep(until_next_minute) => {},
+                _ = shutdown.cancelled() => break,
+            }
 
-                    guard.clear();
-                    data
-                };
+            writer.write_minute(&aggregator, minute, partial).await;
 
-                let pool = pool.clone();
+            for (name, stats) in aggregator.feed_stats() {
+                info!(
+                    exchange = name,
+                    received = stats.received,
+                    queued = stats.queued,
+                    dropped = stats.dropped,
+                    conflated = stats.conflated,
+                    "Feed stats"
+                );
+            }
+            writer.report();
 
-                tokio::task::spawn_blocking(move || {
-                    OLHC::save_map(guard, &mut pool.get().expect("Error getting DB connection"))
-                });
+            minute = next_minute;
+            partial = false;
+        }
 
-                println!("\nSaved data to the database\n");
+        info!("Waiting for exchange connections to close");
+        for feed in feeds {
+            if feed.await.is_err() {
+                warn!("Exchange connection task failed during shutdown");
             }
-        });
+        }
 
-        writer.await?;
-        Ok(())
+        writer.write_last(&aggregator, minute).await;
+        writer.finish().await
     }
 
-    pub async fn get_orderbooks_receiver(exchanges: Vec<Box<dyn Exchange>>) -> UnboundedReceiver<Orderbook> {
-        let (tx, rx) = unbounded_channel::<Orderbook>();
+    // Feeds captured frames through the same parsing and aggregation as live data. Minutes are closed
+    // by the receive time of the frames instead of the clock, so the same capture always gives the same bars.
+    // `speed` 1.0 replays at the original pace, 10.0 ten times faster, 0.0 as fast as possible
+    pub async fn replay(
+        files: Vec<PathBuf>,
+        exchanges: Vec<Box<dyn Exchange>>,
+        aggregator: Aggregator,
+        sinks: Vec<Arc<dyn BarSink>>,
+        gap_policies: GapPolicies,
+        speed: f64,
+    ) -> Result<()> {
+        let mut feeds = exchanges
+            .into_iter()
+            .map(|exchange| {
+                let publisher = aggregator.publisher(exchange.name());
+                (exchange.name(), (exchange, publisher))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let (frames_tx, mut frames) = mpsc::channel(1024);
+        let reader = tokio::task::spawn_blocking(move || read_frames(files, frames_tx));
+
+        let mut writer = BarWriter::new(sinks, gap_policies, Leadership::always());
+        let mut minute: Option<NaiveDateTime> = None;
+        let mut partial = true;
+        let mut previous: Option<i64> = None;
+        let (mut replayed, mut skipped, mut unreadable) = (0, 0, 0);
+
+        while let Some(frame) = frames.recv().await {
+            if speed > 0.0
+                && let Some(previous) = previous
+                && frame.received_at > previous
+            {
+                let pause = (frame.received_at - previous) as f64 / speed;
+                tokio::time::sleep(Duration::from_micros(pause as u64)).await;
+            }
+            previous = Some(previous.unwrap_or(frame.received_at).max(frame.received_at));
+
+            // Close every minute that ended before this frame, including minutes without any frames
+            let frame_minute = minute_start(frame.received_at());
+            let current = minute.get_or_insert(frame_minute);
+            while *current < frame_minute {
+                writer.write_minute(&aggregator, *current, partial).await;
+                *current += TimeDelta::minutes(1);
+                partial = false;
+            }
+
+            // Control frames only mattered to the live connection
+            if frame.kind != FrameKind::Text {
+                continue;
+            }
+            let Some((exchange, publisher)) = feeds.get_mut(frame.exchange.as_str()) else {
+                skipped += 1;
+                continue;
+            };
+
+            // Counted like live, where an unreadable frame is dropped and the feed goes on
+            let data = match Self::classify(Some(Ok(Message::text(frame.data)))) {
+                Ok(Some(MessageType::Data(data))) => Some(data),
+                Ok(_) => None,
+                Err(e) => {
+                    metrics::MESSAGES_RECEIVED.with_label_values(&[exchange.name()]).inc();
+                    metrics::MESSAGES_UNPARSED.with_label_values(&[exchange.name()]).inc();
+                    warn!(exchange = exchange.name(), error = %e, "Dropping a frame that isn't JSON");
+                    unreadable += 1;
+                    None
+                },
+            };
+            if let Some(data) = data
+                && let Some(orderbook) = exchange.parse_orderbook_data(&data)
+                && !publisher.publish(orderbook).await
+            {
+                bail!("Error sending orderbook from {}", frame.exchange);
+            }
+            replayed += 1;
+        }
+
+        reader.await??;
+        info!(replayed, skipped, unreadable, "Replay finished, frames of exchanges that aren't configured were skipped");
+
+        if let Some(minute) = minute {
+            writer.write_last(&aggregator, minute).await;
+        }
+        writer.finish().await
+    }
 
-        let ping_interval = load_ping_interval().expect("Error loading ping interval");
+    // Spawns a reading task per feed, every parsed orderbook goes straight to the aggregator shards
+    // The tasks close their connections and return once `shutdown` is cancelled
+    // With `capture` every inbound frame is recorded before it's parsed
+    pub fn publish_orderbooks(
+        feeds: Vec<Feed>,
+        aggregator: &Aggregator,
+        shutdown: CancellationToken,
+        capture: Option<Capture>,
+    ) -> Vec<JoinHandle<()>> {
+        let mut tasks = Vec::new();
 
-        for mut exchange in exchanges {
+        for Feed { mut exchange, mut markets, heartbeat, shard, mut commands, outages } in feeds {
             let name = exchange.name();
+            for market in markets.iter() {
+                aggregator.expect(name, &market_symbol(market));
+            }
+
+            let mut publisher = aggregator.publisher(name);
+            let shutdown = shutdown.clone();
+            let capture = capture.clone();
 
-            let tx = tx.clone();
+            let connection = next_connection_id();
+            let span = info_span!("feed", exchange = name, shard, connection, markets = %markets.join(","));
 
-            tokio::spawn(async move {
+            tasks.push(tokio::spawn(async move {
                 let mut start = Instant::now();
+                let mut connection = connection;
+                // Clocks of every subscribed market, so the ones that never get an update go stale
+                let mut clocks = markets
+                    .iter()
+                    .map(|market| {
+                        let symbol = market_symbol(market);
+                        let clock = metrics::last_message_clock(name, &symbol);
+                        (symbol, clock)
+                    })
+                    .collect::<HashMap<_, _>>();
+                // Unsubscribed markets, their updates still in flight are dropped
+                let mut removed = HashSet::new();
+                let received = metrics::MESSAGES_RECEIVED.with_label_values(&[name]);
+                let parsed = metrics::MESSAGES_PARSED.with_label_values(&[name]);
+                let unparsed = metrics::MESSAGES_UNPARSED.with_label_values(&[name]);
+                let reconnects = metrics::RECONNECTS.with_label_values(&[name]);
+                let mut feed = FeedGuard::start(name, shard, connection);
                 loop {
-                    let data = Engine::read_orderbooks(exchange.read_stream())
-                        .await
-                        .expect(format!("Error reading orderbooks from {}", name).as_str());
+                    let frame = tokio::select! {
+                        frame = Engine::next_frame(exchange.read_stream()) => frame,
+                        Some(command) = commands.recv() => {
+                            Self::change_markets(exchange.as_mut(), &mut markets, &mut clocks, &mut removed, command).await;
+                            continue;
+                        },
+                        _ = shutdown.cancelled() => break,
+                    };
+                    if let (Some(capture), Some(frame)) = (&capture, &frame) {
+                        capture.record(name, connection, frame).await;
+                    }
+                    let data = match Self::classify(frame) {
+                        Ok(data) => data,
+                        // An unreadable frame only costs itself, the feed goes on with the next one
+                        Err(e) => {
+                            received.inc();
+                            unparsed.inc();
+                            warn!(error = %e, "Dropping a frame that isn't JSON");
+                            None
+                        },
+                    };
 
+                    // Set when the connection is gone, it's reconnected after this frame
+                    let mut lost = false;
                     if let Some(data) = data {
 
                         match data {
                             MessageType::Data(data) => {
-                                if let Some(orderbook) = exchange.parse_orderbook_data(&data) {
-                                    tx.send(orderbook)
-                                        .expect(format!("Error sending orderbook from {}", name).as_str());
+                                received.inc();
+                                match exchange.parse_orderbook_data(&data) {
+                                    Some(orderbook) if removed.contains(&*orderbook.symbol) => parsed.inc(),
+                                    Some(orderbook) => {
+                                        parsed.inc();
+                                        // A symbol the exchange spells other than subscribed gets its clock now
+                                        if !clocks.contains_key(&*orderbook.symbol) {
+                                            debug!(market = %orderbook.symbol, "First update");
+                                            clocks.insert(orderbook.symbol.to_string(), metrics::last_message_clock(name, &orderbook.symbol));
+                                        }
+                                        metrics::touch(&clocks[&*orderbook.symbol]);
+
+                                        // Only fails once the aggregator is gone, nothing is left to publish to
+                                        if !publisher.publish(orderbook).await {
+                                            error!("Aggregator stopped, closing the feed");
+                                            break;
+                                        }
+                                    },
+                                    None => unparsed.inc(),
                                 }
                             },
                             MessageType::Ping(payload) => {
                                 if let Some(write_stream) = exchange.write_stream().as_mut() {
-                                    write_stream.send(Message::Pong(payload))
-                                        .await
-                                        .expect("Error responding to ping");
-                                    println!("Responding to ping from {}", name);
+                                    debug!("Responding to ping");
+                                    if let Err(e) = write_stream.send(Message::Pong(payload)).await {
+                                        warn!(error = %e, "Error responding to ping");
+                                        lost = true;
+                                    }
                                 }
                             },
                             MessageType::Pong => {
-                                println!("Received pong from {}", name);
+                                debug!("Received pong");
                             }
                             Closed => {
-                                Self::connect_to(exchange.as_mut()).await;
+                                warn!("Connection closed");
+                                lost = true;
                             }
                         }
                     }
 
-                    if start.elapsed().as_secs() >= ping_interval {
+                    if !lost && start.elapsed() >= heartbeat {
                         if let Some(write_stream) = exchange.write_stream().as_mut() {
-                            println!("Sending ping to {}", name);
-                            write_stream.send(Message::Ping(Bytes::new())).await.expect("Error sending ping");
+                            debug!("Sending ping");
+                            if let Err(e) = write_stream.send(Message::Ping(Bytes::new())).await {
+                                warn!(error = %e, "Error sending ping");
+                                lost = true;
+                            }
                         }
                         start = Instant::now();
                     }
+
+                    if lost {
+                        reconnects.inc();
+                        warn!("Reconnecting");
+                        feed.reconnecting();
+                        let disconnected_at = Utc::now();
+                        // The bars of the minutes the connection was lost and got back in only
+                        // cover part of them, partial bars are replaced by the backfill
+                        publisher.interrupt(&markets);
+                        if !Self::reconnect(exchange.as_mut(), &markets, &shutdown).await {
+                            break;
+                        }
+                        publisher.interrupt(&markets);
+                        connection = next_connection_id();
+                        feed.connected(connection);
+                        tracing::Span::current().record("connection", connection);
+                        info!("Reconnected");
+                        Self::report_outage(&outages, name, &markets, disconnected_at);
+                    }
                 }
-            });
+
+                Self::close(exchange.as_mut()).await;
+            }.instrument(span)));
 
         }
 
-        rx
+        tasks
     }
 
-    async fn read_orderbooks(r_stream: &mut Option<ReadStream>) -> Result<Option<MessageType>> {
-        if let Some(r_stream) = r_stream {
-            if let Some(msg) = (*r_stream).next().await {
-                match msg {
-                    Ok(Message::Text(text)) => {
-                        let data = serde_json::from_str::<HashMap<String, Value>>(&text)?;
-
-                        return Ok(Some(MessageType::Data(data)));
-                    },
-                    Ok(Message::Close(_)) => {
-                        return Ok(Some(Closed));
-                    },
-                    Ok(Message::Ping(p)) => {
-                        return Ok(Some(MessageType::Ping(p)));
-                    },
-                    Ok(Message::Pong(_)) => {
-                        return Ok(Some(MessageType::Pong));
-                    }
-                    Err(e) => {
-                        println!("Error receiving message: {}", e);
-                        return Ok(Some(Closed));
-                    },
-                    _ => {
-                        println!("Received unexpected message from the server");
-                    }
+    // Subscribes or unsubscribes the feed's connection. `markets` is what a reconnect subscribes to
+    async fn change_markets(
+        exchange: &mut dyn Exchange,
+        markets: &mut Vec<String>,
+        clocks: &mut HashMap<String, Arc<AtomicI64>>,
+        removed: &mut HashSet<String>,
+        command: FeedCommand,
+    ) {
+        let name = exchange.name();
+        match command {
+            FeedCommand::Subscribe(added) => {
+                if let Err(e) = AnyExchange::subscribe(exchange, &added).await {
+                    warn!(error = %e, "Error subscribing to added markets");
+                }
+                for market in added {
+                    let symbol = market_symbol(&market);
+                    removed.remove(&symbol);
+                    clocks.insert(symbol.clone(), metrics::last_message_clock(name, &symbol));
+                    markets.push(market);
+                }
+            },
+            FeedCommand::Unsubscribe(unsubscribed) => {
+                if let Err(e) = AnyExchange::unsubscribe(exchange, &unsubscribed).await {
+                    warn!(error = %e, "Error unsubscribing from removed markets");
+                }
+                for market in unsubscribed.iter() {
+                    let symbol = market_symbol(market);
+                    clocks.remove(&symbol);
+                    metrics::forget_market(name, &symbol);
+                    removed.insert(symbol);
+                }
+                markets.retain(|market| !unsubscribed.contains(market));
+            },
+        }
+
+        tracing::Span::current().record("markets", tracing::field::display(markets.join(",")));
+        info!("Markets changed");
+    }
+
+    // Hands the minutes from `disconnected_at` until now to the backfill
+    fn report_outage(outages: &mpsc::Sender<Outage>, exchange: &'static str, markets: &[String], disconnected_at: DateTime<Utc>) {
+        let outage = Outage {
+            exchange,
+            markets: markets.to_vec(),
+            from: minute_start(disconnected_at),
+            to: minute_start(Utc::now()),
+        };
+        match outages.try_send(outage) {
+            Ok(()) => {},
+            Err(TrySendError::Full(outage)) => warn!(from = %outage.from, to = %outage.to, "Too many outages waiting, not backfilling this one"),
+            // Nothing backfills the outages
+            Err(TrySendError::Closed(_)) => {},
+        }
+    }
+
+    // Sends a Close frame so the exchange sees a clean disconnect
+    async fn close(exchange: &mut dyn Exchange) {
+        let name = exchange.name();
+        if let Some(write_stream) = exchange.write_stream().as_mut() {
+            match tokio::time::timeout(Duration::from_secs(5), write_stream.close()).await {
+                Ok(Ok(())) => info!(exchange = name, "Closed connection"),
+                Ok(Err(e)) => warn!(exchange = name, error = %e, "Error closing connection"),
+                Err(_) => warn!(exchange = name, "Timed out closing connection"),
+            }
+        }
+    }
+
+    async fn next_frame(r_stream: &mut Option<ReadStream>) -> Option<Result<Message, WsError>> {
+        match r_stream {
+            Some(r_stream) => r_stream.next().await,
+            None => None,
+        }
+    }
+
+    fn classify(frame: Option<Result<Message, WsError>>) -> Result<Option<MessageType>> {
+        if let Some(msg) = frame {
+            match msg {
+                Ok(Message::Text(text)) => {
+                    let data = serde_json::from_str::<HashMap<String, Value>>(&text)?;
+
+                    return Ok(Some(MessageType::Data(data)));
+                },
+                Ok(Message::Close(_)) => {
+                    return Ok(Some(Closed));
+                },
+                Ok(Message::Ping(p)) => {
+                    return Ok(Some(MessageType::Ping(p)));
+                },
+                Ok(Message::Pong(_)) => {
+                    return Ok(Some(MessageType::Pong));
+                }
+                Err(e) => {
+                    warn!(error = %e, "Error receiving message");
+                    return Ok(Some(Closed));
+                },
+                _ => {
+                    debug!("Received unexpected message from the server");
                 }
             }
         }
@@ -185,3 +527,10 @@ impl Engine {
         Ok(None)
     }
 }
+
+// Start of the minute `time` falls in
+fn minute_start(time: DateTime<Utc>) -> NaiveDateTime {
+    time.duration_trunc(TimeDelta::minutes(1))
+        .expect("Error truncating time to minute")
+        .naive_utc()
+}
@@ -0,0 +1,191 @@
+use crate::aggregator::{Instrument, InstrumentId};
+use crate::structs::{ClosedBar, INSERT_CHUNK, OLHC};
+
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::bail;
+use chrono::NaiveDateTime;
+use diesel::{PgConnection, QueryResult, RunQueryDsl};
+use db::models::NewGap1min;
+use db::schema::gaps_1min;
+use exchange::structs::{market_symbol, Price};
+
+// What to do with a minute in which a market received no updates
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GapPolicy {
+    // Write a flat bar at the previous close, flagged as synthetic.
+    // A market without a previous close gets a gap record instead
+    CarryForward,
+    // Write a gap record and no bar
+    #[default]
+    Record,
+    // Write nothing
+    Ignore,
+}
+
+impl FromStr for GapPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "carry_forward" => Ok(GapPolicy::CarryForward),
+            "record" => Ok(GapPolicy::Record),
+            "ignore" => Ok(GapPolicy::Ignore),
+            _ => bail!("Unknown gap policy {}, expected carry_forward, record or ignore", s),
+        }
+    }
+}
+
+// Gap policy of every market, the default unless the market's exchange section overrides it
+#[derive(Clone, Debug, Default)]
+pub struct GapPolicies {
+    default: GapPolicy,
+    // By lowercase exchange name and market symbol
+    markets: HashMap<(String, String), GapPolicy>,
+}
+
+impl GapPolicies {
+    pub fn new(default: GapPolicy) -> Self {
+        Self {
+            default,
+            markets: HashMap::new(),
+        }
+    }
+
+    // `market` as configured or as its updates name it, btc-usdt and BTCUSDT are the same market
+    pub fn set(&mut self, exchange: &str, market: &str, policy: GapPolicy) {
+        self.markets.insert((exchange.to_lowercase(), market_symbol(market)), policy);
+    }
+
+    pub fn policy(&self, exchange: &str, market: &str) -> GapPolicy {
+        self.markets
+            .get(&(exchange.to_lowercase(), market_symbol(market)))
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Gap {
+    pub exchange: &'static str,
+    pub market: Arc<str>,
+    pub timestamp: NaiveDateTime,
+}
+
+// Inserts at most INSERT_CHUNK rows in one statement, gaps already stored are skipped
+pub fn insert_gaps(rows: &[NewGap1min], conn: &mut PgConnection) -> QueryResult<usize> {
+    diesel::insert_into(gaps_1min::table)
+        .values(rows)
+        .on_conflict_do_nothing()
+        .execute(conn)
+}
+
+impl Gap {
+    // Saves the gaps with multi-row inserts, returns the number of rows written
+    pub fn save_all(gaps: &[Gap], conn: &mut PgConnection) -> QueryResult<usize> {
+        let mut saved = 0;
+
+        for chunk in gaps.chunks(INSERT_CHUNK) {
+            let rows = chunk
+                .iter()
+                .map(|gap| NewGap1min::new(gap.exchange, &gap.market, gap.timestamp))
+                .collect::<Vec<NewGap1min>>();
+
+            saved += insert_gaps(&rows, conn)?;
+        }
+
+        Ok(saved)
+    }
+}
+
+struct TrackedInstrument {
+    exchange: &'static str,
+    market: Arc<str>,
+    policy: GapPolicy,
+    // None until the market produced a bar
+    last_close: Option<Price>,
+}
+
+// Remembers the subscribed markets and every market that produced a bar, and fills the minutes
+// in which they were silent
+#[derive(Default)]
+pub struct GapTracker {
+    policies: GapPolicies,
+    instruments: HashMap<InstrumentId, TrackedInstrument>,
+}
+
+impl GapTracker {
+    pub fn new(policies: GapPolicies) -> Self {
+        Self {
+            policies,
+            instruments: HashMap::new(),
+        }
+    }
+
+    // Adds synthetic bars to `bars` and returns the gaps to record for the minute at `timestamp`.
+    // `expected` markets are tracked even before their first bar, so a feed that's down from the start
+    // leaves gaps too. Silent `retired` markets are forgotten instead, until they produce a bar again
+    pub fn fill(
+        &mut self,
+        bars: &mut Vec<ClosedBar>,
+        timestamp: NaiveDateTime,
+        expected: &[(InstrumentId, Instrument)],
+        retired: &HashSet<InstrumentId>,
+    ) -> Vec<Gap> {
+        let mut updated = HashSet::with_capacity(bars.len());
+
+        for bar in bars.iter() {
+            updated.insert(bar.instrument);
+            self.instruments
+                .entry(bar.instrument)
+                .and_modify(|tracked| tracked.last_close = Some(bar.olhc.close))
+                .or_insert_with(|| TrackedInstrument {
+                    exchange: bar.exchange,
+                    market: bar.market.clone(),
+                    policy: self.policies.policy(bar.exchange, &bar.market),
+                    last_close: Some(bar.olhc.close),
+                });
+        }
+        for (id, instrument) in expected {
+            self.instruments
+                .entry(*id)
+                .or_insert_with(|| TrackedInstrument {
+                    exchange: instrument.exchange,
+                    market: instrument.market.clone(),
+                    policy: self.policies.policy(instrument.exchange, &instrument.market),
+                    last_close: None,
+                });
+        }
+
+        self.instruments.retain(|id, _| updated.contains(id) || !retired.contains(id));
+
+        let mut gaps = Vec::new();
+        for (id, tracked) in self.instruments.iter() {
+            if updated.contains(id) {
+                continue;
+            }
+
+            match (tracked.policy, tracked.last_close) {
+                (GapPolicy::CarryForward, Some(last_close)) => bars.push(ClosedBar {
+                    instrument: *id,
+                    exchange: tracked.exchange,
+                    market: tracked.market.clone(),
+                    timestamp,
+                    olhc: OLHC::new(last_close),
+                    partial: false,
+                    synthetic: true,
+                }),
+                (GapPolicy::CarryForward | GapPolicy::Record, _) => gaps.push(Gap {
+                    exchange: tracked.exchange,
+                    market: tracked.market.clone(),
+                    timestamp,
+                }),
+                (GapPolicy::Ignore, _) => {}
+            }
+        }
+
+        gaps
+    }
+}
@@ -0,0 +1,274 @@
+use crate::metrics;
+use crate::status::{self, Role};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use diesel::dsl::max;
+use diesel::dsl::sql;
+use diesel::sql_types::{BigInt, Integer};
+use diesel::{define_sql_function, Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use db::schema::bars_1min;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+// How often a standby tries to take the lock and the leader checks the connection holding it
+const ELECTION_INTERVAL: Duration = Duration::from_secs(5);
+
+// A round that takes longer, connecting included, counts as a lost connection
+const ROUND_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Connection parameters of the lock's session, so a dead network is noticed within seconds
+// instead of the OS defaults of hours. Parameters already in the url are kept
+const LOCK_CONNECTION_PARAMS: [(&str, &str); 6] = [
+    ("connect_timeout", "5"),
+    ("keepalives", "1"),
+    ("keepalives_idle", "5"),
+    ("keepalives_interval", "2"),
+    ("keepalives_count", "3"),
+    ("tcp_user_timeout", "10000"),
+];
+
+// A single statement of the lock's session never takes longer than a round
+const STATEMENT_TIMEOUT: &str = "5s";
+
+// The server ends the session when the process stops checking it, e.g. it hangs, which releases the lock
+const IDLE_TIMEOUT: &str = "20s";
+
+// First server_version_num with idle_session_timeout, PostgreSQL 14
+const IDLE_SESSION_TIMEOUT_VERSION: i32 = 140000;
+
+define_sql_function!(fn pg_try_advisory_lock(key: BigInt) -> Bool);
+
+// Whether this instance writes the bars. Without HA it always does, with HA only while it holds
+// the advisory lock; the other instances are standbys building the same bars without writing them
+#[derive(Clone)]
+pub struct Leadership {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    leader: AtomicBool,
+    // Last complete minute in the database when the lock was taken, until the writer picked it up
+    took_over: Mutex<Option<NaiveDateTime>>,
+}
+
+impl Leadership {
+    // Leader for good, no election
+    pub fn always() -> Self {
+        Self::new(true)
+    }
+
+    // Standby until it wins the election
+    pub fn standby() -> Self {
+        status::role(Role::Standby);
+        metrics::LEADER.set(0);
+        Self::new(false)
+    }
+
+    fn new(leader: bool) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                leader: AtomicBool::new(leader),
+                took_over: Mutex::new(None),
+            }),
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.inner.leader.load(Ordering::SeqCst)
+    }
+
+    // Returns the last minute the previous leader completed, once after taking over.
+    // NaiveDateTime::MIN when the database had no bars yet
+    pub(crate) fn take_over(&self) -> Option<NaiveDateTime> {
+        self.inner.took_over.lock().expect("Leadership lock poisoned").take()
+    }
+
+    fn promote(&self, last_written: Option<NaiveDateTime>) {
+        *self.inner.took_over.lock().expect("Leadership lock poisoned") = Some(last_written.unwrap_or(NaiveDateTime::MIN));
+        self.inner.leader.store(true, Ordering::SeqCst);
+        status::role(Role::Leader);
+        metrics::LEADER.set(1);
+    }
+
+    fn demote(&self) {
+        self.inner.leader.store(false, Ordering::SeqCst);
+        self.take_over();
+        status::role(Role::Standby);
+        metrics::LEADER.set(0);
+    }
+}
+
+enum Round {
+    // Took the lock, with the last complete minute in the database
+    Won(Option<NaiveDateTime>),
+    // Still holds the lock
+    Kept,
+    // Another instance holds it
+    Lost,
+}
+
+// Competes for the advisory lock `lock_key` until `release` is cancelled. The lock lives as long as the
+// session of a connection of its own, so it's given up when the process dies or loses the database.
+// Cancel `release` only after the last bars were written, the lock is released by closing the connection
+pub async fn run_election(database_url: String, lock_key: i64, leadership: Leadership, release: CancellationToken) {
+    let mut conn: Option<PgConnection> = None;
+    let url = lock_connection_url(&database_url);
+    info!(lock_key, "Standing for leader election");
+
+    loop {
+        let leader = leadership.is_leader();
+        let url = url.clone();
+        let round = tokio::task::spawn_blocking(move || {
+            let mut conn = match conn {
+                Some(conn) => conn,
+                None => match open_lock_session(&url) {
+                    Ok(conn) => conn,
+                    Err(e) => return (None, Err(e)),
+                },
+            };
+            match campaign(&mut conn, lock_key, leader) {
+                Ok(round) => (Some(conn), Ok(round)),
+                // A broken session no longer holds the lock, the next round starts over on a new one
+                Err(e) => (None, Err(e)),
+            }
+        });
+        // A hung round keeps its connection, it's closed whenever the blocking call returns
+        let (returned, round) = match tokio::time::timeout(ROUND_TIMEOUT, round).await {
+            Ok(Ok(finished)) => finished,
+            // The session went down with the task, so did the lock
+            Ok(Err(e)) => (None, Err(anyhow!("Election task failed: {}", e))),
+            Err(_) => (None, Err(anyhow!("The database didn't answer within {} seconds", ROUND_TIMEOUT.as_secs()))),
+        };
+        conn = returned;
+
+        match round {
+            Ok(Round::Won(last_written)) => {
+                info!(last_written = ?last_written, "Won the leader election, writing bars from now on");
+                leadership.promote(last_written);
+            },
+            Ok(Round::Kept) | Ok(Round::Lost) => {},
+            Err(e) if leader => {
+                error!(error = format!("{:#}", e), "Lost the connection holding the leader lock, stepping down");
+                leadership.demote();
+            },
+            Err(e) => warn!(error = format!("{:#}", e), "Error taking part in the leader election"),
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(ELECTION_INTERVAL) => {},
+            _ = release.cancelled() => break,
+        }
+    }
+
+    if leadership.is_leader() {
+        info!("Releasing the leader lock");
+    }
+    leadership.demote();
+}
+
+// Adds the keepalive and timeout parameters the url doesn't set yet, to a postgres:// url
+// or to a `key=value` connection string
+fn lock_connection_url(database_url: &str) -> String {
+    let uri = database_url.starts_with("postgres://") || database_url.starts_with("postgresql://");
+    let set = match uri {
+        true => database_url.split_once('?').map(|(_, query)| query).unwrap_or("").split('&').collect::<Vec<_>>(),
+        false => database_url.split_whitespace().collect(),
+    };
+    let set = set.iter().filter_map(|param| param.split('=').next()).collect::<Vec<_>>();
+
+    let mut url = database_url.to_string();
+    for (key, value) in LOCK_CONNECTION_PARAMS.iter().filter(|(key, _)| !set.contains(key)) {
+        match uri {
+            true => url.push(if url.contains('?') { '&' } else { '?' }),
+            false => url.push(' '),
+        }
+        url.push_str(&format!("{}={}", key, value));
+    }
+    url
+}
+
+fn open_lock_session(url: &str) -> Result<PgConnection> {
+    let mut conn = PgConnection::establish(url)?;
+    let version = diesel::select(sql::<Integer>("current_setting('server_version_num')::int")).get_result::<i32>(&mut conn)?;
+    if version < IDLE_SESSION_TIMEOUT_VERSION {
+        warn!(server_version_num = version, "PostgreSQL before 14 has no idle_session_timeout, the leader lock's session stays in a transaction instead");
+    }
+    for statement in lock_session_statements(version) {
+        diesel::sql_query(statement).execute(&mut conn)?;
+    }
+    Ok(conn)
+}
+
+// Settings of the lock's session on a server of `version` (server_version_num). Before PostgreSQL 14 the
+// session is kept in a transaction, which idle_in_transaction_session_timeout ends just the same. The rounds
+// only read, so the transaction neither holds back vacuum nor blocks other sessions
+fn lock_session_statements(version: i32) -> Vec<String> {
+    let mut statements = vec![format!("SET statement_timeout = '{}'", STATEMENT_TIMEOUT)];
+    match version >= IDLE_SESSION_TIMEOUT_VERSION {
+        true => statements.push(format!("SET idle_session_timeout = '{}'", IDLE_TIMEOUT)),
+        false => {
+            statements.push(format!("SET idle_in_transaction_session_timeout = '{}'", IDLE_TIMEOUT));
+            statements.push("BEGIN".to_string());
+        },
+    }
+    statements
+}
+
+fn campaign(conn: &mut PgConnection, lock_key: i64, leader: bool) -> Result<Round> {
+    if leader {
+        diesel::sql_query("SELECT 1").execute(conn)?;
+        return Ok(Round::Kept);
+    }
+
+    if !diesel::select(pg_try_advisory_lock(lock_key)).get_result::<bool>(conn)? {
+        return Ok(Round::Lost);
+    }
+
+    let last_written = bars_1min::table
+        .filter(bars_1min::partial.eq(false))
+        .select(max(bars_1min::timestamp))
+        .get_result::<Option<NaiveDateTime>>(conn)?;
+    Ok(Round::Won(last_written))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lock_connection_url, lock_session_statements};
+
+    const PARAMS: &str = "connect_timeout=5&keepalives=1&keepalives_idle=5&keepalives_interval=2&keepalives_count=3&tcp_user_timeout=10000";
+
+    #[test]
+    fn lock_urls_get_keepalives_and_timeouts() {
+        assert_eq!(lock_connection_url("postgres://app@db/crypto"), format!("postgres://app@db/crypto?{}", PARAMS));
+        assert_eq!(
+            lock_connection_url("postgres://app@db/crypto?sslmode=require"),
+            format!("postgres://app@db/crypto?sslmode=require&{}", PARAMS)
+        );
+        assert_eq!(
+            lock_connection_url("host=db dbname=crypto"),
+            format!("host=db dbname=crypto {}", PARAMS.replace('&', " "))
+        );
+    }
+
+    #[test]
+    fn parameters_of_the_url_are_kept() {
+        let url = lock_connection_url("postgres://app@db/crypto?keepalives_idle=30&connect_timeout=2");
+        assert!(url.starts_with("postgres://app@db/crypto?keepalives_idle=30&connect_timeout=2&keepalives=1&"), "{}", url);
+        assert_eq!(url.matches("keepalives_idle").count(), 1);
+        assert_eq!(url.matches("connect_timeout").count(), 1);
+    }
+
+    #[test]
+    fn sessions_before_postgres_14_time_out_in_a_transaction() {
+        assert_eq!(lock_session_statements(150004), vec!["SET statement_timeout = '5s'", "SET idle_session_timeout = '20s'"]);
+        assert_eq!(
+            lock_session_statements(130016),
+            vec!["SET statement_timeout = '5s'", "SET idle_in_transaction_session_timeout = '20s'", "BEGIN"]
+        );
+    }
+}
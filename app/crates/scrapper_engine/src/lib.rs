@@ -1,12 +1,22 @@
-use std::collections::HashMap;
 use futures_util::stream::SplitStream;
 use tokio::net::TcpStream;
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
-use crate::structs::OLHC;
 
-mod utils;
 pub mod engine;
+pub mod aggregator;
+pub mod gaps;
+pub mod queue;
+pub mod spill;
+pub mod sinks;
+pub mod capture;
+pub mod metrics;
+pub mod logging;
+pub mod status;
+pub mod config;
+pub mod reload;
+pub mod leader;
+pub mod backfill;
+mod writer;
 mod structs;
 
 type ReadStream = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
-type MapOLHC = HashMap<String, HashMap<String, OLHC>>;
@@ -0,0 +1,37 @@
+use crate::config::LoggingConfig;
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    // Human readable lines
+    Text,
+    // One JSON object per event with the fields of its spans, for log pipelines
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!("Unknown log format {}, expected text or json", s),
+        }
+    }
+}
+
+// Installs the global subscriber, the config was validated so the filter parses
+pub fn init_logging(config: &LoggingConfig) -> Result<()> {
+    let filter = EnvFilter::try_new(&config.filter).map_err(|e| anyhow!("Invalid log filter {}: {}", config.filter, e))?;
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match config.format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().with_current_span(false).with_span_list(true).try_init(),
+    }.map_err(|e| anyhow!("Error installing the log subscriber: {}", e))
+}
@@ -0,0 +1,243 @@
+use crate::aggregator::Aggregator;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+
+use chrono::{DateTime, Utc};
+use db::db::DbPool;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{exponential_buckets, register_histogram, register_int_counter_vec, register_int_gauge, CounterVec, GaugeVec, Histogram, IntCounterVec, IntGauge, Opts};
+use tracing::warn;
+
+// Metrics of the scraper, registered with the default Prometheus registry the api serves at /metrics
+
+pub static MESSAGES_RECEIVED: LazyLock<IntCounterVec> = LazyLock::new(|| register_int_counter_vec!(
+    "scraper_messages_received_total",
+    "Data messages received from the exchange websockets",
+    &["exchange"]
+).expect("Error registering metric"));
+
+pub static MESSAGES_PARSED: LazyLock<IntCounterVec> = LazyLock::new(|| register_int_counter_vec!(
+    "scraper_messages_parsed_total",
+    "Data messages parsed into an orderbook update",
+    &["exchange"]
+).expect("Error registering metric"));
+
+pub static MESSAGES_UNPARSED: LazyLock<IntCounterVec> = LazyLock::new(|| register_int_counter_vec!(
+    "scraper_messages_unparsed_total",
+    "Data messages dropped because they weren't an orderbook update, e.g. subscription acks",
+    &["exchange"]
+).expect("Error registering metric"));
+
+pub static RECONNECTS: LazyLock<IntCounterVec> = LazyLock::new(|| register_int_counter_vec!(
+    "scraper_reconnects_total",
+    "Reconnects after an exchange closed the connection or it failed",
+    &["exchange"]
+).expect("Error registering metric"));
+
+pub static RECORDS_WRITTEN: LazyLock<IntCounterVec> = LazyLock::new(|| register_int_counter_vec!(
+    "scraper_records_written_total",
+    "Bars and gaps written, per sink",
+    &["sink"]
+).expect("Error registering metric"));
+
+pub static SINK_WRITE_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| register_int_counter_vec!(
+    "scraper_sink_write_failures_total",
+    "Minutes a sink failed to write",
+    &["sink"]
+).expect("Error registering metric"));
+
+pub static DB_INSERT_SECONDS: LazyLock<Histogram> = LazyLock::new(|| register_histogram!(
+    "scraper_db_insert_seconds",
+    "Time taken to insert the bars and gaps of a minute into the database",
+    exponential_buckets(0.001, 2.0, 14).expect("Invalid buckets")
+).expect("Error registering metric"));
+
+pub static LEADER: LazyLock<IntGauge> = LazyLock::new(|| register_int_gauge!(
+    "scraper_leader",
+    "1 while this scraper writes the bars, 0 while it's a standby"
+).expect("Error registering metric"));
+
+pub static SPILL_PENDING_BYTES: LazyLock<IntGauge> = LazyLock::new(|| register_int_gauge!(
+    "scraper_spill_pending_bytes",
+    "Size of the minutes waiting in the spill file for the database to come back"
+).expect("Error registering metric"));
+
+pub static SPILL_MINUTES: LazyLock<IntCounterVec> = LazyLock::new(|| register_int_counter_vec!(
+    "scraper_spill_minutes_total",
+    "Minutes spilled to disk while the database was unavailable, replayed from it, or discarded",
+    &["event"]
+).expect("Error registering metric"));
+
+pub static BACKFILLED_BARS: LazyLock<IntCounterVec> = LazyLock::new(|| register_int_counter_vec!(
+    "scraper_backfilled_bars_total",
+    "Bars written from the klines of an exchange's REST api",
+    &["exchange"]
+).expect("Error registering metric"));
+
+// Time of the last update per (exchange, market), in milliseconds since the epoch
+type LastMessages = Mutex<HashMap<(&'static str, String), Arc<AtomicI64>>>;
+
+static LAST_MESSAGES: LazyLock<LastMessages> = LazyLock::new(|| {
+    register(Sampled::gauge(
+        "scraper_last_message_age_seconds",
+        "Seconds since the last orderbook update of a market",
+        &["exchange", "market"],
+        || {
+            let now = Utc::now().timestamp_millis();
+            LAST_MESSAGES.lock().expect("Last messages lock poisoned")
+                .iter()
+                .map(|(key, last)| (key, last.load(Ordering::Relaxed)))
+                .filter(|(_, last)| *last > 0)
+                .map(|((exchange, market), last)| {
+                    let age = (now - last) as f64 / 1000.0;
+                    (vec![exchange.to_string(), market.clone()], age)
+                })
+                .collect()
+        },
+    ));
+    Mutex::new(HashMap::new())
+});
+
+// Clock a feed stamps with the time of every update of the market, feeds keep it to skip the lookup
+// A new clock starts at the time it's taken, i.e. the subscription, so a market that never
+// gets an update still ages and goes stale
+pub fn last_message_clock(exchange: &'static str, market: &str) -> Arc<AtomicI64> {
+    LAST_MESSAGES.lock().expect("Last messages lock poisoned")
+        .entry((exchange, market.to_string()))
+        .or_insert_with(|| Arc::new(AtomicI64::new(Utc::now().timestamp_millis())))
+        .clone()
+}
+
+// Drops the clock of a market that's no longer scraped
+pub fn forget_market(exchange: &'static str, market: &str) {
+    LAST_MESSAGES.lock().expect("Last messages lock poisoned").remove(&(exchange, market.to_string()));
+}
+
+// Time of the last update of every market seen so far, as (exchange, market, time)
+pub fn last_messages() -> Vec<(&'static str, String, DateTime<Utc>)> {
+    LAST_MESSAGES.lock().expect("Last messages lock poisoned")
+        .iter()
+        .filter_map(|((exchange, market), last)| {
+            let last = DateTime::from_timestamp_millis(last.load(Ordering::Relaxed)).filter(|last| last.timestamp() > 0)?;
+            Some((*exchange, market.clone(), last))
+        })
+        .collect()
+}
+
+pub fn touch(clock: &AtomicI64) {
+    clock.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+}
+
+// Exposes the queue depth and overload drops of the aggregator the engine runs with
+pub fn register_aggregator(aggregator: &Aggregator) {
+    let queued = aggregator.clone();
+    register(Sampled::gauge(
+        "scraper_aggregator_queue_depth",
+        "Updates waiting in the aggregator shards",
+        &[],
+        move || vec![(vec![], queued.queue_depth() as f64)],
+    ));
+
+    let feeds = aggregator.clone();
+    register(Sampled::counter(
+        "scraper_updates_dropped_total",
+        "Orderbook updates the aggregator dropped or conflated because a shard was full",
+        &["exchange", "reason"],
+        move || {
+            let mut dropped = HashMap::<(&str, &str), f64>::new();
+            for (exchange, stats) in feeds.feed_stats() {
+                *dropped.entry((exchange, "dropped")).or_default() += stats.dropped as f64;
+                *dropped.entry((exchange, "conflated")).or_default() += stats.conflated as f64;
+            }
+            dropped.into_iter()
+                .map(|((exchange, reason), count)| (vec![exchange.to_string(), reason.to_string()], count))
+                .collect()
+        },
+    ));
+}
+
+// Exposes how many connections of each pool are in use, a pool that's always full makes writes and queries wait
+pub fn register_pools(pools: &[(&'static str, &DbPool)]) {
+    let pools = pools.iter().map(|(name, pool)| (*name, (*pool).clone())).collect::<Vec<_>>();
+    register(Sampled::gauge(
+        "db_pool_connections",
+        "Database connections of each pool by state, plus the maximum",
+        &["pool", "state"],
+        move || {
+            pools.iter().flat_map(|(name, pool)| {
+                let state = pool.state();
+                [
+                    ("idle", state.idle_connections as f64),
+                    ("in_use", (state.connections - state.idle_connections) as f64),
+                    ("max", pool.max_size() as f64),
+                ].map(|(label, value)| (vec![name.to_string(), label.to_string()], value))
+            }).collect()
+        },
+    ));
+}
+
+fn register(collector: Sampled) {
+    if let Err(e) = prometheus::register(Box::new(collector)) {
+        warn!(error = %e, "Error registering metric");
+    }
+}
+
+type Sample = Box<dyn Fn() -> Vec<(Vec<String>, f64)> + Send + Sync>;
+
+// Metric whose values are read when it's scraped, one per label set
+struct Sampled {
+    desc: Desc,
+    opts: Opts,
+    labels: &'static [&'static str],
+    counter: bool,
+    sample: Sample,
+}
+
+impl Sampled {
+    fn gauge(name: &str, help: &str, labels: &'static [&'static str], sample: impl Fn() -> Vec<(Vec<String>, f64)> + Send + Sync + 'static) -> Self {
+        Self::new(name, help, labels, false, Box::new(sample))
+    }
+
+    fn counter(name: &str, help: &str, labels: &'static [&'static str], sample: impl Fn() -> Vec<(Vec<String>, f64)> + Send + Sync + 'static) -> Self {
+        Self::new(name, help, labels, true, Box::new(sample))
+    }
+
+    fn new(name: &str, help: &str, labels: &'static [&'static str], counter: bool, sample: Sample) -> Self {
+        let opts = Opts::new(name, help);
+        let desc = Desc::new(
+            name.to_string(),
+            help.to_string(),
+            labels.iter().map(|label| label.to_string()).collect(),
+            HashMap::new(),
+        ).expect("Invalid metric description");
+
+        Self { desc, opts, labels, counter, sample }
+    }
+}
+
+impl Collector for Sampled {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![&self.desc]
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let samples = (self.sample)();
+
+        if self.counter {
+            let metric = CounterVec::new(self.opts.clone(), self.labels).expect("Invalid metric");
+            for (labels, value) in samples {
+                metric.with_label_values(&labels).inc_by(value);
+            }
+            metric.collect()
+        } else {
+            let metric = GaugeVec::new(self.opts.clone(), self.labels).expect("Invalid metric");
+            for (labels, value) in samples {
+                metric.with_label_values(&labels).set(value);
+            }
+            metric.collect()
+        }
+    }
+}
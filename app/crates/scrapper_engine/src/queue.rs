@@ -0,0 +1,350 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use anyhow::bail;
+use exchange::structs::Price;
+use tokio::sync::{Notify, Semaphore};
+
+use crate::aggregator::InstrumentId;
+
+// What a feed does when the shard it publishes to can't keep up
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverloadPolicy {
+    // Wait for free space, slowing the feed down
+    Block,
+    // Discard the oldest queued update to make room
+    DropOldest,
+    // Replace the queued update of the same instrument with the latest one,
+    // falls back to dropping the oldest update if none is queued
+    Conflate,
+}
+
+impl FromStr for OverloadPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "block" => Ok(OverloadPolicy::Block),
+            "drop_oldest" => Ok(OverloadPolicy::DropOldest),
+            "conflate" => Ok(OverloadPolicy::Conflate),
+            _ => bail!("Unknown overload policy {}, expected block, drop_oldest or conflate", s),
+        }
+    }
+}
+
+// Counters of a single exchange feed
+#[derive(Default, Debug)]
+pub struct FeedStats {
+    pub received: AtomicU64,
+    pub queued: AtomicU64,
+    pub dropped: AtomicU64,
+    pub conflated: AtomicU64,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FeedStatsSnapshot {
+    pub received: u64,
+    pub queued: u64,
+    pub dropped: u64,
+    pub conflated: u64,
+}
+
+impl FeedStats {
+    pub fn snapshot(&self) -> FeedStatsSnapshot {
+        FeedStatsSnapshot {
+            received: self.received.load(Ordering::Relaxed),
+            queued: self.queued.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            conflated: self.conflated.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Index of a feed in the aggregator's feed list
+pub type FeedId = usize;
+
+pub type Feeds = Arc<RwLock<Vec<(&'static str, Arc<FeedStats>)>>>;
+
+pub struct Tick {
+    pub instrument: InstrumentId,
+    pub price: Price,
+    pub feed: FeedId,
+}
+
+pub enum ShardCommand<F> {
+    Tick(Tick),
+    Control(F),
+}
+
+struct State<F> {
+    items: VecDeque<ShardCommand<F>>,
+    ticks: usize,
+    closed: bool,
+}
+
+// Bounded multi-producer queue in front of a shard. Only ticks count towards the capacity,
+// control commands are never dropped so a flush can't get lost under load
+pub struct ShardQueue<F> {
+    state: Mutex<State<F>>,
+    not_empty: Notify,
+    free_slots: Semaphore,
+    capacity: usize,
+    policy: OverloadPolicy,
+    feeds: Feeds,
+}
+
+impl<F> ShardQueue<F> {
+    pub fn new(capacity: usize, policy: OverloadPolicy, feeds: Feeds) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            state: Mutex::new(State {
+                items: VecDeque::with_capacity(capacity),
+                ticks: 0,
+                closed: false,
+            }),
+            not_empty: Notify::new(),
+            free_slots: Semaphore::new(capacity),
+            capacity,
+            policy,
+            feeds,
+        }
+    }
+
+    // Returns false once the queue is closed
+    pub async fn push_tick(&self, tick: Tick, stats: &FeedStats) -> bool {
+        if self.policy == OverloadPolicy::Block {
+            match self.free_slots.acquire().await {
+                Ok(permit) => permit.forget(),
+                Err(_) => return false,
+            }
+        }
+
+        let mut discarded = None;
+        {
+            let mut state = self.state.lock().expect("Shard queue lock poisoned");
+            if state.closed {
+                return false;
+            }
+
+            if self.policy != OverloadPolicy::Block && state.ticks >= self.capacity {
+                if self.policy == OverloadPolicy::Conflate
+                    && let Some(queued) = Self::find_queued(&mut state.items, tick.instrument)
+                {
+                    queued.price = tick.price;
+                    stats.conflated.fetch_add(1, Ordering::Relaxed);
+                    return true;
+                }
+
+                discarded = Self::remove_oldest_tick(&mut state);
+            }
+
+            state.items.push_back(ShardCommand::Tick(tick));
+            state.ticks += 1;
+        }
+
+        stats.queued.fetch_add(1, Ordering::Relaxed);
+        if let Some(feed) = discarded {
+            self.feed_stats(feed).dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        self.not_empty.notify_one();
+        true
+    }
+
+    pub fn push_control(&self, command: F) -> bool {
+        {
+            let mut state = self.state.lock().expect("Shard queue lock poisoned");
+            if state.closed {
+                return false;
+            }
+            state.items.push_back(ShardCommand::Control(command));
+        }
+
+        self.not_empty.notify_one();
+        true
+    }
+
+    // Waits for the next command, returns None once the queue is closed and drained
+    pub async fn pop(&self) -> Option<ShardCommand<F>> {
+        loop {
+            {
+                let mut state = self.state.lock().expect("Shard queue lock poisoned");
+                if let Some(command) = state.items.pop_front() {
+                    if let ShardCommand::Tick(_) = command {
+                        state.ticks -= 1;
+                        if self.policy == OverloadPolicy::Block {
+                            self.free_slots.add_permits(1);
+                        }
+                    }
+                    return Some(command);
+                }
+
+                if state.closed {
+                    return None;
+                }
+            }
+
+            self.not_empty.notified().await;
+        }
+    }
+
+    pub fn close(&self) {
+        self.state.lock().expect("Shard queue lock poisoned").closed = true;
+        self.free_slots.close();
+        self.not_empty.notify_one();
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().expect("Shard queue lock poisoned").ticks
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn find_queued(items: &mut VecDeque<ShardCommand<F>>, instrument: InstrumentId) -> Option<&mut Tick> {
+        items.iter_mut().rev().find_map(|command| match command {
+            ShardCommand::Tick(tick) if tick.instrument == instrument => Some(tick),
+            _ => None,
+        })
+    }
+
+    fn remove_oldest_tick(state: &mut State<F>) -> Option<FeedId> {
+        let index = state.items.iter().position(|command| matches!(command, ShardCommand::Tick(_)))?;
+        match state.items.remove(index)? {
+            ShardCommand::Tick(tick) => {
+                state.ticks -= 1;
+                Some(tick.feed)
+            },
+            ShardCommand::Control(_) => None,
+        }
+    }
+
+    fn feed_stats(&self, feed: FeedId) -> Arc<FeedStats> {
+        self.feeds.read().expect("Feeds lock poisoned")[feed].1.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FeedStats, Feeds, OverloadPolicy, ShardCommand, ShardQueue, Tick};
+    use crate::aggregator::InstrumentId;
+
+    use std::sync::Arc;
+
+    use exchange::structs::Price;
+    use futures_util::FutureExt;
+
+    // Queue of `capacity` ticks fed by two feeds
+    fn queue(capacity: usize, policy: OverloadPolicy) -> (ShardQueue<&'static str>, [Arc<FeedStats>; 2]) {
+        let stats = [Arc::new(FeedStats::default()), Arc::new(FeedStats::default())];
+        let feeds = Feeds::default();
+        feeds.write().unwrap().extend([("Binance", stats[0].clone()), ("ByBit", stats[1].clone())]);
+        (ShardQueue::new(capacity, policy, feeds), stats)
+    }
+
+    fn tick(instrument: u32, price: &str, feed: usize) -> Tick {
+        Tick {
+            instrument: InstrumentId(instrument),
+            price: Price::parse(price).unwrap(),
+            feed,
+        }
+    }
+
+    async fn push(queue: &ShardQueue<&'static str>, stats: &[Arc<FeedStats>; 2], tick: Tick) {
+        let feed = tick.feed;
+        assert!(queue.push_tick(tick, &stats[feed]).now_or_never().expect("Push blocked"));
+    }
+
+    // What's queued, as (instrument, price) for ticks and the name of control commands
+    fn drain(queue: &ShardQueue<&'static str>) -> Vec<String> {
+        let mut items = Vec::new();
+        while let Some(Some(command)) = queue.pop().now_or_never() {
+            items.push(match command {
+                ShardCommand::Tick(tick) => format!("{}@{}", tick.instrument.0, tick.price),
+                ShardCommand::Control(name) => name.to_string(),
+            });
+        }
+        items
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_free_space() {
+        let (queue, stats) = queue(2, OverloadPolicy::Block);
+        push(&queue, &stats, tick(1, "1", 0)).await;
+        push(&queue, &stats, tick(2, "1", 0)).await;
+
+        let mut blocked = Box::pin(queue.push_tick(tick(3, "1", 0), &stats[0]));
+        assert_eq!((&mut blocked).now_or_never(), None, "Push into a full queue didn't wait");
+        assert_eq!(queue.len(), 2);
+
+        assert!(matches!(queue.pop().now_or_never(), Some(Some(ShardCommand::Tick(_)))));
+        assert_eq!(blocked.now_or_never(), Some(true), "Push didn't resume once there was space");
+
+        assert_eq!(drain(&queue), ["2@1", "3@1"]);
+        let counters = stats[0].snapshot();
+        assert_eq!((counters.queued, counters.dropped, counters.conflated), (3, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_evicts_the_oldest_tick() {
+        let (queue, stats) = queue(2, OverloadPolicy::DropOldest);
+        push(&queue, &stats, tick(1, "1", 0)).await;
+        push(&queue, &stats, tick(2, "1", 1)).await;
+        push(&queue, &stats, tick(1, "2", 1)).await;
+        push(&queue, &stats, tick(3, "1", 1)).await;
+
+        assert_eq!(queue.len(), 2);
+        assert_eq!(drain(&queue), ["1@2", "3@1"]);
+        // Drops are counted against the feed of the evicted tick
+        assert_eq!(stats[0].snapshot().dropped, 1);
+        assert_eq!(stats[1].snapshot().dropped, 1);
+        assert_eq!(stats[1].snapshot().queued, 3);
+        assert_eq!(stats[1].snapshot().conflated, 0);
+    }
+
+    #[tokio::test]
+    async fn conflate_keeps_the_latest_quote_per_instrument() {
+        let (queue, stats) = queue(2, OverloadPolicy::Conflate);
+        push(&queue, &stats, tick(1, "1", 0)).await;
+        push(&queue, &stats, tick(2, "1", 0)).await;
+        push(&queue, &stats, tick(1, "2", 0)).await;
+        push(&queue, &stats, tick(1, "3", 0)).await;
+
+        let counters = stats[0].snapshot();
+        assert_eq!((counters.queued, counters.dropped, counters.conflated), (2, 0, 2));
+        assert_eq!(queue.len(), 2);
+
+        // No quote of the instrument is queued, the oldest tick makes room
+        push(&queue, &stats, tick(3, "1", 0)).await;
+        let counters = stats[0].snapshot();
+        assert_eq!((counters.queued, counters.dropped, counters.conflated), (3, 1, 2));
+        assert_eq!(drain(&queue), ["2@1", "3@1"]);
+    }
+
+    #[tokio::test]
+    async fn control_commands_are_never_dropped() {
+        let (queue, stats) = queue(1, OverloadPolicy::DropOldest);
+        push(&queue, &stats, tick(1, "1", 0)).await;
+        assert!(queue.push_control("flush"));
+        push(&queue, &stats, tick(2, "1", 0)).await;
+
+        assert_eq!(drain(&queue), ["flush", "2@1"]);
+        assert_eq!(stats[0].snapshot().dropped, 1);
+    }
+
+    #[tokio::test]
+    async fn closed_queues_refuse_pushes() {
+        let (queue, stats) = queue(1, OverloadPolicy::Block);
+        push(&queue, &stats, tick(1, "1", 0)).await;
+        let blocked = queue.push_tick(tick(2, "1", 0), &stats[0]);
+        queue.close();
+
+        assert_eq!(blocked.now_or_never(), Some(false));
+        assert!(!queue.push_control("flush"));
+        // What was queued is still handed out before the end
+        assert_eq!(drain(&queue), ["1@1"]);
+        assert!(matches!(queue.pop().now_or_never(), Some(None)));
+    }
+}
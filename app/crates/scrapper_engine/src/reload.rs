@@ -0,0 +1,184 @@
+use crate::aggregator::Aggregator;
+use crate::config::Config;
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use exchange::structs::market_symbol;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+// How often the config file is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// Change of the markets of a running feed
+#[derive(Debug)]
+pub enum FeedCommand {
+    Subscribe(Vec<String>),
+    Unsubscribe(Vec<String>),
+}
+
+struct FeedHandle {
+    exchange: &'static str,
+    shard: usize,
+    markets: Vec<String>,
+    commands: mpsc::Sender<FeedCommand>,
+}
+
+// Changes the markets of the running feeds to match a reloaded config. Feeds keep their
+// connections, so the bars in progress of the markets that stay are untouched
+#[derive(Default)]
+pub struct MarketControl {
+    feeds: Vec<FeedHandle>,
+}
+
+impl MarketControl {
+    // Registers a feed, returns the receiving end of its commands
+    pub(crate) fn register(&mut self, exchange: &'static str, shard: usize, markets: &[String]) -> mpsc::Receiver<FeedCommand> {
+        let (commands, receiver) = mpsc::channel(16);
+        self.feeds.push(FeedHandle { exchange, shard, markets: markets.to_vec(), commands });
+        receiver
+    }
+
+    // Markets of every feed as (exchange, shard, markets)
+    pub fn markets(&self) -> Vec<(&'static str, usize, Vec<String>)> {
+        self.feeds.iter().map(|feed| (feed.exchange, feed.shard, feed.markets.clone())).collect()
+    }
+
+    // Subscribes the running feeds to the markets added to `config` and unsubscribes them from
+    // the removed ones. New markets go to the connection of the exchange with the fewest markets
+    pub async fn apply(&mut self, config: &Config, aggregator: &Aggregator) {
+        let mut running = self.feeds.iter().map(|feed| feed.exchange).collect::<Vec<_>>();
+        running.sort();
+        running.dedup();
+
+        for (name, _) in config.enabled_exchanges() {
+            if !running.iter().any(|exchange| exchange.eq_ignore_ascii_case(name)) {
+                warn!(exchange = name, "Exchange isn't connected, restart to scrape it");
+            }
+        }
+
+        for exchange in running {
+            let section = config.exchanges
+                .get(&exchange.to_lowercase())
+                .filter(|section| section.enabled);
+            let wanted = section.map(|section| section.markets.clone()).unwrap_or_default();
+            let shard_size = section.map(|section| section.shard_size).unwrap_or(usize::MAX);
+
+            self.apply_exchange(exchange, wanted, shard_size, aggregator).await;
+        }
+    }
+
+    async fn apply_exchange(&mut self, exchange: &'static str, wanted: Vec<String>, shard_size: usize, aggregator: &Aggregator) {
+        let wanted_symbols = wanted.iter().map(|market| market_symbol(market)).collect::<HashSet<_>>();
+        let feeds = self.feeds.iter_mut().filter(|feed| feed.exchange == exchange).collect::<Vec<_>>();
+        let current_symbols = feeds.iter()
+            .flat_map(|feed| feed.markets.iter().map(|market| market_symbol(market)))
+            .collect::<HashSet<_>>();
+
+        let mut removed_markets = Vec::new();
+        for feed in feeds.iter() {
+            let removed = feed.markets.iter()
+                .filter(|market| !wanted_symbols.contains(&market_symbol(market)))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !removed.is_empty() {
+                removed_markets.push((feed.shard, removed));
+            }
+        }
+
+        let added = wanted.into_iter()
+            .filter(|market| !current_symbols.contains(&market_symbol(market)))
+            .collect::<Vec<_>>();
+
+        if removed_markets.is_empty() && added.is_empty() {
+            return;
+        }
+
+        let mut feeds = feeds;
+        for (shard, removed) in removed_markets {
+            let Some(feed) = feeds.iter_mut().find(|feed| feed.shard == shard) else {
+                continue;
+            };
+            feed.markets.retain(|market| !removed.contains(market));
+            for market in removed.iter() {
+                aggregator.retire(exchange, &market_symbol(market));
+            }
+            info!(exchange, shard, markets = %removed.join(","), "Unsubscribing removed markets");
+            Self::send(feed, FeedCommand::Unsubscribe(removed)).await;
+        }
+
+        let mut subscriptions = Vec::<(usize, Vec<String>)>::new();
+        for market in added {
+            let Some(feed) = feeds.iter_mut().min_by_key(|feed| feed.markets.len()) else {
+                break;
+            };
+            if feed.markets.len() >= shard_size {
+                warn!(exchange, shard = feed.shard, market = %market, "Every connection is full, restart to open more connections");
+            }
+
+            aggregator.reinstate(exchange, &market_symbol(&market));
+            feed.markets.push(market.clone());
+            match subscriptions.iter_mut().find(|(shard, _)| *shard == feed.shard) {
+                Some((_, markets)) => markets.push(market),
+                None => subscriptions.push((feed.shard, vec![market])),
+            }
+        }
+
+        for (shard, markets) in subscriptions {
+            let Some(feed) = feeds.iter().find(|feed| feed.shard == shard) else {
+                continue;
+            };
+            info!(exchange, shard, markets = %markets.join(","), "Subscribing added markets");
+            Self::send(feed, FeedCommand::Subscribe(markets)).await;
+        }
+    }
+
+    async fn send(feed: &FeedHandle, command: FeedCommand) {
+        if feed.commands.send(command).await.is_err() {
+            warn!(exchange = feed.exchange, shard = feed.shard, "Feed stopped, markets not changed");
+        }
+    }
+}
+
+// Reloads the config on SIGHUP or when its file changes and applies the new markets, until shutdown.
+// An invalid config is logged and ignored, the feeds keep their markets
+pub async fn watch_config(mut control: MarketControl, aggregator: Aggregator, shutdown: CancellationToken) {
+    let path = Config::file_path();
+    let mut modified = path.as_deref().and_then(modified_at);
+
+    #[cfg(unix)]
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .expect("Error listening for SIGHUP");
+
+    loop {
+        #[cfg(unix)]
+        let signal = hangup.recv();
+        #[cfg(not(unix))]
+        let signal = std::future::pending::<Option<()>>();
+
+        tokio::select! {
+            _ = signal => info!("SIGHUP received, reloading config"),
+            _ = tokio::time::sleep(POLL_INTERVAL) => {
+                let current = path.as_deref().and_then(modified_at);
+                if current == modified {
+                    continue;
+                }
+                modified = current;
+                info!("Config file changed, reloading config");
+            },
+            _ = shutdown.cancelled() => return,
+        }
+
+        match Config::load() {
+            Ok(config) => control.apply(&config, &aggregator).await,
+            Err(e) => error!("{:#}", e),
+        }
+    }
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    path.metadata().and_then(|metadata| metadata.modified()).ok()
+}
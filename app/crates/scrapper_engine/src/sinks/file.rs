@@ -0,0 +1,280 @@
+use crate::gaps::Gap;
+use crate::sinks::BarSink;
+use crate::sinks::stored::read_stored_bars;
+use crate::structs::ClosedBar;
+
+use std::fs::{self, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use chrono::{NaiveDate, NaiveDateTime};
+use db::models::Bar1min;
+use diesel::PgConnection;
+use serde::Serialize;
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    Csv,
+    Ndjson,
+}
+
+impl FileFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::Csv => "csv",
+            FileFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BarRecord<'a> {
+    exchange: &'a str,
+    market: &'a str,
+    timestamp: String,
+    // Prices are written as decimal strings so no precision is lost
+    open: String,
+    close: String,
+    min: String,
+    max: String,
+    partial: bool,
+    synthetic: bool,
+}
+
+impl<'a> BarRecord<'a> {
+    fn new(bar: &'a ClosedBar) -> Self {
+        Self {
+            exchange: bar.exchange,
+            market: &bar.market,
+            timestamp: bar.timestamp.format(TIMESTAMP_FORMAT).to_string(),
+            open: bar.olhc.open.to_string(),
+            close: bar.olhc.close.to_string(),
+            min: bar.olhc.min.to_string(),
+            max: bar.olhc.max.to_string(),
+            partial: bar.partial,
+            synthetic: bar.synthetic,
+        }
+    }
+
+    fn from_stored(bar: &'a Bar1min) -> Self {
+        Self {
+            exchange: &bar.exchange,
+            market: &bar.market,
+            timestamp: bar.timestamp.format(TIMESTAMP_FORMAT).to_string(),
+            open: bar.open.normalized().to_plain_string(),
+            close: bar.close.normalized().to_plain_string(),
+            min: bar.min.normalized().to_plain_string(),
+            max: bar.max.normalized().to_plain_string(),
+            partial: bar.partial,
+            synthetic: bar.synthetic,
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{}",
+            self.exchange, self.market, self.timestamp,
+            self.open, self.close, self.min, self.max,
+            self.partial, self.synthetic
+        )
+    }
+}
+
+#[derive(Serialize)]
+struct GapRecord<'a> {
+    exchange: &'a str,
+    market: &'a str,
+    timestamp: String,
+}
+
+impl<'a> GapRecord<'a> {
+    fn new(gap: &'a Gap) -> Self {
+        Self {
+            exchange: gap.exchange,
+            market: &gap.market,
+            timestamp: gap.timestamp.format(TIMESTAMP_FORMAT).to_string(),
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        format!("{},{},{}", self.exchange, self.market, self.timestamp)
+    }
+}
+
+const BARS_CSV_HEADER: &str = "exchange,market,timestamp,open,close,min,max,partial,synthetic";
+const GAPS_CSV_HEADER: &str = "exchange,market,timestamp";
+
+// Appends bars to one file per day, e.g. bars_1min-2026-10-19.csv, named after the day the bars cover.
+// Gaps go to gaps_1min-<day> files next to them
+pub struct FileSink {
+    dir: PathBuf,
+    format: FileFormat,
+    // Keeps writes of consecutive minutes from interleaving
+    lock: Mutex<()>,
+}
+
+impl FileSink {
+    pub fn new(dir: impl Into<PathBuf>, format: FileFormat) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        Ok(Self {
+            dir,
+            format,
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn path(&self, table: &str, timestamp: NaiveDateTime) -> PathBuf {
+        self.dir.join(format!("{}-{}.{}", table, timestamp.format("%Y-%m-%d"), self.format.extension()))
+    }
+
+    // Appends the lines to the file of the day, a new CSV file starts with the header
+    fn append(&self, path: PathBuf, header: &str, lines: impl Iterator<Item = Result<String>>) -> Result<usize> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let is_new = file.metadata()?.len() == 0;
+        let mut writer = BufWriter::new(file);
+
+        if is_new && self.format == FileFormat::Csv {
+            writeln!(writer, "{}", header)?;
+        }
+
+        let mut written = 0;
+        for line in lines {
+            writeln!(writer, "{}", line?)?;
+            written += 1;
+        }
+        writer.flush()?;
+
+        Ok(written)
+    }
+}
+
+impl BarSink for FileSink {
+    fn name(&self) -> &'static str {
+        self.format.extension()
+    }
+
+    fn write(&self, bars: &[ClosedBar], gaps: &[Gap]) -> Result<usize> {
+        let _guard = self.lock.lock().expect("File sink lock poisoned");
+        let mut written = 0;
+
+        // All records of a minute share its timestamp, so they always land in the same day's file
+        if let Some(bar) = bars.first() {
+            let lines = bars.iter().map(|bar| {
+                let record = BarRecord::new(bar);
+                match self.format {
+                    FileFormat::Csv => Ok(record.to_csv()),
+                    FileFormat::Ndjson => Ok(serde_json::to_string(&record)?),
+                }
+            });
+            written += self.append(self.path("bars_1min", bar.timestamp), BARS_CSV_HEADER, lines)?;
+        }
+
+        if let Some(gap) = gaps.first() {
+            let lines = gaps.iter().map(|gap| {
+                let record = GapRecord::new(gap);
+                match self.format {
+                    FileFormat::Csv => Ok(record.to_csv()),
+                    FileFormat::Ndjson => Ok(serde_json::to_string(&record)?),
+                }
+            });
+            written += self.append(self.path("gaps_1min", gap.timestamp), GAPS_CSV_HEADER, lines)?;
+        }
+
+        Ok(written)
+    }
+}
+
+// Writes the stored bars between `from` and `to` (inclusive days) as CSV, in the columns of the csv sink.
+// Returns the number of rows written
+pub fn export_csv(conn: &mut PgConnection, out: &mut impl Write, from: Option<NaiveDate>, to: Option<NaiveDate>) -> Result<usize> {
+    writeln!(out, "{}", BARS_CSV_HEADER)?;
+
+    let mut written = 0;
+    read_stored_bars(conn, from, to, |page| {
+        for bar in page {
+            writeln!(out, "{}", BarRecord::from_stored(bar).to_csv())?;
+            written += 1;
+        }
+        Ok(())
+    })?;
+    out.flush()?;
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileFormat, FileSink};
+    use crate::aggregator::Aggregator;
+    use crate::gaps::Gap;
+    use crate::queue::OverloadPolicy;
+    use crate::sinks::BarSink;
+    use crate::structs::ClosedBar;
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    use chrono::DateTime;
+    use exchange::structs::{Instrument, Orderbook};
+
+    fn dir(format: FileFormat) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("file-sink-test-{:?}-{}", format, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    // A real bar of BTCUSDT and a gap of ETHUSDT at 2025-01-01 00:00
+    async fn minute() -> (Vec<ClosedBar>, Vec<Gap>) {
+        let aggregator = Aggregator::new(1, 100, OverloadPolicy::Block);
+        let mut publisher = aggregator.publisher("Binance");
+        assert!(publisher.publish(Orderbook::new("Binance", &Instrument::new("BTCUSDT", 8), "99.5", "100.5").expect("Invalid orderbook")).await);
+        assert!(publisher.publish(Orderbook::new("Binance", &Instrument::new("BTCUSDT", 8), "0.5", "0.000125").expect("Invalid orderbook")).await);
+        let minute = DateTime::from_timestamp(1_735_689_600, 0).expect("Invalid time").naive_utc();
+        let bars = aggregator.flush(minute).await;
+        (bars, vec![Gap { exchange: "Binance", market: "ETHUSDT".into(), timestamp: minute }])
+    }
+
+    #[tokio::test]
+    async fn csv_files_start_with_the_header_once() {
+        let dir = dir(FileFormat::Csv);
+        let sink = FileSink::new(&dir, FileFormat::Csv).expect("Error creating sink");
+        let (bars, gaps) = minute().await;
+
+        assert_eq!(sink.write(&bars, &gaps).expect("Error writing"), 2);
+        assert_eq!(sink.write(&bars, &[]).expect("Error writing"), 1);
+
+        assert_eq!(fs::read_to_string(dir.join("bars_1min-2025-01-01.csv")).expect("No bars file"), concat!(
+            "exchange,market,timestamp,open,close,min,max,partial,synthetic\n",
+            "Binance,BTCUSDT,2025-01-01 00:00:00,100.5,0.000125,0.000125,100.5,false,false\n",
+            "Binance,BTCUSDT,2025-01-01 00:00:00,100.5,0.000125,0.000125,100.5,false,false\n",
+        ));
+        assert_eq!(fs::read_to_string(dir.join("gaps_1min-2025-01-01.csv")).expect("No gaps file"), concat!(
+            "exchange,market,timestamp\n",
+            "Binance,ETHUSDT,2025-01-01 00:00:00\n",
+        ));
+        fs::remove_dir_all(&dir).expect("Error removing files");
+    }
+
+    #[tokio::test]
+    async fn ndjson_files_hold_one_object_per_line() {
+        let dir = dir(FileFormat::Ndjson);
+        let sink = FileSink::new(&dir, FileFormat::Ndjson).expect("Error creating sink");
+        let (bars, gaps) = minute().await;
+
+        assert_eq!(sink.write(&bars, &gaps).expect("Error writing"), 2);
+
+        assert_eq!(fs::read_to_string(dir.join("bars_1min-2025-01-01.ndjson")).expect("No bars file"), concat!(
+            r#"{"exchange":"Binance","market":"BTCUSDT","timestamp":"2025-01-01 00:00:00","#,
+            r#""open":"100.5","close":"0.000125","min":"0.000125","max":"100.5","partial":false,"synthetic":false}"#, "\n",
+        ));
+        assert_eq!(fs::read_to_string(dir.join("gaps_1min-2025-01-01.ndjson")).expect("No gaps file"),
+            "{\"exchange\":\"Binance\",\"market\":\"ETHUSDT\",\"timestamp\":\"2025-01-01 00:00:00\"}\n");
+        fs::remove_dir_all(&dir).expect("Error removing files");
+    }
+}
@@ -0,0 +1,93 @@
+pub mod postgres;
+pub mod file;
+pub mod stdout;
+pub mod parquet;
+mod stored;
+
+pub use postgres::PostgresSink;
+pub use file::{export_csv, FileFormat, FileSink};
+pub use stdout::StdoutSink;
+pub use parquet::{FilePeriod, ParquetArchive, ParquetSink};
+
+use crate::gaps::Gap;
+use crate::spill::SpillBuffer;
+use crate::structs::ClosedBar;
+use crate::config::SinksConfig;
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use db::db::DbPool;
+
+// Destination of the bars closed every minute. The engine fans every minute out to all sinks,
+// each write runs on a blocking thread so sinks are free to do synchronous IO
+pub trait BarSink: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Writes the bars and gaps of one minute, returns the number of records written
+    fn write(&self, bars: &[ClosedBar], gaps: &[Gap]) -> Result<usize>;
+
+    // Called once a minute to log sink specific stats
+    fn report(&self) {}
+
+    // Called on shutdown after the last write
+    fn close(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Error of a write the sink kept to retry later, e.g. spilled to disk while the database is down.
+// Nothing was lost, but nothing reached the destination either so it doesn't count as a flush
+#[derive(Debug)]
+pub struct Deferred(pub &'static str);
+
+impl fmt::Display for Deferred {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl std::error::Error for Deferred {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SinkKind {
+    Postgres,
+    Csv,
+    Ndjson,
+    Stdout,
+    Parquet,
+}
+
+impl FromStr for SinkKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "postgres" => Ok(SinkKind::Postgres),
+            "csv" => Ok(SinkKind::Csv),
+            "ndjson" => Ok(SinkKind::Ndjson),
+            "stdout" => Ok(SinkKind::Stdout),
+            "parquet" => Ok(SinkKind::Parquet),
+            _ => bail!("Unknown sink {}, expected postgres, csv, ndjson, stdout or parquet", s),
+        }
+    }
+}
+
+// Opens every enabled sink, the postgres sink needs `pool`
+pub fn open_sinks(config: &SinksConfig, pool: Option<&DbPool>) -> Result<Vec<Arc<dyn BarSink>>> {
+    config.enabled.iter().map(|kind| -> Result<Arc<dyn BarSink>> {
+        Ok(match kind {
+            SinkKind::Postgres => {
+                let pool = pool.ok_or_else(|| anyhow!("The postgres sink needs a database"))?;
+                let spill = SpillBuffer::new(config.spill_path.clone(), config.spill_max_bytes)?;
+                Arc::new(PostgresSink::new(pool.clone(), spill))
+            },
+            SinkKind::Csv => Arc::new(FileSink::new(config.dir.clone(), FileFormat::Csv)?),
+            SinkKind::Ndjson => Arc::new(FileSink::new(config.dir.clone(), FileFormat::Ndjson)?),
+            SinkKind::Stdout => Arc::new(StdoutSink),
+            SinkKind::Parquet => Arc::new(ParquetSink::new(config.parquet_dir.clone())?),
+        })
+    }).collect()
+}
@@ -0,0 +1,427 @@
+use crate::gaps::Gap;
+use crate::sinks::BarSink;
+use crate::sinks::stored::read_stored_bars;
+use crate::structs::ClosedBar;
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use arrow_array::{ArrayRef, BooleanArray, Decimal128Array, RecordBatch, TimestampMicrosecondArray};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use bigdecimal::{BigDecimal, ToPrimitive};
+use chrono::{DurationRound, NaiveDate, NaiveDateTime, TimeDelta, Utc};
+use diesel::PgConnection;
+use db::models::Bar1min;
+use exchange::structs::Price;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use tracing::info;
+
+// Prices are stored as DECIMAL(38, 18), wide enough for every price the scraper can parse
+const DECIMAL_PRECISION: u8 = 38;
+const DECIMAL_SCALE: u8 = Price::MAX_SCALE;
+
+// How often the sink writes the rows it buffered to its open files as a row group
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+// Time a file covers at most
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilePeriod {
+    Hour,
+    Day,
+}
+
+impl FilePeriod {
+    // Start of the period `timestamp` falls in
+    fn start(self, timestamp: NaiveDateTime) -> NaiveDateTime {
+        let period = match self {
+            FilePeriod::Hour => TimeDelta::hours(1),
+            FilePeriod::Day => TimeDelta::days(1),
+        };
+        timestamp.duration_trunc(period).expect("Error truncating time to the file period")
+    }
+}
+
+// Bar as written to Parquet, prices are decimal mantissas at DECIMAL_SCALE
+pub struct ParquetBar<'a> {
+    exchange: &'a str,
+    market: &'a str,
+    timestamp: NaiveDateTime,
+    open: i128,
+    close: i128,
+    min: i128,
+    max: i128,
+    partial: bool,
+    synthetic: bool,
+}
+
+impl<'a> ParquetBar<'a> {
+    pub fn from_closed(bar: &'a ClosedBar) -> Self {
+        Self {
+            exchange: bar.exchange,
+            market: &bar.market,
+            timestamp: bar.timestamp,
+            open: price_to_decimal(bar.olhc.open),
+            close: price_to_decimal(bar.olhc.close),
+            min: price_to_decimal(bar.olhc.min),
+            max: price_to_decimal(bar.olhc.max),
+            partial: bar.partial,
+            synthetic: bar.synthetic,
+        }
+    }
+
+    pub fn from_stored(bar: &'a Bar1min) -> Result<Self> {
+        Ok(Self {
+            exchange: &bar.exchange,
+            market: &bar.market,
+            timestamp: bar.timestamp,
+            open: bigdecimal_to_decimal(&bar.open)?,
+            close: bigdecimal_to_decimal(&bar.close)?,
+            min: bigdecimal_to_decimal(&bar.min)?,
+            max: bigdecimal_to_decimal(&bar.max)?,
+            partial: bar.partial,
+            synthetic: bar.synthetic,
+        })
+    }
+}
+
+fn price_to_decimal(price: Price) -> i128 {
+    price.mantissa() as i128 * 10i128.pow((DECIMAL_SCALE - price.scale()) as u32)
+}
+
+fn bigdecimal_to_decimal(value: &BigDecimal) -> Result<i128> {
+    let (mantissa, _) = value.with_scale(DECIMAL_SCALE as i64).into_bigint_and_exponent();
+    mantissa.to_i128().ok_or_else(|| anyhow!("Price {} doesn't fit in a Parquet decimal", value))
+}
+
+// File being written for one exchange, market and period
+struct Partition {
+    // Start of the period the file covers
+    period: NaiveDateTime,
+    path: PathBuf,
+    writer: ArrowWriter<File>,
+}
+
+impl Partition {
+    // Writes the footer and makes the file visible under its final name, never replacing another file
+    fn close(self) -> Result<()> {
+        self.writer.close()?;
+        let in_progress = self.path.with_extension("parquet.inprogress");
+        if let Err(e) = fs::hard_link(&in_progress, &self.path) {
+            bail!("Error finishing {}, kept as {}: {}", self.path.display(), in_progress.display(), e);
+        }
+        fs::remove_file(in_progress)?;
+        Ok(())
+    }
+}
+
+// Parquet files partitioned as
+// <dir>/exchange=<exchange>/market=<market>/date=<yyyy-mm-dd>/<hhmmss>-<writer>.parquet,
+// named after the first minute they hold and the archive writing them, so two writers never
+// share a name. Files are written as .parquet.inprogress and renamed once closed, which happens
+// when the next period starts or on close
+pub struct ParquetArchive {
+    dir: PathBuf,
+    period: FilePeriod,
+    // Sets the files of this archive apart from those of other runs, exports and backfills
+    writer_id: String,
+    schema: SchemaRef,
+    partitions: HashMap<(String, String), Partition>,
+}
+
+impl ParquetArchive {
+    pub fn new(dir: impl Into<PathBuf>, period: FilePeriod) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        // Exchange, market and date are encoded in the path so readers pick them up as hive partitions
+        let price = DataType::Decimal128(DECIMAL_PRECISION, DECIMAL_SCALE as i8);
+        let schema = Schema::new(vec![
+            Field::new("timestamp", DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())), false),
+            Field::new("open", price.clone(), false),
+            Field::new("close", price.clone(), false),
+            Field::new("min", price.clone(), false),
+            Field::new("max", price, false),
+            Field::new("partial", DataType::Boolean, false),
+            Field::new("synthetic", DataType::Boolean, false),
+        ]);
+
+        Ok(Self {
+            dir,
+            period,
+            writer_id: format!("{}-{}", Utc::now().format("%Y%m%dT%H%M%S%6f"), std::process::id()),
+            schema: Arc::new(schema),
+            partitions: HashMap::new(),
+        })
+    }
+
+    // Appends the bars to the files of their periods, returns the number of rows written
+    pub fn write(&mut self, bars: &[ParquetBar]) -> Result<usize> {
+        let mut groups = HashMap::<(&str, &str, NaiveDateTime), Vec<&ParquetBar>>::new();
+        for bar in bars {
+            groups.entry((bar.exchange, bar.market, self.period.start(bar.timestamp))).or_default().push(bar);
+        }
+
+        for ((exchange, market, period), rows) in groups {
+            let batch = self.batch(&rows)?;
+            self.partition(exchange, market, period, rows[0].timestamp)?.writer.write(&batch)?;
+        }
+
+        // Markets that went silent still get their files closed once the period is over
+        if let Some(latest) = bars.iter().map(|bar| self.period.start(bar.timestamp)).max() {
+            self.close_before(latest)?;
+        }
+
+        Ok(bars.len())
+    }
+
+    // Writes the rows buffered so far to the open files as a row group
+    pub fn flush(&mut self) -> Result<()> {
+        for partition in self.partitions.values_mut() {
+            partition.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    // Closes every open file, the next write starts new ones
+    pub fn close(&mut self) -> Result<()> {
+        for (_, partition) in self.partitions.drain() {
+            partition.close()?;
+        }
+        Ok(())
+    }
+
+    fn close_before(&mut self, period: NaiveDateTime) -> Result<()> {
+        let ended = self.partitions
+            .iter()
+            .filter(|(_, partition)| partition.period < period)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        for key in ended {
+            if let Some(partition) = self.partitions.remove(&key) {
+                partition.close()?;
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the open file for the period, rolling over to a new one when the period changed
+    fn partition(&mut self, exchange: &str, market: &str, period: NaiveDateTime, first: NaiveDateTime) -> Result<&mut Partition> {
+        let key = (exchange.to_string(), market.to_string());
+
+        if let Some(partition) = self.partitions.get(&key)
+            && partition.period != period
+            && let Some(partition) = self.partitions.remove(&key)
+        {
+            partition.close()?;
+        }
+
+        if !self.partitions.contains_key(&key) {
+            let dir = self.dir
+                .join(format!("exchange={}", exchange))
+                .join(format!("market={}", market))
+                .join(format!("date={}", period.date()));
+            fs::create_dir_all(&dir)?;
+
+            let path = dir.join(format!("{}-{}.parquet", first.format("%H%M%S"), self.writer_id));
+            if path.exists() {
+                bail!("{} already exists", path.display());
+            }
+            let file = File::create_new(path.with_extension("parquet.inprogress"))?;
+            let properties = WriterProperties::builder()
+                .set_compression(Compression::ZSTD(ZstdLevel::default()))
+                .build();
+            let writer = ArrowWriter::try_new(file, self.schema.clone(), Some(properties))?;
+
+            self.partitions.insert(key.clone(), Partition { period, path, writer });
+        }
+
+        Ok(self.partitions.get_mut(&key).expect("Partition was just opened"))
+    }
+
+    fn batch(&self, rows: &[&ParquetBar]) -> Result<RecordBatch> {
+        let decimals = |price: fn(&ParquetBar) -> i128| -> Result<ArrayRef> {
+            Ok(Arc::new(
+                Decimal128Array::from(rows.iter().map(|bar| price(bar)).collect::<Vec<i128>>())
+                    .with_precision_and_scale(DECIMAL_PRECISION, DECIMAL_SCALE as i8)?,
+            ))
+        };
+
+        let timestamps = TimestampMicrosecondArray::from(
+            rows.iter().map(|bar| bar.timestamp.and_utc().timestamp_micros()).collect::<Vec<i64>>()
+        ).with_timezone("UTC");
+
+        Ok(RecordBatch::try_new(self.schema.clone(), vec![
+            Arc::new(timestamps),
+            decimals(|bar| bar.open)?,
+            decimals(|bar| bar.close)?,
+            decimals(|bar| bar.min)?,
+            decimals(|bar| bar.max)?,
+            Arc::new(BooleanArray::from(rows.iter().map(|bar| bar.partial).collect::<Vec<bool>>())),
+            Arc::new(BooleanArray::from(rows.iter().map(|bar| bar.synthetic).collect::<Vec<bool>>())),
+        ])?)
+    }
+
+    // Writes the stored bars between `from` and `to` (inclusive days) to Parquet, one market at a time.
+    // Returns the number of rows written
+    pub fn backfill(&mut self, conn: &mut PgConnection, from: Option<NaiveDate>, to: Option<NaiveDate>) -> Result<usize> {
+        let mut written = 0;
+        let mut current: Option<(String, String)> = None;
+
+        read_stored_bars(conn, from, to, |page| {
+            let first = &page[0];
+            if current.as_ref().is_some_and(|(exchange, market)| *exchange != first.exchange || *market != first.market) {
+                self.finish_market(current.take())?;
+            }
+            current = Some((first.exchange.clone(), first.market.clone()));
+
+            let rows = page.iter().map(ParquetBar::from_stored).collect::<Result<Vec<_>>>()?;
+            written += self.write(&rows)?;
+            Ok(())
+        })?;
+        self.finish_market(current)?;
+
+        Ok(written)
+    }
+
+    fn finish_market(&mut self, market: Option<(String, String)>) -> Result<()> {
+        self.close()?;
+        if let Some((exchange, market)) = market {
+            info!(exchange = %exchange, market = %market, "Backfilled");
+        }
+        Ok(())
+    }
+}
+
+// Archives the bars of every minute to Parquet in daily files, rolled at UTC midnight. The rows
+// are written out as a row group every FLUSH_INTERVAL, so a crash leaves at most that much in memory.
+// Gaps aren't archived
+pub struct ParquetSink {
+    archive: Mutex<ParquetArchive>,
+    last_flush: Mutex<Instant>,
+}
+
+impl ParquetSink {
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+        Ok(Self {
+            archive: Mutex::new(ParquetArchive::new(dir, FilePeriod::Day)?),
+            last_flush: Mutex::new(Instant::now()),
+        })
+    }
+}
+
+impl BarSink for ParquetSink {
+    fn name(&self) -> &'static str {
+        "parquet"
+    }
+
+    fn write(&self, bars: &[ClosedBar], _gaps: &[Gap]) -> Result<usize> {
+        let rows = bars.iter().map(ParquetBar::from_closed).collect::<Vec<_>>();
+        let mut archive = self.archive.lock().expect("Parquet archive lock poisoned");
+        let written = archive.write(&rows)?;
+
+        let mut last_flush = self.last_flush.lock().expect("Parquet flush lock poisoned");
+        if last_flush.elapsed() >= FLUSH_INTERVAL {
+            archive.flush()?;
+            *last_flush = Instant::now();
+        }
+        Ok(written)
+    }
+
+    fn close(&self) -> Result<()> {
+        self.archive.lock().expect("Parquet archive lock poisoned").close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FilePeriod, ParquetArchive, ParquetBar};
+
+    use std::fs;
+    use std::path::Path;
+
+    use chrono::NaiveDateTime;
+
+    fn bar(timestamp: &str) -> ParquetBar<'static> {
+        ParquetBar {
+            exchange: "Binance",
+            market: "BTCUSDT",
+            timestamp: NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M").expect("Invalid time"),
+            open: 1,
+            close: 1,
+            min: 1,
+            max: 1,
+            partial: false,
+            synthetic: false,
+        }
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut files = fs::read_dir(dir)
+            .expect("No partition")
+            .map(|entry| entry.expect("Error listing partition").file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn hourly_files_are_finished_when_the_next_hour_starts() {
+        let dir = std::env::temp_dir().join(format!("parquet-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let day = dir.join("exchange=Binance/market=BTCUSDT/date=2025-01-01");
+
+        let mut live = ParquetArchive::new(&dir, FilePeriod::Hour).expect("Error creating archive");
+        live.write(&[bar("2025-01-01 10:58"), bar("2025-01-01 10:59")]).expect("Error writing");
+        live.flush().expect("Error flushing");
+        live.write(&[bar("2025-01-01 11:00")]).expect("Error writing");
+
+        let written = files(&day);
+        assert_eq!(written.len(), 2, "{:?}", written);
+        assert!(written[0].starts_with("105800-") && written[0].ends_with(".parquet"), "{:?}", written);
+        assert!(written[1].starts_with("110000-") && written[1].ends_with(".parquet.inprogress"), "{:?}", written);
+
+        // Another writer starting at the same minute gets a file of its own
+        let mut export = ParquetArchive::new(&dir, FilePeriod::Day).expect("Error creating archive");
+        export.write(&[bar("2025-01-01 10:58")]).expect("Error writing");
+        export.close().expect("Error closing");
+        live.close().expect("Error closing");
+
+        let written = files(&day);
+        assert_eq!(written.len(), 3, "{:?}", written);
+        assert!(written.iter().all(|file| file.ends_with(".parquet")), "{:?}", written);
+        assert_eq!(written.iter().filter(|file| file.starts_with("105800-")).count(), 2, "{:?}", written);
+
+        fs::remove_dir_all(&dir).expect("Error removing archive");
+    }
+
+    // The period of the sink
+    #[test]
+    fn daily_files_are_finished_at_utc_midnight() {
+        let dir = std::env::temp_dir().join(format!("parquet-daily-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let partition = |date: &str| dir.join(format!("exchange=Binance/market=BTCUSDT/date={}", date));
+
+        let mut live = ParquetArchive::new(&dir, FilePeriod::Day).expect("Error creating archive");
+        live.write(&[bar("2025-01-01 10:58")]).expect("Error writing");
+        live.flush().expect("Error flushing");
+        live.write(&[bar("2025-01-01 23:59")]).expect("Error writing");
+        assert_eq!(files(&partition("2025-01-01")).len(), 1);
+        assert!(files(&partition("2025-01-01"))[0].ends_with(".parquet.inprogress"));
+
+        live.write(&[bar("2025-01-02 00:00")]).expect("Error writing");
+        let finished = files(&partition("2025-01-01"));
+        assert!(finished.len() == 1 && finished[0].starts_with("105800-") && finished[0].ends_with(".parquet"), "{:?}", finished);
+        let started = files(&partition("2025-01-02"));
+        assert!(started.len() == 1 && started[0].starts_with("000000-") && started[0].ends_with(".parquet.inprogress"), "{:?}", started);
+
+        live.close().expect("Error closing");
+        fs::remove_dir_all(&dir).expect("Error removing archive");
+    }
+}
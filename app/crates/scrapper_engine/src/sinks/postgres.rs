@@ -0,0 +1,52 @@
+use crate::gaps::Gap;
+use crate::sinks::{BarSink, Deferred};
+use crate::spill::{SaveOutcome, SpillBuffer};
+use crate::structs::ClosedBar;
+
+use anyhow::{bail, Result};
+use db::db::DbPool;
+use tracing::{info, warn};
+
+// Writes every minute in one transaction, spilling it to disk while the database is unavailable
+pub struct PostgresSink {
+    pool: DbPool,
+    spill: SpillBuffer,
+}
+
+impl PostgresSink {
+    pub fn new(pool: DbPool, spill: SpillBuffer) -> Self {
+        Self { pool, spill }
+    }
+}
+
+impl BarSink for PostgresSink {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn write(&self, bars: &[ClosedBar], gaps: &[Gap]) -> Result<usize> {
+        match self.spill.save(bars, gaps, self.pool.get().map_err(Into::into))? {
+            SaveOutcome::Saved { saved, replayed } => {
+                if replayed > 0 {
+                    info!(replayed, "Replayed spilled minutes");
+                }
+                Ok(saved)
+            },
+            SaveOutcome::Spilled => Err(Deferred("Database unavailable, bars spilled to disk").into()),
+            SaveOutcome::Discarded => bail!("Database unavailable and spill file is full, bars discarded"),
+        }
+    }
+
+    fn report(&self) {
+        let spilled = self.spill.stats();
+        if spilled.pending_bytes > 0 || spilled.discarded > 0 {
+            warn!(
+                pending_bytes = spilled.pending_bytes,
+                spilled = spilled.spilled,
+                replayed = spilled.replayed,
+                discarded = spilled.discarded,
+                "Spill file not empty"
+            );
+        }
+    }
+}
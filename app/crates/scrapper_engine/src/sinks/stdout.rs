@@ -0,0 +1,73 @@
+use crate::gaps::Gap;
+use crate::sinks::BarSink;
+use crate::structs::ClosedBar;
+
+use std::io::{stdout, Write};
+
+use anyhow::Result;
+
+// Prints one line per bar and gap, handy to watch the scraper without any storage
+pub struct StdoutSink;
+
+impl BarSink for StdoutSink {
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
+
+    fn write(&self, bars: &[ClosedBar], gaps: &[Gap]) -> Result<usize> {
+        write_lines(&mut stdout().lock(), bars, gaps)
+    }
+}
+
+fn write_lines(out: &mut impl Write, bars: &[ClosedBar], gaps: &[Gap]) -> Result<usize> {
+    for bar in bars {
+        writeln!(
+            out,
+            "{} {} {} open {} close {} min {} max {}{}{}",
+            bar.timestamp, bar.exchange, bar.market,
+            bar.olhc.open, bar.olhc.close, bar.olhc.min, bar.olhc.max,
+            if bar.partial { " partial" } else { "" },
+            if bar.synthetic { " synthetic" } else { "" },
+        )?;
+    }
+
+    for gap in gaps {
+        writeln!(out, "{} {} {} no updates", gap.timestamp, gap.exchange, gap.market)?;
+    }
+
+    Ok(bars.len() + gaps.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_lines;
+    use crate::aggregator::Aggregator;
+    use crate::gaps::Gap;
+    use crate::queue::OverloadPolicy;
+
+    use chrono::DateTime;
+    use exchange::structs::{Instrument, Orderbook};
+
+    #[tokio::test]
+    async fn bars_and_gaps_are_printed_one_per_line() {
+        let aggregator = Aggregator::new(1, 100, OverloadPolicy::Block);
+        let mut publisher = aggregator.publisher("Binance");
+        assert!(publisher.publish(Orderbook::new("Binance", &Instrument::new("BTCUSDT", 8), "99.5", "100.5").expect("Invalid orderbook")).await);
+        assert!(publisher.publish(Orderbook::new("Binance", &Instrument::new("BTCUSDT", 8), "101", "102").expect("Invalid orderbook")).await);
+        let minute = DateTime::from_timestamp(1_735_689_600, 0).expect("Invalid time").naive_utc();
+        let mut bars = aggregator.flush(minute).await;
+        let mut partial = bars[0].clone();
+        partial.partial = true;
+        partial.synthetic = true;
+        bars.push(partial);
+        let gap = Gap { exchange: "ByBit", market: "ETHUSDT".into(), timestamp: minute };
+
+        let mut out = Vec::new();
+        assert_eq!(write_lines(&mut out, &bars, &[gap]).expect("Error printing"), 3);
+        assert_eq!(String::from_utf8(out).expect("Invalid output"), concat!(
+            "2025-01-01 00:00:00 Binance BTCUSDT open 100.5 close 102 min 100.5 max 102\n",
+            "2025-01-01 00:00:00 Binance BTCUSDT open 100.5 close 102 min 100.5 max 102 partial synthetic\n",
+            "2025-01-01 00:00:00 ByBit ETHUSDT no updates\n",
+        ));
+    }
+}
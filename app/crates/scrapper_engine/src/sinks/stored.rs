@@ -0,0 +1,56 @@
+use anyhow::Result;
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use db::models::{Bar1min, INTERVAL_1MIN};
+use db::schema::bars_1min;
+
+// Rows read from bars_1min per query
+const PAGE: i64 = 10_000;
+
+// Reads the stored bars between `from` and `to` (inclusive days) one market and page at a time,
+// in timestamp order. Pages never mix markets
+pub(crate) fn read_stored_bars(
+    conn: &mut PgConnection,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    mut on_page: impl FnMut(&[Bar1min]) -> Result<()>,
+) -> Result<()> {
+    let markets = bars_1min::table
+        .select((bars_1min::exchange, bars_1min::market))
+        .distinct()
+        .order_by((bars_1min::exchange, bars_1min::market))
+        .load::<(String, String)>(conn)?;
+
+    for (exchange, market) in markets {
+        let mut after: Option<NaiveDateTime> = None;
+
+        loop {
+            let mut query = bars_1min::table
+                .filter(bars_1min::exchange.eq(&exchange))
+                .filter(bars_1min::market.eq(&market))
+                .filter(bars_1min::interval.eq(INTERVAL_1MIN))
+                .order_by(bars_1min::timestamp)
+                .limit(PAGE)
+                .into_boxed();
+
+            if let Some(after) = after {
+                query = query.filter(bars_1min::timestamp.gt(after));
+            } else if let Some(from) = from {
+                query = query.filter(bars_1min::timestamp.ge(from.and_time(Default::default())));
+            }
+            if let Some(to) = to.and_then(|to| to.succ_opt()) {
+                query = query.filter(bars_1min::timestamp.lt(to.and_time(Default::default())));
+            }
+
+            let page = query.load::<Bar1min>(conn)?;
+            let Some(last) = page.last() else {
+                break;
+            };
+            after = Some(last.timestamp);
+
+            on_page(&page)?;
+        }
+    }
+
+    Ok(())
+}
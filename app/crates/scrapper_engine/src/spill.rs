@@ -0,0 +1,371 @@
+use crate::gaps::{insert_gaps, Gap};
+use crate::metrics;
+use crate::structs::{upsert_bars, ClosedBar, INSERT_CHUNK};
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use anyhow::Result;
+use bigdecimal::BigDecimal;
+use chrono::NaiveDateTime;
+use diesel::{Connection, PgConnection, QueryResult};
+use db::models::{NewBar1min, NewGap1min};
+use serde::{Deserialize, Serialize};
+use tracing::{debug_span, error, warn};
+
+#[derive(Serialize, Deserialize)]
+struct SpilledBar {
+    exchange: String,
+    market: String,
+    timestamp: NaiveDateTime,
+    open: BigDecimal,
+    close: BigDecimal,
+    min: BigDecimal,
+    max: BigDecimal,
+    partial: bool,
+    synthetic: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SpilledGap {
+    exchange: String,
+    market: String,
+    timestamp: NaiveDateTime,
+}
+
+// Everything one flush wanted to write, kept together so it's replayed in one transaction
+#[derive(Serialize, Deserialize)]
+struct SpilledMinute {
+    bars: Vec<SpilledBar>,
+    gaps: Vec<SpilledGap>,
+}
+
+impl SpilledMinute {
+    fn new(bars: &[ClosedBar], gaps: &[Gap]) -> Self {
+        Self {
+            bars: bars.iter().map(|bar| SpilledBar {
+                exchange: bar.exchange.to_string(),
+                market: bar.market.to_string(),
+                timestamp: bar.timestamp,
+                open: bar.olhc.open.into(),
+                close: bar.olhc.close.into(),
+                min: bar.olhc.min.into(),
+                max: bar.olhc.max.into(),
+                partial: bar.partial,
+                synthetic: bar.synthetic,
+            }).collect(),
+            gaps: gaps.iter().map(|gap| SpilledGap {
+                exchange: gap.exchange.to_string(),
+                market: gap.market.to_string(),
+                timestamp: gap.timestamp,
+            }).collect(),
+        }
+    }
+
+    fn save(&self, conn: &mut PgConnection) -> QueryResult<usize> {
+        conn.transaction(|conn| {
+            let mut saved = 0;
+
+            for chunk in self.bars.chunks(INSERT_CHUNK) {
+                let rows = chunk
+                    .iter()
+                    .map(|bar| NewBar1min::new(
+                        &bar.exchange,
+                        &bar.market,
+                        bar.timestamp,
+                        bar.open.clone(),
+                        bar.close.clone(),
+                        bar.min.clone(),
+                        bar.max.clone(),
+                        bar.partial,
+                        bar.synthetic,
+                    ))
+                    .collect::<Vec<NewBar1min>>();
+                saved += upsert_bars(&rows, conn)?;
+            }
+
+            for chunk in self.gaps.chunks(INSERT_CHUNK) {
+                let rows = chunk
+                    .iter()
+                    .map(|gap| NewGap1min::new(&gap.exchange, &gap.market, gap.timestamp))
+                    .collect::<Vec<NewGap1min>>();
+                saved += insert_gaps(&rows, conn)?;
+            }
+
+            Ok(saved)
+        })
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct SpillStats {
+    pub spilled: AtomicU64,
+    pub replayed: AtomicU64,
+    pub discarded: AtomicU64,
+    pub pending_bytes: AtomicU64,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SpillStatsSnapshot {
+    pub spilled: u64,
+    pub replayed: u64,
+    pub discarded: u64,
+    pub pending_bytes: u64,
+}
+
+// Append-only file of minutes that couldn't be written while the database was unavailable.
+// One JSON line per minute, replayed oldest first before anything newer is written
+pub struct SpillBuffer {
+    path: PathBuf,
+    max_bytes: u64,
+    // Serializes appends and replays so minutes are always written in order
+    lock: Mutex<()>,
+    stats: SpillStats,
+}
+
+impl SpillBuffer {
+    pub fn new(path: impl Into<PathBuf>, max_bytes: u64) -> Result<Self> {
+        let path = path.into();
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            fs::create_dir_all(parent)?;
+        }
+
+        let pending_bytes = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        let stats = SpillStats::default();
+        stats.pending_bytes.store(pending_bytes, Ordering::Relaxed);
+        metrics::SPILL_PENDING_BYTES.set(pending_bytes as i64);
+
+        if pending_bytes > 0 {
+            warn!(pending_bytes, path = %path.display(), "Found unsaved bars");
+        }
+
+        Ok(Self {
+            path,
+            max_bytes,
+            lock: Mutex::new(()),
+            stats,
+        })
+    }
+
+    pub fn stats(&self) -> SpillStatsSnapshot {
+        SpillStatsSnapshot {
+            spilled: self.stats.spilled.load(Ordering::Relaxed),
+            replayed: self.stats.replayed.load(Ordering::Relaxed),
+            discarded: self.stats.discarded.load(Ordering::Relaxed),
+            pending_bytes: self.stats.pending_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    // Writes a minute to the database, replaying spilled minutes first.
+    // `conn` is taken before the spill lock, so waiting for the pool never holds up the other writes.
+    // If the database is unavailable the minute is appended to the spill file instead
+    pub fn save(&self, bars: &[ClosedBar], gaps: &[Gap], conn: Result<PooledConnection>) -> Result<SaveOutcome> {
+        let _guard = self.lock.lock().expect("Spill lock poisoned");
+        let _span = debug_span!("save", bars = bars.len(), gaps = gaps.len()).entered();
+
+        let mut conn = match conn {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!(error = format!("{:#}", e), "Database unavailable");
+                return self.append(&SpilledMinute::new(bars, gaps));
+            }
+        };
+
+        let replayed = match self.replay(&mut conn) {
+            Ok(replayed) => replayed,
+            Err(_) => return self.append(&SpilledMinute::new(bars, gaps)),
+        };
+
+        let timer = metrics::DB_INSERT_SECONDS.start_timer();
+        let saved = conn.transaction(|conn| {
+            let bars_saved = ClosedBar::save_all(bars, conn)?;
+            let gaps_saved = Gap::save_all(gaps, conn)?;
+            Ok::<usize, diesel::result::Error>(bars_saved + gaps_saved)
+        });
+        timer.observe_duration();
+
+        match saved {
+            Ok(saved) => Ok(SaveOutcome::Saved { saved, replayed }),
+            Err(e) if is_unavailable(&e) => self.append(&SpilledMinute::new(bars, gaps)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn append(&self, minute: &SpilledMinute) -> Result<SaveOutcome> {
+        let mut line = serde_json::to_string(minute)?;
+        line.push('\n');
+
+        let pending = self.stats.pending_bytes.load(Ordering::Relaxed);
+        if pending + line.len() as u64 > self.max_bytes {
+            self.count(&self.stats.discarded, "discarded");
+            return Ok(SaveOutcome::Discarded);
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+
+        self.count(&self.stats.spilled, "spilled");
+        self.set_pending_bytes(pending + line.len() as u64);
+        Ok(SaveOutcome::Spilled)
+    }
+
+    fn count(&self, counter: &AtomicU64, event: &str) {
+        counter.fetch_add(1, Ordering::Relaxed);
+        metrics::SPILL_MINUTES.with_label_values(&[event]).inc();
+    }
+
+    fn set_pending_bytes(&self, bytes: u64) {
+        self.stats.pending_bytes.store(bytes, Ordering::Relaxed);
+        metrics::SPILL_PENDING_BYTES.set(bytes as i64);
+    }
+
+    // Writes spilled minutes oldest first and stops at the first one the database is unavailable for.
+    // Minutes written are removed from the file even if a later one fails, so nothing is replayed twice.
+    // A minute the database rejects would block every later one, so it's discarded
+    fn replay(&self, conn: &mut PgConnection) -> QueryResult<u64> {
+        if self.stats.pending_bytes.load(Ordering::Relaxed) == 0 {
+            return Ok(0);
+        }
+
+        let lines = match File::open(&self.path) {
+            Ok(file) => BufReader::new(file)
+                .lines()
+                .map_while(|line| line.ok())
+                .filter(|line| !line.trim().is_empty())
+                .collect::<Vec<String>>(),
+            Err(_) => Vec::new(),
+        };
+
+        let mut replayed = 0;
+        let mut result = Ok(());
+        for line in lines.iter() {
+            let minute = match serde_json::from_str::<SpilledMinute>(line) {
+                Ok(minute) => minute,
+                Err(e) => {
+                    warn!(error = %e, "Skipping corrupted spilled minute");
+                    self.count(&self.stats.discarded, "discarded");
+                    replayed += 1;
+                    continue;
+                }
+            };
+
+            match minute.save(conn) {
+                Ok(_) => self.count(&self.stats.replayed, "replayed"),
+                Err(e) if is_unavailable(&e) => {
+                    result = Err(e);
+                    break;
+                },
+                Err(e) => {
+                    error!(error = %e, "Discarding spilled minute rejected by the database");
+                    self.count(&self.stats.discarded, "discarded");
+                }
+            }
+            replayed += 1;
+        }
+
+        if let Err(e) = self.rewrite(&lines[replayed..]) {
+            error!(path = %self.path.display(), error = %e, "Error rewriting spill file");
+        }
+
+        result.map(|_| replayed as u64)
+    }
+
+    // Atomically replaces the spill file with the minutes still pending
+    fn rewrite(&self, remaining: &[String]) -> std::io::Result<()> {
+        if remaining.is_empty() {
+            if self.path.exists() {
+                fs::remove_file(&self.path)?;
+            }
+            self.set_pending_bytes(0);
+            return Ok(());
+        }
+
+        let tmp = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        let mut bytes = 0;
+        for line in remaining {
+            file.write_all(line.as_bytes())?;
+            file.write_all(b"\n")?;
+            bytes += line.len() as u64 + 1;
+        }
+        file.sync_data()?;
+        fs::rename(&tmp, &self.path)?;
+
+        self.set_pending_bytes(bytes);
+        Ok(())
+    }
+}
+
+pub type PooledConnection = diesel::r2d2::PooledConnection<diesel::r2d2::ConnectionManager<PgConnection>>;
+
+#[derive(Debug)]
+pub enum SaveOutcome {
+    // Rows written for this minute and number of spilled minutes replayed before it
+    Saved { saved: usize, replayed: u64 },
+    Spilled,
+    // The spill file reached its size cap, the minute is lost
+    Discarded,
+}
+
+// Errors meaning the database can't be reached, as opposed to a rejected query
+fn is_unavailable(error: &diesel::result::Error) -> bool {
+    use diesel::result::{DatabaseErrorKind, Error};
+
+    matches!(
+        error,
+        Error::DatabaseError(DatabaseErrorKind::ClosedConnection, _)
+            | Error::DatabaseError(DatabaseErrorKind::UnableToSendCommand, _)
+            | Error::BrokenTransactionManager
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SaveOutcome, SpillBuffer};
+    use crate::aggregator::Aggregator;
+    use crate::metrics;
+    use crate::queue::OverloadPolicy;
+
+    use std::fs;
+
+    use anyhow::anyhow;
+    use chrono::DateTime;
+    use exchange::structs::{Instrument, Orderbook};
+
+    #[tokio::test]
+    async fn unavailable_minutes_are_spilled_until_the_cap() {
+        let aggregator = Aggregator::new(1, 100, OverloadPolicy::Block);
+        let mut publisher = aggregator.publisher("Binance");
+        assert!(publisher.publish(Orderbook::new("Binance", &Instrument::new("BTCUSDT", 8), "1", "10").expect("Invalid orderbook")).await);
+        let bars = aggregator.flush(DateTime::from_timestamp(1_735_689_600, 0).expect("Invalid time").naive_utc()).await;
+
+        let path = std::env::temp_dir().join(format!("spill-test-{}.ndjson", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let spilled = metrics::SPILL_MINUTES.with_label_values(&["spilled"]).get();
+        let discarded = metrics::SPILL_MINUTES.with_label_values(&["discarded"]).get();
+
+        // Room for one minute only
+        let spill = SpillBuffer::new(&path, 200).expect("Error creating spill file");
+        let outcome = spill.save(&bars, &[], Err(anyhow!("Database down"))).expect("Error spilling");
+        assert!(matches!(outcome, SaveOutcome::Spilled), "Unexpected outcome {:?}", outcome);
+        let outcome = spill.save(&bars, &[], Err(anyhow!("Database down"))).expect("Error spilling");
+        assert!(matches!(outcome, SaveOutcome::Discarded), "Unexpected outcome {:?}", outcome);
+
+        let stats = spill.stats();
+        assert_eq!((stats.spilled, stats.discarded, stats.replayed), (1, 1, 0));
+        assert_eq!(stats.pending_bytes, fs::metadata(&path).expect("No spill file").len());
+        assert_eq!(metrics::SPILL_PENDING_BYTES.get() as u64, stats.pending_bytes);
+        assert_eq!(metrics::SPILL_MINUTES.with_label_values(&["spilled"]).get(), spilled + 1);
+        assert_eq!(metrics::SPILL_MINUTES.with_label_values(&["discarded"]).get(), discarded + 1);
+
+        // A restart picks up what's pending
+        assert_eq!(SpillBuffer::new(&path, 200).expect("Error opening spill file").stats().pending_bytes, stats.pending_bytes);
+        fs::remove_file(&path).expect("Error removing spill file");
+    }
+}
@@ -0,0 +1,214 @@
+use crate::metrics;
+
+use std::collections::HashMap;
+use std::sync::{LazyLock, RwLock};
+
+use chrono::{DateTime, Utc};
+
+// Liveness of the scraper as seen by the health endpoints. There's one engine per process,
+// so like the metrics the status is kept process wide
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeedState {
+    Connected,
+    Reconnecting,
+    Closed,
+}
+
+impl FeedState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FeedState::Connected => "connected",
+            FeedState::Reconnecting => "reconnecting",
+            FeedState::Closed => "closed",
+        }
+    }
+}
+
+// Whether the scraper writes its bars, only one of several HA instances does
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Role {
+    #[default]
+    Leader,
+    Standby,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Leader => "leader",
+            Role::Standby => "standby",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FeedStatus {
+    pub exchange: &'static str,
+    // Index of the connection among the connections to the same exchange
+    pub shard: usize,
+    pub state: FeedState,
+    pub connection: u64,
+    // When the feed entered its state
+    pub since: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MarketStatus {
+    pub exchange: &'static str,
+    pub market: String,
+    pub last_message: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ScraperStatus {
+    pub running: bool,
+    pub started_at: Option<DateTime<Utc>>,
+    pub role: Role,
+    pub feeds: Vec<FeedStatus>,
+    pub markets: Vec<MarketStatus>,
+    // Last successful write per sink
+    pub flushes: Vec<(&'static str, DateTime<Utc>)>,
+}
+
+#[derive(Default)]
+struct Status {
+    running: bool,
+    started_at: Option<DateTime<Utc>>,
+    role: Role,
+    feeds: HashMap<(&'static str, usize), FeedStatus>,
+    flushes: HashMap<&'static str, DateTime<Utc>>,
+}
+
+static STATUS: LazyLock<RwLock<Status>> = LazyLock::new(RwLock::default);
+
+fn update(change: impl FnOnce(&mut Status)) {
+    change(&mut STATUS.write().expect("Status lock poisoned"));
+}
+
+pub fn feed_state(exchange: &'static str, shard: usize, state: FeedState, connection: u64) {
+    update(|status| set_feed_state(status, exchange, shard, state, connection));
+}
+
+fn set_feed_state(status: &mut Status, exchange: &'static str, shard: usize, state: FeedState, connection: u64) {
+    status.feeds.insert((exchange, shard), FeedStatus { exchange, shard, state, connection, since: Utc::now() });
+}
+
+pub fn role(role: Role) {
+    update(|status| status.role = role);
+}
+
+pub fn flushed(sink: &'static str) {
+    update(|status| {
+        status.flushes.insert(sink, Utc::now());
+    });
+}
+
+pub fn snapshot() -> ScraperStatus {
+    let status = STATUS.read().expect("Status lock poisoned");
+
+    let mut feeds = status.feeds.values().cloned().collect::<Vec<_>>();
+    feeds.sort_by_key(|feed| (feed.exchange, feed.shard));
+
+    let mut markets = metrics::last_messages()
+        .into_iter()
+        .map(|(exchange, market, last_message)| MarketStatus { exchange, market, last_message })
+        .collect::<Vec<_>>();
+    markets.sort_by(|a, b| (a.exchange, &a.market).cmp(&(b.exchange, &b.market)));
+
+    let mut flushes = status.flushes.iter().map(|(sink, at)| (*sink, *at)).collect::<Vec<_>>();
+    flushes.sort();
+
+    ScraperStatus {
+        running: status.running,
+        started_at: status.started_at,
+        role: status.role,
+        feeds,
+        markets,
+        flushes,
+    }
+}
+
+// Marks the scraper as running while alive. Dropped when the scraper task ends, also when it panics
+pub struct ScraperGuard;
+
+impl ScraperGuard {
+    pub fn start() -> Self {
+        update(|status| {
+            status.running = true;
+            status.started_at = Some(Utc::now());
+        });
+        ScraperGuard
+    }
+}
+
+impl Drop for ScraperGuard {
+    fn drop(&mut self) {
+        // Also runs while unwinding, a poisoned lock still holds usable status
+        let mut status = STATUS.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        status.running = false;
+    }
+}
+
+// Reports the state of a feed while its task runs and the feed as closed when the task ends,
+// also when it panics
+pub struct FeedGuard {
+    exchange: &'static str,
+    shard: usize,
+    connection: u64,
+}
+
+impl FeedGuard {
+    pub fn start(exchange: &'static str, shard: usize, connection: u64) -> Self {
+        feed_state(exchange, shard, FeedState::Connected, connection);
+        FeedGuard { exchange, shard, connection }
+    }
+
+    pub fn reconnecting(&self) {
+        feed_state(self.exchange, self.shard, FeedState::Reconnecting, self.connection);
+    }
+
+    pub fn connected(&mut self, connection: u64) {
+        self.connection = connection;
+        feed_state(self.exchange, self.shard, FeedState::Connected, connection);
+    }
+}
+
+impl Drop for FeedGuard {
+    fn drop(&mut self) {
+        let mut status = STATUS.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        set_feed_state(&mut status, self.exchange, self.shard, FeedState::Closed, self.connection);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{snapshot, FeedGuard, FeedState};
+
+    fn state(exchange: &str) -> Option<FeedState> {
+        snapshot().feeds.into_iter().find(|feed| feed.exchange == exchange).map(|feed| feed.state)
+    }
+
+    #[tokio::test]
+    async fn feeds_are_closed_when_their_task_panics() {
+        let task = tokio::spawn(async {
+            let mut feed = FeedGuard::start("Panicking", 0, 1);
+            feed.connected(2);
+            panic!("Feed failed");
+        });
+
+        assert!(task.await.is_err());
+        let feed = snapshot().feeds.into_iter().find(|feed| feed.exchange == "Panicking").expect("No feed status");
+        assert_eq!((feed.state, feed.connection), (FeedState::Closed, 2));
+    }
+
+    #[test]
+    fn feeds_report_their_state_while_running() {
+        let feed = FeedGuard::start("Running", 0, 1);
+        assert_eq!(state("Running"), Some(FeedState::Connected));
+        feed.reconnecting();
+        assert_eq!(state("Running"), Some(FeedState::Reconnecting));
+        drop(feed);
+        assert_eq!(state("Running"), Some(FeedState::Closed));
+    }
+}
@@ -1,91 +1,303 @@
-use std::collections::HashMap;
-use crate::MapOLHC;
+use crate::aggregator::InstrumentId;
 
 use std::sync::Arc;
 
-use bigdecimal::BigDecimal;
-use diesel::{PgConnection, RunQueryDsl};
-use futures_util::lock::Mutex;
+use chrono::NaiveDateTime;
+use diesel::dsl::sql;
+use diesel::sql_types::{Bool, Numeric};
+use diesel::upsert::excluded;
+use diesel::query_dsl::methods::FilterDsl;
+use diesel::{BoolExpressionMethods, ExpressionMethods, PgConnection, QueryResult, RunQueryDsl};
 use db::models::NewBar1min;
 use db::schema::bars_1min;
-use exchange::structs::Orderbook;
+use exchange::structs::Price;
 
+// Bar taken out of the aggregator, ready to be persisted
 #[derive(Clone, Debug)]
+pub struct ClosedBar {
+    pub instrument: InstrumentId,
+    pub exchange: &'static str,
+    pub market: Arc<str>,
+    // Start of the minute the bar covers
+    pub timestamp: NaiveDateTime,
+    pub olhc: OLHC,
+    pub partial: bool,
+    pub synthetic: bool,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug)]
 pub struct OLHC {
-    pub open: BigDecimal,
-    pub close: BigDecimal,
-    pub min: BigDecimal,
-    pub max: BigDecimal,
+    pub open: Price,
+    pub close: Price,
+    pub min: Price,
+    pub max: Price,
 }
 
 impl OLHC {
-    pub fn new(open: BigDecimal) -> Self {
+    pub fn new(open: Price) -> Self {
         OLHC {
-            open: open.clone(),
-            close: open.clone(),
-            min: open.clone(),
+            open,
+            close: open,
+            min: open,
             max: open,
         }
     }
 
-    pub async fn update_map(map: Arc<Mutex<MapOLHC>>, orderbook: Orderbook) {
-        let price = orderbook.ask;
+    pub fn update(&mut self, price: Price) {
+        self.close = price;
 
-        if price < BigDecimal::from(0) {
-            return;
+        if self.min > price {
+            self.min = price;
         }
 
-        if let Some(exchange) = map.lock().await.get_mut(&orderbook.exchange) {
-            if let Some(market) = exchange.get_mut(&orderbook.symbol) {
-                market.close = price.clone();
-
-                if market.min > price {
-                    market.min = price.clone();
-                }
-
-                if market.max < price {
-                    market.max = price.clone();
-                }
-
-            } else {
-                exchange.insert(
-                    orderbook.symbol.clone(),
-                    OLHC::new(price),
-                );
-            }
-        } else {
-            let mut markets = HashMap::<String, OLHC>::new();
-            markets.insert(
-                orderbook.symbol.clone(),
-                OLHC::new(price),
-            );
-            map.lock().await.insert(orderbook.exchange.clone(), markets);
+        if self.max < price {
+            self.max = price;
         }
     }
+}
+
+// Postgres allows 65535 bind parameters per statement, a row takes at most 9 of them
+pub const INSERT_CHUNK: usize = 1000;
 
-    pub fn save_map(map: MapOLHC, conn: &mut PgConnection) {
+// Merging a bar written twice for the same minute, e.g. a retried flush or two scrapers.
+// A real bar always replaces a synthetic one and a synthetic bar never overrides a real one,
+// otherwise the earlier open and the later close are kept and the extremes are combined
+const MERGE_OPEN: &str = "CASE WHEN bars_1min.synthetic AND NOT excluded.synthetic \
+    THEN excluded.open ELSE bars_1min.open END";
+const MERGE_CLOSE: &str = "CASE WHEN excluded.synthetic AND NOT bars_1min.synthetic \
+    THEN bars_1min.close ELSE excluded.close END";
+const MERGE_MIN: &str = "CASE WHEN bars_1min.synthetic AND NOT excluded.synthetic THEN excluded.min \
+    WHEN excluded.synthetic AND NOT bars_1min.synthetic THEN bars_1min.min \
+    ELSE LEAST(bars_1min.min, excluded.min) END";
+const MERGE_MAX: &str = "CASE WHEN bars_1min.synthetic AND NOT excluded.synthetic THEN excluded.max \
+    WHEN excluded.synthetic AND NOT bars_1min.synthetic THEN bars_1min.max \
+    ELSE GREATEST(bars_1min.max, excluded.max) END";
+// The bar is complete as soon as one of the writes covered the whole minute
+const MERGE_PARTIAL: &str = "bars_1min.partial AND excluded.partial";
+const MERGE_SYNTHETIC: &str = "bars_1min.synthetic AND excluded.synthetic";
+const MERGE_BACKFILLED: &str = "bars_1min.backfilled AND excluded.backfilled";
 
-        for (exchange, markets) in map.iter() {
-            for (market, olhc) in markets.iter() {
-                olhc.save_to_db(&exchange, &market, conn)
-            }
+// Upserts at most INSERT_CHUNK rows in one statement
+pub fn upsert_bars(rows: &[NewBar1min], conn: &mut PgConnection) -> QueryResult<usize> {
+    diesel::insert_into(bars_1min::table)
+        .values(rows)
+        .on_conflict((bars_1min::exchange, bars_1min::market, bars_1min::interval, bars_1min::timestamp))
+        .do_update()
+        .set((
+            bars_1min::open.eq(sql::<Numeric>(MERGE_OPEN)),
+            bars_1min::close.eq(sql::<Numeric>(MERGE_CLOSE)),
+            bars_1min::min.eq(sql::<Numeric>(MERGE_MIN)),
+            bars_1min::max.eq(sql::<Numeric>(MERGE_MAX)),
+            bars_1min::partial.eq(sql::<Bool>(MERGE_PARTIAL)),
+            bars_1min::synthetic.eq(sql::<Bool>(MERGE_SYNTHETIC)),
+            bars_1min::backfilled.eq(sql::<Bool>(MERGE_BACKFILLED)),
+        ))
+        .execute(conn)
+}
+
+// Upserts at most INSERT_CHUNK bars read from the klines of an exchange. They take the place of
+// synthetic, partial and earlier backfilled bars, a bar built over a whole minute of quotes is kept
+pub fn upsert_backfilled(rows: &[NewBar1min], conn: &mut PgConnection) -> QueryResult<usize> {
+    diesel::insert_into(bars_1min::table)
+        .values(rows)
+        .on_conflict((bars_1min::exchange, bars_1min::market, bars_1min::interval, bars_1min::timestamp))
+        .do_update()
+        .set((
+            bars_1min::open.eq(excluded(bars_1min::open)),
+            bars_1min::close.eq(excluded(bars_1min::close)),
+            bars_1min::min.eq(excluded(bars_1min::min)),
+            bars_1min::max.eq(excluded(bars_1min::max)),
+            bars_1min::partial.eq(false),
+            bars_1min::synthetic.eq(false),
+            bars_1min::backfilled.eq(true),
+        ))
+        .filter(bars_1min::synthetic.or(bars_1min::partial).or(bars_1min::backfilled))
+        .execute(conn)
+}
+
+impl ClosedBar {
+    // Saves the bars with multi-row upserts, returns the number of rows written.
+    // Call it inside a transaction so a failed chunk doesn't leave the minute half written
+    pub fn save_all(bars: &[ClosedBar], conn: &mut PgConnection) -> QueryResult<usize> {
+        let mut saved = 0;
+
+        for chunk in bars.chunks(INSERT_CHUNK) {
+            let rows = chunk
+                .iter()
+                .map(|bar| bar.to_row())
+                .collect::<Vec<NewBar1min>>();
+
+            saved += upsert_bars(&rows, conn)?;
         }
 
+        Ok(saved)
+    }
+
+    fn to_row(&self) -> NewBar1min<'_> {
+        NewBar1min::new(
+            self.exchange,
+            &self.market,
+            self.timestamp,
+            self.olhc.open.into(),
+            self.olhc.close.into(),
+            self.olhc.min.into(),
+            self.olhc.max.into(),
+            self.partial,
+            self.synthetic,
+        )
+    }
+}
+
+// Run against the database in TEST_DATABASE_URL, inside a transaction that's never committed.
+// Ignored by default, run them with `cargo test -- --ignored`
+#[cfg(test)]
+mod tests {
+    use super::{upsert_bars, ClosedBar, OLHC, INSERT_CHUNK};
+    use crate::aggregator::InstrumentId;
+
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+    use chrono::{NaiveDateTime, TimeDelta};
+    use diesel::{Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+    use db::migrations::run_pending_migrations;
+    use db::models::{Bar1min, NewBar1min};
+    use db::schema::bars_1min;
+    use exchange::structs::Price;
+
+    // Not an exchange the scraper writes, so stored bars don't get in the way
+    const EXCHANGE: &str = "Test";
+    const MINUTE: &str = "2025-01-01 10:00:00";
+
+    fn connection() -> PgConnection {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL isn't set");
+        let mut conn = PgConnection::establish(&url).expect("Error connecting to the test database");
+        run_pending_migrations(&mut conn).expect("Error migrating the test database");
+        conn.begin_test_transaction().expect("Error starting the test transaction");
+        conn
     }
 
-    fn save_to_db(&self, exchange: &String, market: &String, conn: &mut PgConnection) {
-        let bar_1min = NewBar1min::new(
-            exchange,
-            market,
-            self.open.clone(),
-            self.close.clone(),
-            self.min.clone(),
-            self.max.clone(),
-        );
-
-        diesel::insert_into(bars_1min::table)
-            .values(&bar_1min)
-            .execute(conn)
-            .expect("Error saving bars_1min");
+    fn minute() -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(MINUTE, "%Y-%m-%d %H:%M:%S").expect("Invalid minute")
     }
-}
\ No newline at end of file
+
+    // Bar of the minute with open, close, min and max
+    fn row(prices: [&str; 4], partial: bool, synthetic: bool) -> NewBar1min<'static> {
+        let [open, close, min, max] = prices.map(|price| BigDecimal::from_str(price).expect("Invalid price"));
+        NewBar1min::new(EXCHANGE, "BTCUSDT", minute(), open, close, min, max, partial, synthetic)
+    }
+
+    fn stored(conn: &mut PgConnection) -> Bar1min {
+        bars_1min::table
+            .filter(bars_1min::exchange.eq(EXCHANGE))
+            .filter(bars_1min::market.eq("BTCUSDT"))
+            .filter(bars_1min::timestamp.eq(minute()))
+            .first::<Bar1min>(conn)
+            .expect("Bar not stored")
+    }
+
+    fn prices(bar: &Bar1min) -> [String; 4] {
+        [&bar.open, &bar.close, &bar.min, &bar.max].map(|price| price.normalized().to_string())
+    }
+
+    #[test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    fn real_bars_keep_the_first_open_and_the_last_close_and_combine_the_extremes() {
+        let mut conn = connection();
+
+        upsert_bars(&[row(["100", "101", "99", "102"], true, false)], &mut conn).expect("Error upserting");
+        upsert_bars(&[row(["105", "103", "98", "101"], true, false)], &mut conn).expect("Error upserting");
+
+        let bar = stored(&mut conn);
+        assert_eq!(prices(&bar), ["100", "103", "98", "102"]);
+        assert!(bar.partial && !bar.synthetic && !bar.backfilled);
+    }
+
+    #[test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    fn a_full_write_completes_a_partial_bar() {
+        let mut conn = connection();
+
+        upsert_bars(&[row(["100", "100", "100", "100"], true, false)], &mut conn).expect("Error upserting");
+        upsert_bars(&[row(["100", "101", "100", "101"], false, false)], &mut conn).expect("Error upserting");
+        assert!(!stored(&mut conn).partial);
+
+        // and a later partial write doesn't make it partial again
+        upsert_bars(&[row(["101", "101", "101", "101"], true, false)], &mut conn).expect("Error upserting");
+        assert!(!stored(&mut conn).partial);
+    }
+
+    #[test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    fn real_bars_replace_synthetic_ones() {
+        let mut conn = connection();
+
+        upsert_bars(&[row(["100", "100", "100", "100"], false, true)], &mut conn).expect("Error upserting");
+        upsert_bars(&[row(["105", "103", "102", "106"], false, false)], &mut conn).expect("Error upserting");
+
+        let bar = stored(&mut conn);
+        assert_eq!(prices(&bar), ["105", "103", "102", "106"]);
+        assert!(!bar.synthetic);
+    }
+
+    #[test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    fn synthetic_bars_never_override_real_ones() {
+        let mut conn = connection();
+
+        upsert_bars(&[row(["105", "103", "102", "106"], false, false)], &mut conn).expect("Error upserting");
+        upsert_bars(&[row(["90", "90", "90", "90"], false, true)], &mut conn).expect("Error upserting");
+
+        let bar = stored(&mut conn);
+        assert_eq!(prices(&bar), ["105", "103", "102", "106"]);
+        assert!(!bar.synthetic);
+    }
+
+    #[test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    fn synthetic_bars_merge_like_real_ones() {
+        let mut conn = connection();
+
+        upsert_bars(&[row(["100", "100", "100", "100"], false, true)], &mut conn).expect("Error upserting");
+        upsert_bars(&[row(["101", "101", "101", "101"], false, true)], &mut conn).expect("Error upserting");
+
+        let bar = stored(&mut conn);
+        assert_eq!(prices(&bar), ["100", "101", "100", "101"]);
+        assert!(bar.synthetic);
+    }
+
+    #[test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    fn bars_are_saved_in_chunks() {
+        let mut conn = connection();
+
+        // More rows than the bind parameters of a single statement can hold
+        let count = 65535 / 9 + INSERT_CHUNK + 1;
+        let price = Price::parse("100").expect("Invalid price");
+        let bars = (0..count)
+            .map(|i| ClosedBar {
+                instrument: InstrumentId(0),
+                exchange: EXCHANGE,
+                market: "BTCUSDT".into(),
+                timestamp: minute() + TimeDelta::minutes(i as i64),
+                olhc: OLHC::new(price),
+                partial: false,
+                synthetic: false,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(ClosedBar::save_all(&bars, &mut conn).expect("Error saving"), count);
+        // Saving them again updates every row
+        assert_eq!(ClosedBar::save_all(&bars, &mut conn).expect("Error saving"), count);
+
+        let stored = bars_1min::table
+            .filter(bars_1min::exchange.eq(EXCHANGE))
+            .filter(bars_1min::market.eq("BTCUSDT"))
+            .filter(bars_1min::timestamp.ge(minute()))
+            .count()
+            .get_result::<i64>(&mut conn)
+            .expect("Error counting bars");
+        assert_eq!(stored as usize, count);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::aggregator::Aggregator;
+use crate::gaps::{Gap, GapPolicies, GapTracker};
+use crate::leader::Leadership;
+use crate::metrics;
+use crate::sinks::{BarSink, Deferred};
+use crate::status;
+use crate::structs::ClosedBar;
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use chrono::NaiveDateTime;
+use tokio::task::{JoinError, JoinSet};
+use tracing::{debug, error, info, warn};
+
+type SinkWrite = (&'static str, Result<usize>);
+type MinuteBars = Arc<(Vec<ClosedBar>, Vec<Gap>)>;
+
+// Minutes a standby keeps, so when it takes over it can write what the old leader didn't get to.
+// The election takes a few seconds, a minute boundary passes at most once in between
+const STANDBY_BACKLOG: usize = 3;
+
+// Takes the bars of each minute out of the aggregator and fans them out to the sinks.
+// Shared by the live engine and replay so both build bars the same way
+pub struct BarWriter {
+    sinks: Vec<Arc<dyn BarSink>>,
+    writes: JoinSet<SinkWrite>,
+    gaps: GapTracker,
+    failed: usize,
+    leadership: Leadership,
+    // Last minutes built while standing by, oldest first
+    backlog: VecDeque<(NaiveDateTime, MinuteBars)>,
+}
+
+impl BarWriter {
+    // Writes only while `leadership` says so, standbys build the same bars and hold the last ones back
+    pub fn new(sinks: Vec<Arc<dyn BarSink>>, gap_policies: GapPolicies, leadership: Leadership) -> Self {
+        Self {
+            sinks,
+            writes: JoinSet::new(),
+            gaps: GapTracker::new(gap_policies),
+            failed: 0,
+            leadership,
+            backlog: VecDeque::new(),
+        }
+    }
+
+    // Flushes the bars of the minute starting at `minute`, fills its gaps and starts writing them.
+    // The first write after taking over also writes the backlog minutes the old leader didn't complete
+    pub async fn write_minute(&mut self, aggregator: &Aggregator, minute: NaiveDateTime, partial: bool) {
+        let mut bars = aggregator.flush(minute).await;
+        let missing = self.gaps.fill(&mut bars, minute, &aggregator.expected(), &aggregator.retired());
+        if partial {
+            bars.iter_mut().for_each(|bar| bar.partial = true);
+        }
+        let minute_bars = Arc::new((bars, missing));
+
+        if !self.leadership.is_leader() {
+            debug!(minute = %minute, "Standing by, bars not written");
+            self.backlog.push_back((minute, minute_bars));
+            if self.backlog.len() > STANDBY_BACKLOG {
+                self.backlog.pop_front();
+            }
+            return;
+        }
+
+        let backlog = std::mem::take(&mut self.backlog);
+        if let Some(last_written) = self.leadership.take_over() {
+            for (minute, minute_bars) in backlog.into_iter().filter(|(minute, _)| *minute > last_written) {
+                info!(minute = %minute, "Writing a minute the previous leader didn't");
+                self.spawn_writes(minute_bars);
+            }
+        }
+        self.spawn_writes(minute_bars);
+
+        while let Some(write) = self.writes.try_join_next() {
+            if !Self::report_write(write) {
+                self.failed += 1;
+            }
+        }
+    }
+
+    // Flushes the unfinished minute as partial, no gaps are recorded for it
+    pub async fn write_last(&mut self, aggregator: &Aggregator, minute: NaiveDateTime) {
+        let mut bars = aggregator.flush(minute).await;
+        if !self.leadership.is_leader() {
+            return;
+        }
+        bars.iter_mut().for_each(|bar| bar.partial = true);
+        info!(bars = bars.len(), "Flushing partial bars");
+
+        self.spawn_writes(Arc::new((bars, Vec::new())));
+    }
+
+    fn spawn_writes(&mut self, minute_bars: MinuteBars) {
+        for sink in self.sinks.iter() {
+            let (sink, minute_bars) = (sink.clone(), minute_bars.clone());
+            self.writes.spawn_blocking(move || (sink.name(), sink.write(&minute_bars.0, &minute_bars.1)));
+        }
+    }
+
+    pub fn report(&self) {
+        self.sinks.iter().for_each(|sink| sink.report());
+    }
+
+    // Waits for all writes still in flight and closes the sinks, fails if any write failed
+    pub async fn finish(mut self) -> Result<()> {
+        while let Some(write) = self.writes.join_next().await {
+            if !Self::report_write(write) {
+                self.failed += 1;
+            }
+        }
+
+        for sink in self.sinks {
+            if let Err(e) = sink.close() {
+                error!(sink = sink.name(), error = format!("{:#}", e), "Error closing sink");
+                self.failed += 1;
+            }
+        }
+
+        if self.failed > 0 {
+            bail!("{} bar writes failed", self.failed);
+        }
+
+        Ok(())
+    }
+
+    // Logs the outcome of a sink write, returns false if it failed.
+    // Only records that reached the destination refresh the sink's last flush
+    fn report_write(write: Result<SinkWrite, JoinError>) -> bool {
+        match write {
+            Ok((sink, Ok(written))) => {
+                metrics::RECORDS_WRITTEN.with_label_values(&[sink]).inc_by(written as u64);
+                info!(sink, written, "Wrote records");
+                status::flushed(sink);
+                true
+            },
+            Ok((sink, Err(e))) if e.is::<Deferred>() => {
+                warn!(sink, reason = %e, "Bars not written yet");
+                true
+            },
+            Ok((sink, Err(e))) => {
+                metrics::SINK_WRITE_FAILURES.with_label_values(&[sink]).inc();
+                error!(sink, error = format!("{:#}", e), "Error writing bars");
+                false
+            },
+            Err(e) => {
+                error!(error = %e, "Bars writing task failed");
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BarWriter;
+    use crate::aggregator::Aggregator;
+    use crate::gaps::{Gap, GapPolicies, GapPolicy};
+    use crate::leader::Leadership;
+    use crate::queue::OverloadPolicy;
+    use crate::sinks::{BarSink, Deferred};
+    use crate::status;
+    use crate::structs::ClosedBar;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use chrono::DateTime;
+    use exchange::structs::{Instrument, Orderbook};
+
+    // Keeps every minute for later, like the postgres sink while the database is down
+    #[derive(Default)]
+    struct Deferring {
+        writes: AtomicUsize,
+    }
+
+    impl BarSink for Deferring {
+        fn name(&self) -> &'static str {
+            "deferring"
+        }
+
+        fn write(&self, _bars: &[ClosedBar], _gaps: &[Gap]) -> Result<usize> {
+            self.writes.fetch_add(1, Ordering::Relaxed);
+            Err(Deferred("Kept for later").into())
+        }
+    }
+
+    #[tokio::test]
+    async fn deferred_writes_are_neither_flushes_nor_failures() {
+        let aggregator = Aggregator::new(1, 100, OverloadPolicy::Block);
+        let mut publisher = aggregator.publisher("Binance");
+        assert!(publisher.publish(Orderbook::new("Binance", &Instrument::new("BTCUSDT", 8), "1", "10").expect("Invalid orderbook")).await);
+
+        let sink = Arc::new(Deferring::default());
+        let mut writer = BarWriter::new(vec![sink.clone()], GapPolicies::new(GapPolicy::Ignore), Leadership::always());
+        let minute = DateTime::from_timestamp(1_735_689_600, 0).expect("Invalid time").naive_utc();
+        writer.write_minute(&aggregator, minute, false).await;
+
+        writer.finish().await.expect("A deferred write isn't a failure");
+        assert_eq!(sink.writes.load(Ordering::Relaxed), 1);
+        assert!(status::snapshot().flushes.iter().all(|(sink, _)| *sink != "deferring"), "A deferred write refreshed the last flush");
+    }
+}
@@ -0,0 +1,174 @@
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
+use exchange::exchanges::Binance;
+use mock_exchange::{MockExchange, Venue};
+use scrapper_engine::aggregator::Aggregator;
+use scrapper_engine::backfill::{Backfill, BackfilledBar};
+use scrapper_engine::config::{Config, ConfigFormat};
+use scrapper_engine::engine::Engine;
+use scrapper_engine::queue::OverloadPolicy;
+use tokio::time::{timeout, Instant};
+use tokio_util::sync::CancellationToken;
+
+const WAIT: Duration = Duration::from_secs(10);
+
+// 2025-01-01 00:00 UTC
+const START: i64 = 1_735_689_600_000;
+const MINUTE: i64 = 60_000;
+
+fn minute(offset: i64) -> NaiveDateTime {
+    DateTime::from_timestamp_millis(START + offset * MINUTE).expect("Invalid time").naive_utc()
+}
+
+// Backfill reading the klines of `mock`, at most `requests_per_second` requests a second
+fn backfill(mock: &MockExchange, requests_per_second: u32) -> Backfill {
+    let name = match mock.venue() {
+        Venue::Binance => "binance",
+        Venue::ByBit => "bybit",
+        Venue::KuCoin => "kucoin",
+    };
+    let config = Config::parse(&format!(r#"
+        [exchanges.{}]
+        rest_url = "{}"
+
+        [backfill]
+        requests_per_second = {}
+    "#, name, mock.rest_url(), requests_per_second), ConfigFormat::Toml).expect("Invalid TOML");
+    Backfill::new(&config)
+}
+
+// (exchange, market, timestamp, open, close, min, max)
+fn rows(bars: &[BackfilledBar]) -> Vec<(&str, &str, NaiveDateTime, String, String, String, String)> {
+    bars.iter()
+        .map(|bar| (
+            bar.exchange,
+            bar.market.as_str(),
+            bar.timestamp,
+            bar.olhc.open.to_string(),
+            bar.olhc.close.to_string(),
+            bar.olhc.min.to_string(),
+            bar.olhc.max.to_string(),
+        ))
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn long_ranges_are_read_in_rate_limited_pages() {
+    let mock = MockExchange::binance().await;
+    for offset in 0..2500 {
+        let price = (100 + offset).to_string();
+        mock.add_kline("BTCUSDT", START + offset * MINUTE, [&price, &price, &price, &price]);
+    }
+
+    let started = Instant::now();
+    let bars = backfill(&mock, 4).fetch("binance", "btcusdt", minute(0), minute(2499)).await.expect("Backfill failed");
+
+    // 1000 klines a page, the 3 requests are 250 ms apart
+    assert_eq!(mock.kline_requests(), 3);
+    assert!(started.elapsed() >= Duration::from_millis(500), "Requests weren't spaced, took {:?}", started.elapsed());
+    assert_eq!(bars.len(), 2500);
+    assert!(bars.windows(2).all(|pair| pair[1].timestamp - pair[0].timestamp == TimeDelta::minutes(1)));
+    assert_eq!(rows(&bars[..1]), vec![("Binance", "BTCUSDT", minute(0), "100".into(), "100".into(), "100".into(), "100".into())]);
+    assert_eq!(bars[2499].timestamp, minute(2499));
+    assert_eq!(bars[2499].olhc.close.to_string(), "2599");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn full_kucoin_pages_keep_their_oldest_minute() {
+    let mock = MockExchange::kucoin().await;
+    for offset in 0..3000 {
+        mock.add_kline("btc-usdt", START + offset * MINUTE, ["1", "1", "1", "1"]);
+    }
+
+    let bars = backfill(&mock, 100).fetch("kucoin", "btc-usdt", minute(0), minute(2999)).await.expect("Backfill failed");
+
+    // KuCoin returns the newest 1500 klines of a request, a page must not ask for more
+    assert_eq!(mock.kline_requests(), 3);
+    assert_eq!(bars.len(), 3000);
+    assert!(bars.iter().enumerate().all(|(offset, bar)| bar.timestamp == minute(offset as i64)), "Minutes are missing");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn klines_of_every_exchange_are_read_oldest_first() {
+    for (mock, market) in [
+        (MockExchange::binance().await, "btcusdt"),
+        (MockExchange::bybit().await, "btcusdt"),
+        (MockExchange::kucoin().await, "btc-usdt"),
+    ] {
+        // open, high, low, close
+        mock.add_kline(market, START, ["100", "105.5", "99", "101"]);
+        mock.add_kline(market, START + MINUTE, ["101", "103", "100.25", "102"]);
+        mock.add_kline(market, START + 2 * MINUTE, ["102", "102", "95", "96"]);
+        // Outside of the range
+        mock.add_kline(market, START + 3 * MINUTE, ["1", "1", "1", "1"]);
+
+        let bars = backfill(&mock, 100).fetch(&format!("{:?}", mock.venue()), market, minute(0), minute(2)).await.expect("Backfill failed");
+
+        let exchange = match mock.venue() {
+            Venue::Binance => "Binance",
+            Venue::ByBit => "ByBit",
+            Venue::KuCoin => "KuCoin",
+        };
+        assert_eq!(rows(&bars), vec![
+            (exchange, "BTCUSDT", minute(0), "100".into(), "101".into(), "99".into(), "105.5".into()),
+            (exchange, "BTCUSDT", minute(1), "101".into(), "102".into(), "100.25".into(), "103".into()),
+            (exchange, "BTCUSDT", minute(2), "102".into(), "96".into(), "95".into(), "102".into()),
+        ], "Unexpected bars of {}", exchange);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rate_limited_requests_are_retried() {
+    // Binance says when to retry, ByBit and KuCoin are retried after a backoff
+    for mock in [MockExchange::binance().await, MockExchange::bybit().await, MockExchange::kucoin().await] {
+        let market = match mock.venue() {
+            Venue::KuCoin => "btc-usdt",
+            _ => "btcusdt",
+        };
+        mock.add_kline(market, START, ["100", "100", "100", "100"]);
+        mock.rate_limit_next(1);
+
+        let started = Instant::now();
+        let bars = backfill(&mock, 100).fetch(&format!("{:?}", mock.venue()), market, minute(0), minute(0)).await.expect("Backfill failed");
+
+        assert_eq!(bars.len(), 1, "No bar from {:?}", mock.venue());
+        assert_eq!(mock.kline_requests(), 2);
+        assert!(started.elapsed() >= Duration::from_secs(1), "{:?} was retried too early", mock.venue());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unknown_exchanges_are_rejected() {
+    let mock = MockExchange::binance().await;
+    let error = backfill(&mock, 100).fetch("kraken", "btcusd", minute(0), minute(0)).await.expect_err("Kraken should be unknown");
+
+    assert_eq!(error.to_string(), "Unknown exchange `kraken`, expected one of binance, bybit, kucoin");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reconnects_report_the_missed_minutes() {
+    let mock = MockExchange::binance().await;
+    let mut engine = Engine::new()
+        .add_markets(Binance::with_url(mock.url()).with_rest_url(mock.rest_url()), vec!["btcusdt".to_string()], Duration::from_secs(10))
+        .await
+        .expect("Error connecting");
+    let aggregator = Aggregator::new(1, 1000, OverloadPolicy::Block);
+    let shutdown = CancellationToken::new();
+    let feeds = Engine::publish_orderbooks(engine.feeds, &aggregator, shutdown.clone(), None);
+    timeout(WAIT, mock.wait_for_subscriptions(1)).await.expect("No subscription");
+
+    let disconnected = Utc::now();
+    mock.disconnect_all();
+    let outage = timeout(WAIT, engine.outages.recv()).await.expect("No outage reported").expect("Outages closed");
+
+    assert_eq!(outage.exchange, "Binance");
+    assert_eq!(outage.markets, vec!["btcusdt"]);
+    assert!(outage.from <= disconnected.naive_utc() && outage.from > disconnected.naive_utc() - TimeDelta::minutes(1));
+    assert!(outage.from <= outage.to && outage.to <= Utc::now().naive_utc());
+
+    shutdown.cancel();
+    for feed in feeds {
+        timeout(WAIT, feed).await.expect("Feed didn't stop").expect("Feed failed");
+    }
+}
@@ -0,0 +1,159 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, Timelike, Utc};
+use exchange::Exchange;
+use exchange::enums::AnyExchange;
+use exchange::exchanges::{Binance, ByBit};
+use mock_exchange::MockExchange;
+use scrapper_engine::aggregator::Aggregator;
+use scrapper_engine::capture::{capture_files, Capture};
+use scrapper_engine::engine::Engine;
+use scrapper_engine::gaps::{GapPolicies, GapPolicy};
+use scrapper_engine::queue::OverloadPolicy;
+use scrapper_engine::sinks::{BarSink, FileFormat, FileSink};
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+
+const WAIT: Duration = Duration::from_secs(10);
+const HEARTBEAT: Duration = Duration::from_secs(1);
+
+// (exchange, market, open, close, min, max)
+type Bar = (String, String, String, String, String, String);
+
+// Client of a replay, knowing the tick sizes of `markets` like a live connection
+async fn interned(mut exchange: impl Exchange + 'static, markets: &[&str]) -> Box<dyn Exchange> {
+    let markets = markets.iter().map(|market| market.to_string()).collect::<Vec<_>>();
+    AnyExchange::intern(&mut exchange, &markets).await.expect("Error looking up tick sizes");
+    Box::new(exchange)
+}
+
+// Bars the csv sink wrote to `dir`, without their minute and flags
+fn written_bars(dir: &Path) -> Vec<Bar> {
+    let mut bars = Vec::new();
+    for file in fs::read_dir(dir).expect("No sink output") {
+        let path = file.expect("Error listing sink output").path();
+        if !path.file_name().expect("No file name").to_string_lossy().starts_with("bars_1min-") {
+            continue;
+        }
+        for line in fs::read_to_string(&path).expect("Error reading bars").lines().skip(1) {
+            let fields = line.split(',').map(str::to_string).collect::<Vec<_>>();
+            bars.push((fields[0].clone(), fields[1].clone(), fields[3].clone(), fields[4].clone(), fields[5].clone(), fields[6].clone()));
+        }
+    }
+    bars.sort();
+    bars
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replaying_a_capture_gives_the_live_bars() {
+    let dir = std::env::temp_dir().join(format!("capture-test-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let (frames, output) = (dir.join("frames"), dir.join("bars"));
+
+    let binance = MockExchange::binance().await;
+    let bybit = MockExchange::bybit().await;
+    let engine = Engine::new()
+        .add_markets(Binance::with_url(binance.url()).with_rest_url(binance.rest_url()), vec!["btcusdt".to_string(), "ethusdt".to_string()], HEARTBEAT)
+        .await
+        .expect("Error connecting")
+        .add_markets(ByBit::with_url(bybit.url()).with_rest_url(bybit.rest_url()), vec!["btcusdt".to_string()], HEARTBEAT)
+        .await
+        .expect("Error connecting");
+    let (capture, capture_writer) = Capture::start(&frames).expect("Error starting capture");
+    let aggregator = Aggregator::new(2, 1000, OverloadPolicy::Block);
+    let shutdown = CancellationToken::new();
+    let feeds = Engine::publish_orderbooks(engine.feeds, &aggregator, shutdown.clone(), Some(capture));
+    timeout(WAIT, binance.wait_for_subscriptions(2)).await.expect("No subscription");
+    timeout(WAIT, bybit.wait_for_subscriptions(1)).await.expect("No subscription");
+
+    // The replay closes minutes by the capture time, keep every quote in the same minute
+    while Utc::now().second() >= 50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    binance.send_quote("btcusdt", "100.4", "100.5");
+    binance.send_quote("btcusdt", "101.9", "102");
+    binance.send_quote("btcusdt", "99.4", "99.5");
+    binance.send_quote("ethusdt", "10.1", "10.2");
+    bybit.send_quote("BTCUSDT", "100.1", "100.2");
+    bybit.send_quote("BTCUSDT", "100.3", "100.4");
+    timeout(WAIT, async {
+        while aggregator.feed_stats().iter().map(|(_, stats)| stats.queued).sum::<u64>() < 6 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await.expect("Ticks didn't reach the aggregator");
+
+    let mut live = aggregator
+        .flush(NaiveDateTime::default())
+        .await
+        .into_iter()
+        .map(|bar| (
+            bar.exchange.to_string(),
+            bar.market.to_string(),
+            bar.olhc.open.to_string(),
+            bar.olhc.close.to_string(),
+            bar.olhc.min.to_string(),
+            bar.olhc.max.to_string(),
+        ))
+        .collect::<Vec<_>>();
+    live.sort();
+    assert_eq!(live.len(), 3, "{:?}", live);
+
+    // The capture is complete once every feed dropped its clone
+    shutdown.cancel();
+    for feed in feeds {
+        timeout(WAIT, feed).await.expect("Feed didn't stop").expect("Feed failed");
+    }
+    timeout(WAIT, capture_writer).await.expect("Capture didn't finish").expect("Capture task failed").expect("Error capturing");
+
+    let files = capture_files(&frames).expect("Error listing captures");
+    assert!(!files.is_empty());
+    let exchanges = vec![
+        interned(Binance::new().with_rest_url(binance.rest_url()), &["btcusdt", "ethusdt"]).await,
+        interned(ByBit::new().with_rest_url(bybit.rest_url()), &["btcusdt"]).await,
+    ];
+    let sinks: Vec<Arc<dyn BarSink>> = vec![Arc::new(FileSink::new(&output, FileFormat::Csv).expect("Error creating sink"))];
+    let aggregator = Aggregator::new(2, 1000, OverloadPolicy::Block);
+    timeout(WAIT, Engine::replay(files, exchanges, aggregator, sinks, GapPolicies::new(GapPolicy::Ignore), 0.0))
+        .await
+        .expect("Replay didn't finish")
+        .expect("Error replaying");
+
+    assert_eq!(written_bars(&output), live);
+    fs::remove_dir_all(&dir).expect("Error removing files");
+}
+
+#[tokio::test]
+async fn replay_drops_frames_that_are_not_json_like_live() {
+    let dir = std::env::temp_dir().join(format!("capture-fixture-test-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("Error creating capture dir");
+
+    // Two quotes of one minute with a frame that isn't JSON between them
+    let capture = dir.join("frames-20250101-10.ndjson.zst");
+    let mut encoder = zstd::Encoder::new(File::create(&capture).expect("Error creating capture"), 0).expect("Error compressing");
+    encoder.write_all(include_bytes!("fixtures/unreadable-frame.ndjson")).expect("Error writing capture");
+    encoder.finish().expect("Error finishing capture");
+
+    // Only the tick sizes are read from the mock
+    let mock = MockExchange::binance().await;
+    mock.set_tick_size("btcusdt", "0.10000000");
+
+    let output = dir.join("bars");
+    let exchanges = vec![interned(Binance::new().with_rest_url(mock.rest_url()), &["btcusdt"]).await];
+    let sinks: Vec<Arc<dyn BarSink>> = vec![Arc::new(FileSink::new(&output, FileFormat::Csv).expect("Error creating sink"))];
+    let aggregator = Aggregator::new(1, 1000, OverloadPolicy::Block);
+    Engine::replay(vec![capture], exchanges, aggregator, sinks, GapPolicies::new(GapPolicy::Ignore), 0.0)
+        .await
+        .expect("Error replaying");
+
+    let bar = |values: [&str; 6]| -> Bar {
+        let [exchange, market, open, close, min, max] = values.map(str::to_string);
+        (exchange, market, open, close, min, max)
+    };
+    assert_eq!(written_bars(&output), vec![bar(["Binance", "BTCUSDT", "100.5", "102", "100.5", "102"])]);
+    fs::remove_dir_all(&dir).expect("Error removing files");
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use scrapper_engine::config::{Config, ConfigFormat};
+use scrapper_engine::logging::LogFormat;
+use scrapper_engine::queue::OverloadPolicy;
+use scrapper_engine::sinks::SinkKind;
+
+const TOML: &str = r#"
+[exchanges.Binance]
+markets = ["btcusdt", "ethusdt"]
+heartbeat_secs = 10
+shard_size = 1
+
+[exchanges.kucoin]
+markets = ["btc-usdt"]
+url = "ws://127.0.0.1:9000"
+token_url = "http://127.0.0.1:9001/token"
+
+[exchanges.bybit]
+enabled = false
+
+[api]
+bind = "0.0.0.0:8080"
+
+[database]
+url = "postgres://localhost/crypto"
+pool_size = 4
+
+[sinks]
+enabled = ["postgres", "parquet"]
+parquet_dir = "/var/lib/scraper/parquet"
+
+[aggregator]
+shards = 2
+overload_policy = "conflate"
+"#;
+
+const YAML: &str = r#"
+exchanges:
+  Binance:
+    markets: [btcusdt, ethusdt]
+    heartbeat_secs: 10
+    shard_size: 1
+  kucoin:
+    markets: [btc-usdt]
+    url: ws://127.0.0.1:9000
+    token_url: http://127.0.0.1:9001/token
+  bybit:
+    enabled: false
+api:
+  bind: 0.0.0.0:8080
+database:
+  url: postgres://localhost/crypto
+  pool_size: 4
+sinks:
+  enabled: [postgres, parquet]
+  parquet_dir: /var/lib/scraper/parquet
+aggregator:
+  shards: 2
+  overload_policy: conflate
+"#;
+
+fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars = vars.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect::<HashMap<_, _>>();
+    move |key| vars.get(key).cloned()
+}
+
+fn assert_parsed(config: &Config) {
+    config.validate().expect("Config should be valid");
+
+    let enabled = config.enabled_exchanges().map(|(name, _)| name).collect::<Vec<_>>();
+    assert_eq!(enabled, vec!["binance", "kucoin"]);
+
+    let binance = &config.exchanges["binance"];
+    assert_eq!(binance.markets, vec!["btcusdt", "ethusdt"]);
+    assert_eq!(binance.heartbeat(), Duration::from_secs(10));
+    assert_eq!(binance.shard_size, 1);
+    assert_eq!(config.exchanges["kucoin"].token_url.as_deref(), Some("http://127.0.0.1:9001/token"));
+
+    assert_eq!(config.api.address().expect("Invalid address").port(), 8080);
+    assert_eq!(config.database.pool_size, 4);
+    assert_eq!(config.sinks.enabled, vec![SinkKind::Postgres, SinkKind::Parquet]);
+    assert_eq!(config.sinks.spill_path, "spill/bars_1min.ndjson");
+    assert_eq!(config.aggregator.shard_count().expect("Invalid shards"), 2);
+    assert_eq!(config.aggregator.overload_policy, OverloadPolicy::Conflate);
+}
+
+#[test]
+fn toml_and_yaml_files_are_read_the_same() {
+    assert_parsed(&Config::parse(TOML, ConfigFormat::Toml).expect("Invalid TOML"));
+    assert_parsed(&Config::parse(YAML, ConfigFormat::Yaml).expect("Invalid YAML"));
+}
+
+#[test]
+fn env_variables_override_the_file() {
+    let mut config = Config::parse(TOML, ConfigFormat::Toml).expect("Invalid TOML");
+    config.apply_overrides(env(&[
+        ("MARKETS_BINANCE", "solusdt, adausdt"),
+        ("MARKETS_BYBIT", "btcusdt;ethusdt"),
+        ("ENABLED_BYBIT", "true"),
+        ("PING_INTERVAL", "5"),
+        ("API_BIND", "127.0.0.1:9999"),
+        ("DB_POOL_SIZE", "30"),
+        ("AUTO_MIGRATE", "true"),
+        ("API_DATABASE_URL", "postgres://replica/crypto"),
+        ("API_DB_POOL_SIZE", "40"),
+        ("SINKS", "csv,stdout"),
+        ("HA_ENABLED", "true"),
+        ("HA_LOCK_KEY", "42"),
+        ("REST_URL_KUCOIN", "http://127.0.0.1:9002"),
+        ("BACKFILL_AUTO", "false"),
+        ("BACKFILL_REQUESTS_PER_SECOND", "2"),
+        ("CAPTURE_DIR", "/var/lib/scraper/capture"),
+        ("RUST_LOG", "warn"),
+        ("LOG_FILTER", "info,scrapper_engine=debug"),
+        ("LOG_FORMAT", "json"),
+    ])).expect("Invalid overrides");
+
+    config.validate().expect("Config should be valid");
+    assert_eq!(config.exchanges["binance"].markets, vec!["solusdt", "adausdt"]);
+    assert_eq!(config.exchanges["bybit"].markets, vec!["btcusdt", "ethusdt"]);
+    assert_eq!(config.enabled_exchanges().count(), 3);
+    assert!(config.exchanges.values().all(|exchange| exchange.heartbeat_secs == 5));
+    assert_eq!(config.api.bind, "127.0.0.1:9999");
+    assert_eq!(config.database.pool_size, 30);
+    assert!(config.database.auto_migrate);
+    assert_eq!(config.database.scraper_pool().expect("No scraper pool"), ("postgres://localhost/crypto", 30));
+    assert_eq!(config.database.api_pool().expect("No api pool"), ("postgres://replica/crypto", 40));
+    assert_eq!(config.sinks.enabled, vec![SinkKind::Csv, SinkKind::Stdout]);
+    assert!(config.ha.enabled);
+    assert_eq!(config.ha.lock_key, 42);
+    assert_eq!(config.exchanges["kucoin"].rest_url.as_deref(), Some("http://127.0.0.1:9002"));
+    assert!(!config.backfill.auto);
+    assert_eq!(config.backfill.requests_per_second, 2);
+    assert_eq!(config.capture.dir.as_deref(), Some("/var/lib/scraper/capture"));
+    assert_eq!(config.logging.filter, "info,scrapper_engine=debug");
+    assert_eq!(config.logging.format, LogFormat::Json);
+}
+
+#[test]
+fn env_variables_alone_configure_the_scraper() {
+    let mut config = Config::default();
+    config.apply_overrides(env(&[
+        ("MARKETS_KUCOIN", "btc-usdt,eth-usdt"),
+        ("DATABASE_URL", "postgres://localhost/crypto"),
+    ])).expect("Invalid overrides");
+
+    config.validate().expect("Config should be valid");
+    assert_eq!(config.enabled_exchanges().map(|(name, _)| name).collect::<Vec<_>>(), vec!["kucoin"]);
+    assert_eq!(config.api.bind, "127.0.0.1:8000");
+    assert_eq!(config.database.pool_size, 15);
+    assert!(!config.database.auto_migrate);
+    assert_eq!(config.database.api_pool().expect("No api pool"), ("postgres://localhost/crypto", 15));
+    assert_eq!(config.capture.dir, None);
+    assert_eq!(config.logging.filter, "info");
+    assert_eq!(config.logging.format, LogFormat::Text);
+}
+
+#[test]
+fn invalid_env_values_name_the_variable() {
+    let mut config = Config::default();
+    let error = config.apply_overrides(env(&[("DB_POOL_SIZE", "many")])).expect_err("Pool size should be invalid");
+
+    assert_eq!(error.to_string(), "Invalid DB_POOL_SIZE `many`: invalid digit found in string");
+
+    let error = config.apply_overrides(env(&[("LOG_FORMAT", "xml")])).expect_err("Log format should be invalid");
+    assert_eq!(error.to_string(), "Invalid LOG_FORMAT `xml`: Unknown log format xml, expected text or json");
+}
+
+#[test]
+fn validation_reports_every_problem() {
+    let config = Config::parse(r#"
+        [exchanges.binance]
+        heartbeat_secs = 0
+        rest_url = "api.binance.com"
+
+        [exchanges.kraken]
+        markets = ["btcusd"]
+
+        [api]
+        bind = "localhost"
+
+        [database]
+        api_pool_size = 0
+
+        [sinks]
+        enabled = ["postgres"]
+
+        [logging]
+        filter = "info,scrapper_engine=loud"
+    "#, ConfigFormat::Toml).expect("Invalid TOML");
+
+    let error = config.validate().expect_err("Config should be invalid").to_string();
+    assert_eq!(error, [
+        "Invalid configuration:",
+        "  - exchanges.binance has no markets, list them in `markets` or MARKETS_BINANCE",
+        "  - exchanges.binance.heartbeat_secs must be at least 1",
+        "  - exchanges.binance.rest_url `api.binance.com` isn't a http:// or https:// url",
+        "  - Unknown exchange `kraken`, expected one of binance, bybit, kucoin",
+        "  - api.bind `localhost` isn't an address like 127.0.0.1:8000",
+        "  - database.api_pool_size must be at least 1",
+        "  - The postgres sink needs database.url or DATABASE_URL",
+        "  - logging.filter `info,scrapper_engine=loud` is invalid: error parsing level filter: expected one of \"off\", \"error\", \"warn\", \"info\", \"debug\", \"trace\", or a number 0-5",
+    ].join("\n"));
+}
+
+#[test]
+fn unknown_settings_are_rejected() {
+    let error = Config::parse("[database]\npoolsize = 4\n", ConfigFormat::Toml).expect_err("Typo should be rejected");
+    assert!(error.to_string().contains("unknown field `poolsize`"), "Unexpected error {}", error);
+
+    let error = Config::parse("[sinks]\nenabled = [\"kafka\"]\n", ConfigFormat::Toml).expect_err("Sink should be rejected");
+    assert!(error.to_string().contains("Unknown sink kafka"), "Unexpected error {}", error);
+}
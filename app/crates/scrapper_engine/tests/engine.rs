@@ -0,0 +1,331 @@
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+use exchange::Exchange;
+use exchange::exchanges::{Binance, ByBit, KuCoin};
+use mock_exchange::MockExchange;
+use scrapper_engine::aggregator::Aggregator;
+use scrapper_engine::config::{Config, ConfigFormat};
+use scrapper_engine::engine::Engine;
+use scrapper_engine::queue::OverloadPolicy;
+use scrapper_engine::reload::MarketControl;
+use scrapper_engine::status;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+
+const WAIT: Duration = Duration::from_secs(10);
+
+// Short enough for the heartbeat tests to see pings quickly
+const HEARTBEAT: Duration = Duration::from_secs(1);
+
+struct Running {
+    aggregator: Aggregator,
+    shutdown: CancellationToken,
+    feeds: Vec<JoinHandle<()>>,
+    control: MarketControl,
+}
+
+impl Running {
+    async fn start(exchange: impl Exchange + 'static, markets: &[&str]) -> Self {
+        let engine = Engine::new()
+            .add_markets(exchange, markets.iter().map(|market| market.to_string()).collect(), HEARTBEAT)
+            .await
+            .expect("Error connecting");
+
+        let aggregator = Aggregator::new(2, 1000, OverloadPolicy::Block);
+        let shutdown = CancellationToken::new();
+        let feeds = Engine::publish_orderbooks(engine.feeds, &aggregator, shutdown.clone(), None);
+
+        Self { aggregator, shutdown, feeds, control: engine.control }
+    }
+
+    // Applies the markets of a config file to the running feeds
+    async fn reload(&mut self, config: &str) {
+        let config = Config::parse(config, ConfigFormat::Toml).expect("Invalid TOML");
+        self.control.apply(&config, &self.aggregator).await;
+    }
+
+    // Waits until `count` ticks made it into the aggregator queues
+    async fn wait_for_ticks(&self, count: u64) {
+        timeout(WAIT, async {
+            while self.aggregator.feed_stats().iter().map(|(_, stats)| stats.queued).sum::<u64>() < count {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.expect("Ticks didn't reach the aggregator");
+    }
+
+    // Closes the bars and returns them as (market, open, close, min, max)
+    async fn bars(&self) -> Vec<(String, String, String, String, String)> {
+        let mut bars = self.aggregator
+            .flush(NaiveDateTime::default())
+            .await
+            .into_iter()
+            .map(|bar| (
+                bar.market.to_string(),
+                bar.olhc.open.to_string(),
+                bar.olhc.close.to_string(),
+                bar.olhc.min.to_string(),
+                bar.olhc.max.to_string(),
+            ))
+            .collect::<Vec<_>>();
+        bars.sort();
+        bars
+    }
+
+    async fn stop(self) {
+        self.shutdown.cancel();
+        for feed in self.feeds {
+            timeout(WAIT, feed).await.expect("Feed didn't stop").expect("Feed failed");
+        }
+    }
+}
+
+fn bar(market: &str, open: &str, close: &str, min: &str, max: &str) -> (String, String, String, String, String) {
+    (market.to_string(), open.to_string(), close.to_string(), min.to_string(), max.to_string())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn binance_quotes_become_bars() {
+    let mock = MockExchange::binance().await;
+    let running = Running::start(Binance::with_url(mock.url()).with_rest_url(mock.rest_url()), &["btcusdt", "ethusdt"]).await;
+    timeout(WAIT, mock.wait_for_subscriptions(2)).await.expect("No subscription");
+    assert_eq!(mock.subscriptions(), vec!["BTCUSDT", "ETHUSDT"]);
+
+    mock.send_quote("btcusdt", "100.4", "100.5");
+    mock.send_quote("btcusdt", "101.9", "102");
+    mock.send_quote("btcusdt", "99.4", "99.5");
+    mock.send_quote("btcusdt", "100.6", "100.75");
+    mock.send_quote("ethusdt", "10.1", "10.2");
+    running.wait_for_ticks(5).await;
+
+    assert_eq!(running.bars().await, vec![
+        bar("BTCUSDT", "100.5", "100.75", "99.5", "102"),
+        bar("ETHUSDT", "10.2", "10.2", "10.2", "10.2"),
+    ]);
+
+    running.stop().await;
+    timeout(WAIT, mock.wait_until(|mock| mock.closed() == 1)).await.expect("Connection wasn't closed");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn binance_server_pings_are_answered() {
+    let mock = MockExchange::binance().await;
+    let running = Running::start(Binance::with_url(mock.url()).with_rest_url(mock.rest_url()), &["btcusdt"]).await;
+
+    // The feed only answers while it's reading, which it always is between updates
+    timeout(WAIT, mock.wait_until(|mock| mock.pongs() >= 2)).await.expect("Pings weren't answered");
+
+    running.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bybit_subscribes_and_sends_heartbeats() {
+    let mock = MockExchange::bybit().await;
+    let running = Running::start(ByBit::with_url(mock.url()).with_rest_url(mock.rest_url()), &["btcusdt"]).await;
+    timeout(WAIT, mock.wait_for_subscriptions(1)).await.expect("No subscription");
+    assert_eq!(mock.subscriptions(), vec!["BTCUSDT"]);
+
+    mock.send_quote("BTCUSDT", "64999.9", "65000.1");
+    running.wait_for_ticks(1).await;
+
+    // A heartbeat goes out with the first update after PING_INTERVAL
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    mock.send_quote("BTCUSDT", "65001.9", "65002");
+    timeout(WAIT, mock.wait_until(|mock| mock.pings() >= 1)).await.expect("No heartbeat");
+    running.wait_for_ticks(2).await;
+
+    assert_eq!(running.bars().await, vec![bar("BTCUSDT", "65000.1", "65002", "65000.1", "65002")]);
+
+    running.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn kucoin_connects_with_a_token() {
+    let mock = MockExchange::kucoin().await;
+    let running = Running::start(KuCoin::with_urls(mock.url(), mock.token_url()).with_rest_url(mock.rest_url()), &["btc-usdt", "eth-usdt"]).await;
+    timeout(WAIT, mock.wait_for_subscriptions(2)).await.expect("No subscription");
+    assert_eq!(mock.token_requests(), 1);
+    assert_eq!(mock.subscriptions(), vec!["BTC-USDT", "ETH-USDT"]);
+
+    mock.send_quote("BTC-USDT", "64999.9", "65000.10");
+    mock.send_quote("ETH-USDT", "2500", "2500.5");
+    running.wait_for_ticks(2).await;
+
+    assert_eq!(running.bars().await, vec![
+        bar("BTCUSDT", "65000.1", "65000.1", "65000.1", "65000.1"),
+        bar("ETHUSDT", "2500.5", "2500.5", "2500.5", "2500.5"),
+    ]);
+
+    running.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reconnects_after_the_server_closes() {
+    let mock = MockExchange::binance().await;
+    let running = Running::start(Binance::with_url(mock.url()).with_rest_url(mock.rest_url()), &["btcusdt"]).await;
+    timeout(WAIT, mock.wait_for_subscriptions(1)).await.expect("No subscription");
+
+    mock.send_quote("btcusdt", "1", "100");
+    running.wait_for_ticks(1).await;
+
+    mock.disconnect_all();
+    timeout(WAIT, mock.wait_for_subscriptions(2)).await.expect("No resubscription");
+    assert_eq!(mock.connections(), 2);
+
+    mock.send_quote("btcusdt", "1", "90");
+    running.wait_for_ticks(2).await;
+
+    assert_eq!(running.bars().await, vec![bar("BTCUSDT", "100", "90", "90", "100")]);
+
+    running.stop().await;
+}
+
+#[tokio::test]
+async fn unreachable_exchanges_fail_to_connect() {
+    let error = Engine::new()
+        .add_markets(Binance::with_url("ws://127.0.0.1:1").with_rest_url("http://127.0.0.1:1"), vec!["btcusdt".to_string()], HEARTBEAT)
+        .await
+        .err()
+        .expect("Connected to nothing");
+
+    assert!(error.to_string().starts_with("Error connecting to Binance"), "Unexpected error {:#}", error);
+}
+
+// Value of a metric sample with the given labels, 0 if it wasn't recorded
+fn metric(name: &str, labels: &[(&str, &str)]) -> f64 {
+    prometheus::gather()
+        .iter()
+        .filter(|family| family.name() == name)
+        .flat_map(|family| family.get_metric().iter())
+        .filter(|metric| labels.iter().all(|(label, value)| {
+            metric.get_label().iter().any(|pair| pair.name() == *label && pair.value() == *value)
+        }))
+        .map(|metric| metric.get_counter().get_value() + metric.get_gauge().get_value())
+        .sum()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn feeds_record_metrics() {
+    let mock = MockExchange::kucoin().await;
+    let running = Running::start(KuCoin::with_urls(mock.url(), mock.token_url()).with_rest_url(mock.rest_url()), &["sol-usdt"]).await;
+    timeout(WAIT, mock.wait_for_subscriptions(1)).await.expect("No subscription");
+    let parsed = metric("scraper_messages_parsed_total", &[("exchange", "KuCoin")]);
+
+    mock.send_quote("SOL-USDT", "150", "150.1");
+    running.wait_for_ticks(1).await;
+
+    assert!(metric("scraper_messages_parsed_total", &[("exchange", "KuCoin")]) >= parsed + 1.0);
+    assert!(metric("scraper_messages_received_total", &[("exchange", "KuCoin")]) >= 1.0);
+    let age = metric("scraper_last_message_age_seconds", &[("exchange", "KuCoin"), ("market", "SOLUSDT")]);
+    assert!((0.0..WAIT.as_secs_f64()).contains(&age), "Unexpected age {}", age);
+    assert!(status::snapshot().markets.iter().any(|market| market.exchange == "KuCoin" && market.market == "SOLUSDT"));
+
+    running.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn frames_that_are_not_json_are_dropped() {
+    let mock = MockExchange::bybit().await;
+    let running = Running::start(ByBit::with_url(mock.url()).with_rest_url(mock.rest_url()), &["adausdt"]).await;
+    timeout(WAIT, mock.wait_for_subscriptions(1)).await.expect("No subscription");
+
+    mock.send_raw("<html>502 Bad Gateway</html>");
+    mock.send_quote("ADAUSDT", "0.49", "0.5");
+    running.wait_for_ticks(1).await;
+
+    // Same connection, the feed went on with the next frame
+    assert_eq!(mock.connections(), 1);
+    assert_eq!(running.bars().await, vec![bar("ADAUSDT", "0.5", "0.5", "0.5", "0.5")]);
+
+    running.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn quotes_finer_than_the_tick_size_are_dropped() {
+    let mock = MockExchange::kucoin().await;
+    mock.set_tick_size("xrp-usdt", "0.0001");
+    let running = Running::start(KuCoin::with_urls(mock.url(), mock.token_url()).with_rest_url(mock.rest_url()), &["xrp-usdt"]).await;
+    timeout(WAIT, mock.wait_for_subscriptions(1)).await.expect("No subscription");
+    assert_eq!(mock.instrument_requests(), 1);
+
+    mock.send_quote("XRP-USDT", "2.1", "2.10005");
+    mock.send_quote("XRP-USDT", "2.1", "2.1001");
+    mock.send_quote("XRP-USDT", "2.1", "2.10000000");
+    running.wait_for_ticks(2).await;
+
+    assert_eq!(running.bars().await, vec![bar("XRPUSDT", "2.1001", "2.1", "2.1", "2.1001")]);
+
+    running.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn markets_without_updates_age_from_their_subscription() {
+    let mock = MockExchange::binance().await;
+    let running = Running::start(Binance::with_url(mock.url()).with_rest_url(mock.rest_url()), &["dogeusdt"]).await;
+    timeout(WAIT, mock.wait_for_subscriptions(1)).await.expect("No subscription");
+
+    // Never sends a quote, still gets a clock the health checks see age once the feed runs
+    let market = timeout(WAIT, async {
+        loop {
+            let found = status::snapshot()
+                .markets
+                .into_iter()
+                .find(|market| market.exchange == "Binance" && market.market == "DOGEUSDT");
+            match found {
+                Some(market) => break market,
+                None => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        }
+    }).await.expect("No status of the market");
+    assert!(chrono::Utc::now() - market.last_message < chrono::TimeDelta::seconds(WAIT.as_secs() as i64));
+
+    running.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reload_subscribes_added_markets_without_resetting_bars() {
+    let mock = MockExchange::bybit().await;
+    let mut running = Running::start(ByBit::with_url(mock.url()).with_rest_url(mock.rest_url()), &["btcusdt"]).await;
+    timeout(WAIT, mock.wait_for_subscriptions(1)).await.expect("No subscription");
+
+    mock.send_quote("BTCUSDT", "99.9", "100");
+    running.wait_for_ticks(1).await;
+
+    running.reload("[exchanges.bybit]\nmarkets = [\"btcusdt\", \"ethusdt\"]\n").await;
+    timeout(WAIT, mock.wait_for_subscriptions(2)).await.expect("No subscription of the added market");
+    assert_eq!(mock.subscriptions(), vec!["BTCUSDT", "ETHUSDT"]);
+    assert_eq!(mock.connections(), 1);
+
+    mock.send_quote("BTCUSDT", "100.9", "101");
+    mock.send_quote("ETHUSDT", "9.9", "10");
+    running.wait_for_ticks(3).await;
+
+    assert_eq!(running.bars().await, vec![
+        bar("BTCUSDT", "100", "101", "100", "101"),
+        bar("ETHUSDT", "10", "10", "10", "10"),
+    ]);
+
+    running.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reload_unsubscribes_removed_markets() {
+    let mock = MockExchange::binance().await;
+    let mut running = Running::start(Binance::with_url(mock.url()).with_rest_url(mock.rest_url()), &["btcusdt", "ethusdt"]).await;
+    timeout(WAIT, mock.wait_for_subscriptions(2)).await.expect("No subscription");
+
+    running.reload("[exchanges.binance]\nmarkets = [\"ethusdt\"]\n").await;
+    timeout(WAIT, mock.wait_until(|mock| !mock.unsubscriptions().is_empty())).await.expect("No unsubscription");
+    assert_eq!(mock.unsubscriptions(), vec!["BTCUSDT"]);
+    assert_eq!(running.control.markets(), vec![("Binance", 0, vec!["ethusdt".to_string()])]);
+
+    // The removed market no longer reaches the connection
+    mock.send_quote("btcusdt", "1", "100");
+    mock.send_quote("ethusdt", "10.1", "10.2");
+    running.wait_for_ticks(1).await;
+
+    assert_eq!(running.bars().await, vec![bar("ETHUSDT", "10.2", "10.2", "10.2", "10.2")]);
+
+    running.stop().await;
+}
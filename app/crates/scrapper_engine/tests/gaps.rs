@@ -0,0 +1,130 @@
+use chrono::{DateTime, NaiveDateTime, TimeDelta};
+use exchange::structs::{Instrument, Orderbook};
+use scrapper_engine::aggregator::Aggregator;
+use scrapper_engine::config::{Config, ConfigFormat};
+use scrapper_engine::gaps::{GapPolicy, GapTracker};
+use scrapper_engine::queue::OverloadPolicy;
+
+// 2025-01-01 00:00 UTC
+fn minute(offset: i64) -> NaiveDateTime {
+    DateTime::from_timestamp(1_735_689_600, 0).expect("Invalid time").naive_utc() + TimeDelta::minutes(offset)
+}
+
+fn tracker(config: &str) -> GapTracker {
+    let config = Config::parse(config, ConfigFormat::Toml).expect("Invalid TOML");
+    config.validate().expect("Config should be valid");
+    GapTracker::new(config.gap_policies())
+}
+
+// Closes the minute and fills its gaps, returns the bars as (market, close, synthetic) and the gap markets
+async fn close_minute(aggregator: &Aggregator, tracker: &mut GapTracker, offset: i64) -> (Vec<(String, String, bool)>, Vec<String>) {
+    let mut bars = aggregator.flush(minute(offset)).await;
+    let gaps = tracker.fill(&mut bars, minute(offset), &aggregator.expected(), &aggregator.retired());
+
+    let mut bars = bars.iter()
+        .map(|bar| (bar.market.to_string(), bar.olhc.close.to_string(), bar.synthetic))
+        .collect::<Vec<_>>();
+    bars.sort();
+    let mut gaps = gaps.iter().map(|gap| gap.market.to_string()).collect::<Vec<_>>();
+    gaps.sort();
+    (bars, gaps)
+}
+
+#[tokio::test]
+async fn subscribed_markets_get_gaps_before_their_first_update() {
+    let aggregator = Aggregator::new(2, 100, OverloadPolicy::Block);
+    let mut tracker = tracker(r#"
+        [exchanges.binance]
+        markets = ["btcusdt", "ethusdt", "solusdt"]
+        gap_policies = { ethusdt = "carry_forward", solusdt = "ignore" }
+
+        [sinks]
+        enabled = ["stdout"]
+    "#);
+    for market in ["BTCUSDT", "ETHUSDT", "SOLUSDT"] {
+        aggregator.expect("Binance", market);
+    }
+
+    // The feed is down from the start, nothing to carry forward yet
+    let (bars, gaps) = close_minute(&aggregator, &mut tracker, 0).await;
+    assert!(bars.is_empty());
+    assert_eq!(gaps, ["BTCUSDT", "ETHUSDT"]);
+
+    let mut publisher = aggregator.publisher("Binance");
+    publisher.publish(Orderbook::new("Binance", &Instrument::new("ETHUSDT", 8), "1", "3400.5").expect("Invalid orderbook")).await;
+    let (bars, gaps) = close_minute(&aggregator, &mut tracker, 1).await;
+    assert_eq!(bars, [("ETHUSDT".to_string(), "3400.5".to_string(), false)]);
+    assert_eq!(gaps, ["BTCUSDT"]);
+
+    // Once it had a close the carry_forward market gets flat bars
+    let (bars, gaps) = close_minute(&aggregator, &mut tracker, 2).await;
+    assert_eq!(bars, [("ETHUSDT".to_string(), "3400.5".to_string(), true)]);
+    assert_eq!(gaps, ["BTCUSDT"]);
+}
+
+#[tokio::test]
+async fn retired_markets_stop_getting_gaps() {
+    let aggregator = Aggregator::new(1, 100, OverloadPolicy::Block);
+    let mut tracker = tracker(r#"
+        [gaps]
+        policy = "record"
+
+        [sinks]
+        enabled = ["stdout"]
+    "#);
+    aggregator.expect("ByBit", "BTCUSDT");
+    aggregator.expect("ByBit", "ETHUSDT");
+
+    assert_eq!(close_minute(&aggregator, &mut tracker, 0).await.1, ["BTCUSDT", "ETHUSDT"]);
+    aggregator.retire("ByBit", "ETHUSDT");
+    assert_eq!(close_minute(&aggregator, &mut tracker, 1).await.1, ["BTCUSDT"]);
+    aggregator.reinstate("ByBit", "ETHUSDT");
+    assert_eq!(close_minute(&aggregator, &mut tracker, 2).await.1, ["BTCUSDT", "ETHUSDT"]);
+}
+
+#[test]
+fn policies_are_read_per_market() {
+    let mut config = Config::parse(r#"
+        [gaps]
+        policy = "ignore"
+
+        [exchanges.kucoin]
+        markets = ["btc-usdt", "eth-usdt"]
+        gap_policies = { "btc-usdt" = "record" }
+
+        [sinks]
+        enabled = ["stdout"]
+    "#, ConfigFormat::Toml).expect("Invalid TOML");
+    config.apply_overrides(|key| (key == "GAP_POLICY_KUCOIN_ETHUSDT").then(|| "carry_forward".to_string()))
+        .expect("Invalid overrides");
+    config.validate().expect("Config should be valid");
+
+    let policies = config.gap_policies();
+    assert_eq!(policies.policy("KuCoin", "BTCUSDT"), GapPolicy::Record);
+    assert_eq!(policies.policy("KuCoin", "ETHUSDT"), GapPolicy::CarryForward);
+    assert_eq!(policies.policy("KuCoin", "SOLUSDT"), GapPolicy::Ignore);
+    assert_eq!(policies.policy("Binance", "BTCUSDT"), GapPolicy::Ignore);
+    assert_eq!(Config::default().gaps.policy, GapPolicy::Record);
+}
+
+#[test]
+fn invalid_policies_are_rejected_up_front() {
+    let mut config = Config::default();
+    let error = config.apply_overrides(|key| (key == "GAP_POLICY").then(|| "fill".to_string()))
+        .expect_err("Policy should be invalid");
+    assert_eq!(error.to_string(), "Invalid GAP_POLICY `fill`: Unknown gap policy fill, expected carry_forward, record or ignore");
+
+    let error = Config::parse("[gaps]\npolicy = \"fill\"\n", ConfigFormat::Toml).expect_err("Policy should be invalid");
+    assert!(error.to_string().contains("Unknown gap policy fill"), "Unexpected error {}", error);
+
+    let config = Config::parse(r#"
+        [exchanges.binance]
+        markets = ["btcusdt"]
+        gap_policies = { ethusdt = "record" }
+
+        [sinks]
+        enabled = ["stdout"]
+    "#, ConfigFormat::Toml).expect("Invalid TOML");
+    let error = config.validate().expect_err("Config should be invalid");
+    assert_eq!(error.to_string(), "Invalid configuration:\n  - exchanges.binance.gap_policies names `ethusdt`, which isn't one of its markets");
+}
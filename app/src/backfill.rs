@@ -0,0 +1,82 @@
+use crate::BackfillArgs;
+use crate::migrate::check_schema;
+
+use anyhow::{bail, Context, Result};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use tracing::{error, info};
+use db::db::init_pool;
+use scrapper_engine::backfill::{last_complete_minute, Backfill};
+use scrapper_engine::config::{Config, EXCHANGES};
+
+// Writes the klines of a range of minutes to bars_1min, for the markets of one exchange or of every enabled one
+pub async fn backfill(config: &Config, args: BackfillArgs) -> Result<()> {
+    let to = args.to.unwrap_or_else(last_complete_minute);
+    if args.from > to {
+        bail!("--from {} is after --to {}", args.from, to);
+    }
+
+    let targets = match args.exchange {
+        Some(exchange) => {
+            let exchange = exchange.to_lowercase();
+            if !EXCHANGES.contains(&exchange.as_str()) {
+                bail!("Unknown exchange `{}`, expected one of {}", exchange, EXCHANGES.join(", "));
+            }
+            let markets = match args.markets.is_empty() {
+                true => config.exchanges.get(&exchange).map(|section| section.markets.clone()).unwrap_or_default(),
+                false => args.markets,
+            };
+            if markets.is_empty() {
+                bail!("No markets to backfill on {}, pass --markets or configure exchanges.{}.markets", exchange, exchange);
+            }
+            vec![(exchange, markets)]
+        },
+        None => config.enabled_exchanges()
+            .map(|(name, section)| (name.to_string(), section.markets.clone()))
+            .collect(),
+    };
+    if targets.is_empty() {
+        bail!("No exchange enabled, pass --exchange and --markets");
+    }
+
+    let (url, size) = config.database.scraper_pool()?;
+    let pool = init_pool(url, size).context("Can't create connection pool")?;
+    check_schema(&pool, config.database.auto_migrate)?;
+
+    let backfill = Backfill::new(config);
+    let mut failed = 0;
+    for (exchange, markets) in targets {
+        for market in markets {
+            match backfill.backfill(&pool, &exchange, &market, args.from, to).await {
+                Ok(written) => info!(exchange, market, from = %args.from, to = %to, bars = written, "Backfilled"),
+                Err(e) => {
+                    error!(exchange, market, error = format!("{:#}", e), "Error backfilling");
+                    failed += 1;
+                },
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{} markets failed to backfill", failed);
+    }
+
+    Ok(())
+}
+
+// yyyy-mm-ddThh:mm, or yyyy-mm-dd for the first minute of the day
+pub fn parse_from(value: &str) -> Result<NaiveDateTime, String> {
+    parse_minute(value, NaiveTime::MIN)
+}
+
+// yyyy-mm-ddThh:mm, or yyyy-mm-dd for the last minute of the day
+pub fn parse_to(value: &str) -> Result<NaiveDateTime, String> {
+    parse_minute(value, NaiveTime::from_hms_opt(23, 59, 0).expect("Invalid time"))
+}
+
+fn parse_minute(value: &str, time_of_day: NaiveTime) -> Result<NaiveDateTime, String> {
+    if let Ok(minute) = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M") {
+        return Ok(minute);
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|day| day.and_time(time_of_day))
+        .map_err(|_| format!("`{}` isn't a day like 2025-01-31 or a minute like 2025-01-31T12:30", value))
+}
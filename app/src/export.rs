@@ -0,0 +1,40 @@
+use crate::ExportArgs;
+use crate::migrate::check_schema;
+
+use std::fs::File;
+use std::io::BufWriter;
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use tracing::info;
+use db::db::init_pool;
+use scrapper_engine::config::Config;
+use scrapper_engine::sinks::{export_csv, FilePeriod, ParquetArchive};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Parquet,
+}
+
+// Writes the bars stored in bars_1min to a CSV file or to the parquet archive
+pub fn export(config: &Config, args: ExportArgs) -> Result<()> {
+    let pool = init_pool(config.database.url()?, 1).context("Can't create connection pool")?;
+    check_schema(&pool, config.database.auto_migrate)?;
+    let mut conn = pool.get()?;
+
+    let (written, output) = match args.format {
+        ExportFormat::Csv => {
+            let path = args.output.ok_or_else(|| anyhow!("The csv export needs --output"))?;
+            let mut out = BufWriter::new(File::create(&path)?);
+            (export_csv(&mut conn, &mut out, args.from, args.to)?, path)
+        },
+        ExportFormat::Parquet => {
+            let dir = args.output.unwrap_or_else(|| config.sinks.parquet_dir.clone().into());
+            let mut archive = ParquetArchive::new(&dir, FilePeriod::Day)?;
+            (archive.backfill(&mut conn, args.from, args.to)?, dir)
+        },
+    };
+    info!(written, output = %output.display(), "Export finished");
+
+    Ok(())
+}
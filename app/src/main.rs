@@ -1,39 +1,174 @@
-use axum::serve;
+mod backfill;
+mod export;
+mod migrate;
+mod replay;
+mod run;
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+use chrono::{NaiveDate, NaiveDateTime};
+use clap::{Args, Parser, Subcommand};
 use dotenv::dotenv;
 use rustls::crypto::ring;
-use tokio::net::TcpListener;
-use ::exchange::exchanges::{Binance, ByBit, KuCoin};
-use api::get_app;
-use db::db::init_pool;
-use scrapper_engine::engine::Engine;
+use tracing::error;
+use scrapper_engine::config::Config;
+use scrapper_engine::logging::init_logging;
+
+// Exit codes of every command. Invalid arguments exit with clap's 2
+const EXIT_FAILURE: u8 = 1;
+const EXIT_CONFIG: u8 = 3;
+
+#[derive(Parser)]
+#[command(version, about = "Scrapes crypto exchange quotes into 1-minute bars and serves them over a REST api")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the scraper and the api until Ctrl-C or SIGTERM
+    Run(RunArgs),
+    /// Apply the pending database migrations
+    Migrate,
+    /// Export the stored bars of a range of days to CSV or Parquet
+    Export(ExportArgs),
+    /// Validate the configuration and print what would run
+    CheckConfig,
+    /// Rebuild bars from captured frames and write them to the configured sinks
+    Replay(ReplayArgs),
+    /// Write the 1-minute klines of the exchanges' REST apis to the database for a range of minutes
+    Backfill(BackfillArgs),
+}
+
+#[derive(Args)]
+struct RunArgs {
+    /// Run the scraper. Without --scraper or --api both run
+    #[arg(long)]
+    scraper: bool,
+    /// Run the api
+    #[arg(long)]
+    api: bool,
+}
+
+impl RunArgs {
+    // (scraper, api), both when neither was picked
+    fn components(&self) -> (bool, bool) {
+        match (self.scraper, self.api) {
+            (false, false) => (true, true),
+            picked => picked,
+        }
+    }
+}
+
+#[derive(Args)]
+struct ExportArgs {
+    #[arg(long, value_enum, default_value_t = export::ExportFormat::Csv)]
+    format: export::ExportFormat,
+    /// First day to export, yyyy-mm-dd. Defaults to the first stored bar
+    #[arg(long)]
+    from: Option<NaiveDate>,
+    /// Last day to export (inclusive), yyyy-mm-dd. Defaults to the last stored bar
+    #[arg(long)]
+    to: Option<NaiveDate>,
+    /// CSV file, or Parquet archive directory (defaults to sinks.parquet_dir)
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ReplayArgs {
+    /// 1.0 replays at the original pace, 10.0 ten times faster, 0 as fast as possible
+    #[arg(long, default_value_t = 0.0)]
+    speed: f64,
+    /// Capture files or directories of them
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct BackfillArgs {
+    /// First minute to backfill (UTC), yyyy-mm-ddThh:mm, or yyyy-mm-dd for the whole day
+    #[arg(long, value_parser = backfill::parse_from)]
+    from: NaiveDateTime,
+    /// Last minute to backfill (inclusive). Defaults to the last complete minute
+    #[arg(long, value_parser = backfill::parse_to)]
+    to: Option<NaiveDateTime>,
+    /// Exchange to backfill. Defaults to every enabled exchange
+    #[arg(long)]
+    exchange: Option<String>,
+    /// Markets to backfill, separated by commas. Defaults to the configured markets of the exchange
+    #[arg(long, value_delimiter = ',', requires = "exchange")]
+    markets: Vec<String>,
+}
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
 
     ring::default_provider().install_default().unwrap();
     dotenv().ok();
 
-    let pool = init_pool();
-    let (scraper_pool, api_pool) = (pool.clone(), pool.clone());
-    // SCRAPER ENGINE
-    tokio::spawn(async move {
-        let engine = Engine::new()
-            .add(Binance::new()).await
-            .add(ByBit::new()).await
-            .add(KuCoin::new()).await;
-
-        Engine::save_bars_1min(
-            Engine::get_orderbooks_receiver(engine.exchanges).await,
-            scraper_pool,
-        ).await.expect("Error saving 1 min bars");
-    });
-
-    // REST API
-    let app = get_app(api_pool);
-    let listener = TcpListener::bind("127.0.0.1:8000")
-        .await
-        .expect("Error creating TCP listener");
-
-    println!("Starting api server at http://127.0.0.1:8000");
-    serve(listener, app).await.expect("Error starting api server");
+    // The config says how to log, so its own errors go straight to stderr
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            return ExitCode::from(EXIT_CONFIG);
+        }
+    };
+    if let Err(e) = init_logging(&config.logging) {
+        eprintln!("{:#}", e);
+        return ExitCode::from(EXIT_CONFIG);
+    }
+
+    let result = match cli.command {
+        Command::Run(args) => {
+            if args.components().0 && config.enabled_exchanges().next().is_none() {
+                error!("No exchange enabled, add an [exchanges.<name>] section with markets or set MARKETS_<EXCHANGE>");
+                return ExitCode::from(EXIT_CONFIG);
+            }
+            run::run(config, args).await
+        },
+        Command::Migrate => migrate::migrate(&config),
+        Command::Export(args) => export::export(&config, args),
+        Command::CheckConfig => {
+            check_config(&config);
+            Ok(())
+        },
+        Command::Replay(args) => replay::replay(&config, args).await,
+        Command::Backfill(args) => backfill::backfill(&config, args).await,
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{:#}", e);
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
+}
+
+// Loading already validated the config, this prints what it resolved to
+fn check_config(config: &Config) {
+    match Config::file_path() {
+        Some(path) => println!("Config file: {}", path.display()),
+        None => println!("Config file: none, env variables only"),
+    }
+
+    for (name, exchange) in config.enabled_exchanges() {
+        let connections = exchange.markets.len().div_ceil(exchange.shard_size);
+        println!("Exchange {}: {} markets on {} connections", name, exchange.markets.len(), connections);
+    }
+    if config.enabled_exchanges().next().is_none() {
+        println!("No exchange enabled, only the api can run");
+    }
+
+    let sinks = config.sinks.enabled.iter().map(|sink| format!("{:?}", sink).to_lowercase()).collect::<Vec<_>>();
+    println!("Sinks: {}", sinks.join(", "));
+    println!("Database: {}", if config.database.url.is_some() { "configured" } else { "not configured" });
+    println!("Api: {}{}", config.api.bind, if config.api.tls.is_some() { " (https)" } else { "" });
+    println!("Capture: {}", config.capture.dir.as_deref().unwrap_or("off"));
+    println!("Logging: {} as {}", config.logging.filter, format!("{:?}", config.logging.format).to_lowercase());
+    println!("Configuration is valid");
 }
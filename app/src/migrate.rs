@@ -0,0 +1,64 @@
+use anyhow::{anyhow, bail, Context, Result};
+use diesel::PgConnection;
+use tracing::{info, warn};
+use db::db::{init_pool, DbPool};
+use db::migrations::{run_pending_migrations, schema_status};
+use scrapper_engine::config::Config;
+
+// Applies the pending migrations
+pub fn migrate(config: &Config) -> Result<()> {
+    let pool = init_pool(config.database.url()?, 1).context("Can't create connection pool")?;
+    let mut conn = pool.get()?;
+    check_known(&mut conn)?;
+    apply(&mut conn)?;
+    info!("Database schema is up to date");
+
+    Ok(())
+}
+
+// Checks the schema before starting. Pending migrations are applied with `auto_migrate`,
+// otherwise they and migrations this binary doesn't know stop the start
+pub fn check_schema(pool: &DbPool, auto_migrate: bool) -> Result<()> {
+    check_connection_schema(&mut *pool.get()?, auto_migrate)
+}
+
+// Same as check_schema, on a connection of its own
+pub fn check_connection_schema(conn: &mut PgConnection, auto_migrate: bool) -> Result<()> {
+    let pending = check_known(conn)?;
+    if pending.is_empty() {
+        info!("Database schema is up to date");
+        return Ok(());
+    }
+
+    if !auto_migrate {
+        bail!(
+            "The database has {} pending migrations ({}), run `app migrate` or set database.auto_migrate",
+            pending.len(), pending.join(", ")
+        );
+    }
+    warn!(pending = %pending.join(","), "Applying pending migrations");
+    apply(conn)
+}
+
+// Fails when the database has migrations this binary doesn't embed, returns the pending ones
+fn check_known(conn: &mut PgConnection) -> Result<Vec<String>> {
+    let status = schema_status(conn).map_err(|e| anyhow!("Error reading the schema version: {}", e))?;
+
+    if !status.unknown.is_empty() {
+        bail!(
+            "The database schema is newer than this binary, it doesn't know the migrations {}. Upgrade the binary",
+            status.unknown.join(", ")
+        );
+    }
+
+    Ok(status.pending)
+}
+
+fn apply(conn: &mut PgConnection) -> Result<()> {
+    let applied = run_pending_migrations(conn).map_err(|e| anyhow!("Error applying migrations: {}", e))?;
+    for version in applied {
+        info!(version = %version, "Applied migration");
+    }
+
+    Ok(())
+}
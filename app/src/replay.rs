@@ -0,0 +1,58 @@
+use crate::ReplayArgs;
+use crate::migrate::check_schema;
+
+use anyhow::{bail, Context, Result};
+use ::exchange::Exchange;
+use ::exchange::enums::AnyExchange;
+use ::exchange::exchanges::{Binance, ByBit, KuCoin};
+use db::db::init_pool;
+use scrapper_engine::aggregator::Aggregator;
+use scrapper_engine::capture::capture_files;
+use scrapper_engine::config::Config;
+use scrapper_engine::engine::Engine;
+use scrapper_engine::sinks::open_sinks;
+use scrapper_engine::queue::OverloadPolicy;
+
+// Rebuilds bars from captured frames and writes them to the configured sinks
+pub async fn replay(config: &Config, args: ReplayArgs) -> Result<()> {
+    let mut files = Vec::new();
+    for path in args.paths.iter() {
+        files.extend(capture_files(path)?);
+    }
+    if files.is_empty() {
+        bail!("No capture files found");
+    }
+
+    let pool = match config.uses_database() {
+        true => {
+            let (url, size) = config.database.scraper_pool()?;
+            Some(init_pool(url, size).context("Can't create connection pool")?)
+        },
+        false => None,
+    };
+    if let Some(pool) = pool.as_ref() {
+        check_schema(pool, config.database.auto_migrate)?;
+    }
+    let sinks = open_sinks(&config.sinks, pool.as_ref())?;
+
+    // Quotes are parsed at the tick sizes of the configured markets, like live
+    let mut exchanges: Vec<Box<dyn Exchange>> = Vec::new();
+    for (name, section) in config.enabled_exchanges() {
+        let rest_url = |default: &str| section.rest_url.clone().unwrap_or_else(|| default.to_string());
+        let mut exchange: Box<dyn Exchange> = match name {
+            "binance" => Box::new(Binance::new().with_rest_url(rest_url(Binance::REST_URL))),
+            "bybit" => Box::new(ByBit::new().with_rest_url(rest_url(ByBit::REST_URL))),
+            "kucoin" => Box::new(KuCoin::new().with_rest_url(rest_url(KuCoin::REST_URL))),
+            // The config is validated against the known exchanges
+            _ => unreachable!("Unknown exchange {}", name),
+        };
+        AnyExchange::intern(exchange.as_mut(), &section.markets)
+            .await
+            .with_context(|| format!("Error looking up the tick sizes of {}", exchange.name()))?;
+        exchanges.push(exchange);
+    }
+    // Replay never drops ticks, otherwise the bars would depend on timing
+    let aggregator = Aggregator::new(config.aggregator.shard_count()?, config.aggregator.capacity, OverloadPolicy::Block);
+
+    Engine::replay(files, exchanges, aggregator, sinks, config.gap_policies(), args.speed).await
+}
@@ -0,0 +1,304 @@
+use crate::RunArgs;
+use crate::migrate::check_connection_schema;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use anyhow::{bail, Context, Result};
+use axum::{serve, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
+use diesel::PgConnection;
+use tokio::net::TcpListener;
+use tokio::signal;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use ::exchange::exchanges::{Binance, ByBit, KuCoin};
+use api::{get_app, get_status_app, FeedHealth, FlushHealth, HealthChecks, MarketHealth, ScraperHealth, ScraperProbe};
+use db::db::{init_lazy_pool, DbPool};
+use db::repository::{BarRepository, PgBarRepository};
+use scrapper_engine::aggregator::Aggregator;
+use scrapper_engine::backfill::{backfill_outages, Backfill};
+use scrapper_engine::config::{ApiConfig, Config};
+use scrapper_engine::engine::Engine;
+use scrapper_engine::capture::Capture;
+use scrapper_engine::leader::{run_election, Leadership};
+use scrapper_engine::metrics;
+use scrapper_engine::reload;
+use scrapper_engine::sinks::open_sinks;
+use scrapper_engine::status::{self, ScraperGuard};
+
+// Time given to the scraper to close connections and flush bars after the api server stopped
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Runs the scraper, the api or both until Ctrl-C or SIGTERM. The scraper alone still serves
+// /health, /ready and /metrics
+pub async fn run(config: Config, args: RunArgs) -> Result<()> {
+    let (run_scraper, run_api) = args.components();
+    // The api serves the bars from the database, without one `run` only serves the status endpoints
+    let serve_bars = run_api && (args.api || config.database.url.is_some() || config.database.api_url.is_some());
+
+    let shutdown = CancellationToken::new();
+    tokio::spawn(wait_for_signal(shutdown.clone()));
+
+    // The scraper and the api get a pool each, so api queries never hold up writes.
+    // Migrations are only applied through the primary, a replica follows it
+    let scraper_pool = match run_scraper && config.uses_database() {
+        true => {
+            let (url, size) = config.database.scraper_pool()?;
+            Some(open_pool("scraper", url, size, config.database.auto_migrate)?)
+        },
+        false => None,
+    };
+    let api_pool = match serve_bars {
+        true => {
+            let (url, size) = config.database.api_pool()?;
+            Some(open_pool("api", url, size, config.database.auto_migrate && !config.database.api_uses_replica())?)
+        },
+        false => None,
+    };
+    let pools = [("scraper", scraper_pool.as_ref()), ("api", api_pool.as_ref())]
+        .into_iter()
+        .filter_map(|(name, pool)| Some((name, pool?)))
+        .collect::<Vec<_>>();
+    metrics::register_pools(&pools);
+    let scraper_repository = scraper_pool.clone().map(|pool| Arc::new(PgBarRepository::new(pool)) as Arc<dyn BarRepository>);
+
+    // SCRAPER ENGINE
+    let scraper = match run_scraper {
+        true => Some(spawn_scraper(config.clone(), scraper_pool, shutdown.clone()).await?),
+        false => None,
+    };
+
+    // REST API
+    let checks = HealthChecks {
+        scraper: scraper.is_some().then(|| Arc::new(EngineProbe) as Arc<dyn ScraperProbe>),
+        max_message_age: Duration::from_secs(config.health.max_message_age_secs),
+        max_flush_age: Duration::from_secs(config.health.max_flush_age_secs),
+    };
+    let app = match api_pool {
+        Some(pool) => get_app(Arc::new(PgBarRepository::new(pool)), checks),
+        _ => {
+            info!("No bars to serve, only serving /health, /ready and /metrics");
+            get_status_app(scraper_repository, checks)
+        }
+    };
+    let served = serve_api(app, &config.api, shutdown.clone()).await;
+    // The scraper stops with the api, also when the api couldn't start
+    shutdown.cancel();
+
+    let Some(scraper) = scraper else {
+        return served;
+    };
+    info!("Waiting for the scraper to finish");
+    let finished = tokio::time::timeout(SHUTDOWN_TIMEOUT, scraper).await;
+    served?;
+    match finished {
+        Ok(Ok(Ok(()))) => {
+            info!("Shutdown complete");
+            Ok(())
+        },
+        Ok(Ok(Err(e))) => bail!("Scraper finished with error: {:#}", e),
+        Ok(Err(e)) => bail!("Scraper task failed: {}", e),
+        Err(_) => bail!("Scraper didn't finish within {} seconds", SHUTDOWN_TIMEOUT.as_secs()),
+    }
+}
+
+// Starts while the database is down: the scraper spills its bars and /ready reports the database
+// until it's back. The schema is checked on the first connection, no bars are read or written
+// before it passed. A schema that fails the check at start stops the start
+fn open_pool(name: &'static str, url: &str, size: u32, auto_migrate: bool) -> Result<DbPool> {
+    // The pool logs every failed check, the last one is kept to tell a rejected schema from an outage
+    let rejected = Arc::new(Mutex::new(None::<String>));
+    let last_rejection = rejected.clone();
+    let check = move |conn: &mut PgConnection| {
+        let checked = check_connection_schema(conn, auto_migrate).map_err(|e| format!("{:#}", e));
+        *last_rejection.lock().expect("Schema check lock poisoned") = checked.clone().err();
+        checked.map_err(Into::into)
+    };
+    let pool = init_lazy_pool(url, size, Box::new(check));
+
+    if let Err(e) = pool.get() {
+        if let Some(problem) = rejected.lock().expect("Schema check lock poisoned").take() {
+            bail!(problem);
+        }
+        warn!(pool = name, error = %e, "Database unavailable, the schema is checked once it's back");
+    }
+
+    Ok(pool)
+}
+
+// Connects the exchanges and writes their bars every minute until shutdown.
+// Fails when an exchange can't be connected or the capture can't start
+async fn spawn_scraper(config: Config, pool: Option<DbPool>, shutdown: CancellationToken) -> Result<JoinHandle<Result<()>>> {
+    let sinks = open_sinks(&config.sinks, pool.as_ref())?;
+    let shards = config.aggregator.shard_count()?;
+    let database_url = config.database.url.clone();
+    let engine = connect_exchanges(&config).await?;
+    let (capture, capture_writer) = match config.capture.dir.clone() {
+        Some(dir) => {
+            let (capture, writer) = Capture::start(dir).context("Error starting frame capture")?;
+            (Some(capture), Some(writer))
+        },
+        None => (None, None),
+    };
+
+    Ok(tokio::spawn(async move {
+        let _running = ScraperGuard::start();
+
+        // With HA the lock is held until the last bars were written, not just until shutdown
+        let release = CancellationToken::new();
+        let (leadership, election) = match (config.ha.enabled, database_url) {
+            (true, Some(url)) => {
+                let leadership = Leadership::standby();
+                let election = tokio::spawn(run_election(url, config.ha.lock_key, leadership.clone(), release.clone()));
+                (leadership, Some(election))
+            },
+            _ => (Leadership::always(), None),
+        };
+
+        let aggregator = Aggregator::new(shards, config.aggregator.capacity, config.aggregator.overload_policy);
+        metrics::register_aggregator(&aggregator);
+        let feeds = Engine::publish_orderbooks(engine.feeds, &aggregator, shutdown.clone(), capture);
+        tokio::spawn(reload::watch_config(engine.control, aggregator.clone(), shutdown.clone()));
+        // Backfilled bars go straight to the database, without one outages stay gaps
+        if let (true, Some(pool)) = (config.backfill.auto, pool) {
+            let backfill = Backfill::new(&config);
+            tokio::spawn(backfill_outages(backfill, pool, leadership.clone(), engine.outages, shutdown.clone()));
+        }
+
+        let saved = Engine::save_bars_1min(aggregator, sinks, config.gap_policies(), leadership, shutdown, feeds).await;
+        release.cancel();
+        if let Some(election) = election
+            && election.await.is_err()
+        {
+            error!("Leader election task failed");
+        }
+        if let Some(capture_writer) = capture_writer {
+            match capture_writer.await {
+                Ok(Ok(())) => info!("Frame capture closed"),
+                Ok(Err(e)) => error!(error = format!("{:#}", e), "Error capturing frames"),
+                Err(e) => error!(error = %e, "Frame capture task failed"),
+            }
+        }
+        saved
+    }))
+}
+
+// Connects every enabled exchange section of the config
+async fn connect_exchanges(config: &Config) -> Result<Engine> {
+    let mut engine = Engine::new();
+    for (name, exchange) in config.enabled_exchanges() {
+        let url = |default: &str| exchange.url.clone().unwrap_or_else(|| default.to_string());
+        let rest_url = |default: &str| exchange.rest_url.clone().unwrap_or_else(|| default.to_string());
+        engine = match name {
+            "binance" => engine.add(exchange, || Binance::with_url(url(Binance::URL)).with_rest_url(rest_url(Binance::REST_URL))).await?,
+            "bybit" => engine.add(exchange, || ByBit::with_url(url(ByBit::URL)).with_rest_url(rest_url(ByBit::REST_URL))).await?,
+            "kucoin" => {
+                let token_url = exchange.token_url.clone().unwrap_or_else(|| KuCoin::TOKEN_URL.to_string());
+                engine.add(exchange, || KuCoin::with_urls(url(KuCoin::URL), token_url.clone()).with_rest_url(rest_url(KuCoin::REST_URL))).await?
+            },
+            // The config is validated against the known exchanges
+            _ => unreachable!("Unknown exchange {}", name),
+        };
+    }
+
+    Ok(engine)
+}
+
+// Reports the status of the scraper running in this process to the health endpoints
+struct EngineProbe;
+
+impl ScraperProbe for EngineProbe {
+    fn health(&self) -> ScraperHealth {
+        let status = status::snapshot();
+        ScraperHealth {
+            running: status.running,
+            started_at: status.started_at,
+            role: status.role.as_str().to_string(),
+            feeds: status.feeds.into_iter().map(|feed| FeedHealth {
+                exchange: feed.exchange.to_string(),
+                shard: feed.shard,
+                state: feed.state.as_str().to_string(),
+                connection: feed.connection,
+                since: feed.since,
+            }).collect(),
+            markets: status.markets.into_iter().map(|market| MarketHealth {
+                exchange: market.exchange.to_string(),
+                market: market.market,
+                last_message: market.last_message,
+            }).collect(),
+            flushes: status.flushes.into_iter().map(|(sink, last_flush)| FlushHealth {
+                sink: sink.to_string(),
+                last_flush,
+            }).collect(),
+        }
+    }
+}
+
+// Serves the api until shutdown, over https when TLS is configured
+async fn serve_api(app: Router, config: &ApiConfig, shutdown: CancellationToken) -> Result<()> {
+    let address = config.address().context("Error loading api address")?;
+
+    match &config.tls {
+        None => {
+            let listener = TcpListener::bind(address)
+                .await
+                .with_context(|| format!("Error listening on {}", address))?;
+
+            info!(address = %format!("http://{}", address), "Starting api server");
+            serve(listener, app)
+                .with_graceful_shutdown(shutdown.cancelled_owned())
+                .await
+                .context("Error running api server")?;
+        },
+        Some(tls) => {
+            let tls_config = RustlsConfig::from_pem_file(&tls.cert, &tls.key)
+                .await
+                .context("Error loading TLS certificate")?;
+
+            let handle = Handle::new();
+            let stop = handle.clone();
+            tokio::spawn(async move {
+                shutdown.cancelled().await;
+                stop.graceful_shutdown(None);
+            });
+
+            info!(address = %format!("https://{}", address), "Starting api server");
+            axum_server::bind_rustls(address, tls_config)
+                .handle(handle)
+                .serve(app.into_make_service())
+                .await
+                .with_context(|| format!("Error running api server on {}", address))?;
+        },
+    }
+
+    info!("Api server stopped");
+    Ok(())
+}
+
+// Cancels the token on Ctrl-C or SIGTERM
+async fn wait_for_signal(shutdown: CancellationToken) {
+    let ctrl_c = async {
+        signal::ctrl_c().await.expect("Error listening for Ctrl-C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Error listening for SIGTERM")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    info!("Shutdown signal received");
+    shutdown.cancel();
+}